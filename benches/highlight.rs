@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ropey::Rope;
+use syntect::{easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet};
+use yaz::{editor::DEFAULT_THEME, highlight_server::highlight_rope_line};
+
+/// Returns the contents of a Rust file with the given number of lines.
+fn rust_source(num_lines: usize) -> String {
+    let snippet = [
+        "/// Returns the sum of the given numbers.",
+        "pub fn sum(numbers: &[u64]) -> u64 {",
+        "    let mut total = 0;",
+        "    for n in numbers.iter().filter(|n| **n % 2 == 0) {",
+        "        total += n; // Only the even ones.",
+        "    }",
+        "    println!(\"{}\", total);",
+        "    total",
+        "}",
+        "",
+    ];
+    snippet
+        .iter()
+        .cycle()
+        .take(num_lines)
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn highlight_lines(c: &mut Criterion) {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let theme_set = ThemeSet::load_defaults();
+    let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+    let theme = &theme_set.themes[DEFAULT_THEME];
+    let rope = Rope::from_str(&rust_source(10_000));
+    let mut group = c.benchmark_group("highlight 10000 lines");
+    group.sample_size(10);
+    group.bench_function("highlight_rope_line", |b| {
+        b.iter(|| {
+            let mut highlighter = HighlightLines::new(syntax, theme);
+            for line in rope.lines() {
+                highlight_rope_line(line, &mut highlighter, &syntax_set).unwrap();
+            }
+        })
+    });
+    // The approach before `highlight_rope_line`, which copies every line to a string.
+    group.bench_function("to_string", |b| {
+        b.iter(|| {
+            let mut highlighter = HighlightLines::new(syntax, theme);
+            for line in rope.lines() {
+                let line = line.to_string();
+                highlighter
+                    .highlight_line(&line, &syntax_set)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(style, s)| (style, s.chars().count()))
+                    .for_each(drop);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, highlight_lines);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use yaz::{
+    editor::{editor_mode::TriggerHandler, EditorCmd},
+    events::{KeyCombo, KeyEvt, KeyMatcher, KeyMods},
+};
+
+fn exact(c: char) -> [KeyMatcher; 1] {
+    [KeyMatcher::Exact(KeyEvt::Char(c, KeyMods::NONE))]
+}
+
+/// Returns a handler with 100 two-key bindings, from `a0` to `j9`.
+fn handler_with_100_bindings() -> TriggerHandler {
+    ('a'..='j')
+        .flat_map(|first| ('0'..='9').map(move |second| (first, second)))
+        .fold(TriggerHandler::default(), |handler, (first, second)| {
+            handler.with([exact(first), exact(second)], [EditorCmd::ResetCombo])
+        })
+}
+
+fn handle_combos(c: &mut Criterion) {
+    let handler = handler_with_100_bindings();
+    let combos: Vec<KeyCombo> = ["a", "e5", "j9", "k"]
+        .iter()
+        .map(|combo| combo.parse().unwrap())
+        .collect();
+    c.bench_function("handle with 100 bindings", |b| {
+        b.iter(|| {
+            for combo in &combos {
+                black_box(handler.handle(black_box(combo)));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, handle_combos);
+criterion_main!(benches);
@@ -17,30 +17,94 @@ pub fn tx_generator(_args: TokenStream, tagged_fn: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+/// Generates an action generator named after the tagged function. A different command name can be
+/// given as an argument, e.g., `#[action_generator(map)]`.
 #[proc_macro_attribute]
-pub fn action_generator(_args: TokenStream, tagged_fn: TokenStream) -> TokenStream {
+pub fn action_generator(args: TokenStream, tagged_fn: TokenStream) -> TokenStream {
     let input = parse_macro_input!(tagged_fn as ItemFn);
     let fn_name = input.sig.ident.clone();
+    let cmd_name = if args.is_empty() {
+        fn_name.clone()
+    } else {
+        parse_macro_input!(args as Ident)
+    };
     let const_name = Ident::new(&fn_name.to_string().to_uppercase(), Span::call_site());
     let expanded = quote! {
         pub const #const_name: crate::editor::ActionGenerator
-            = crate::editor::ActionGenerator(std::stringify!(#fn_name), #fn_name);
+            = crate::editor::ActionGenerator(std::stringify!(#cmd_name), #fn_name);
         #input
     };
     TokenStream::from(expanded)
 }
 
-#[proc_macro_derive(BasicEditorMode, attributes(handler))]
+/// Implements `EditorMode` for a struct with a `trigger_handler`. The display is the default one
+/// unless a function that returns it is given with `#[display(fn_name)]`. A function given with
+/// `#[handler(fn_name)]` handles the combos before the trigger handler, which handles the combos
+/// that the function returns none for. The mode falls through iff its trigger handler does.
+///
+/// The fields of type `TransactionGenerator` or `ActionGenerator` can be bound to key patterns
+/// with one or more `#[handler(key = "...")]` attributes, in which case `new()` is generated as
+/// well. Each field is initialized with the generator named after it, e.g., `move_head_left`
+/// with `MOVE_HEAD_LEFT`, and the rest of the fields with their defaults. The action generators
+/// are called without arguments once their pattern matches.
+#[proc_macro_derive(BasicEditorMode, attributes(handler, display))]
 pub fn create_basic_editor_mode(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let struct_name = input.ident.clone();
     let mode_id = struct_name.to_string().to_lowercase().replace("mode", "");
     let mode_id = Ident::new(&mode_id, Span::call_site());
+    let display_fn = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("display"))
+        .map(|attr| attr.parse_args::<syn::Path>())
+        .transpose();
+    let handler_fn = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("handler"))
+        .map(|attr| attr.parse_args::<syn::Path>())
+        .transpose();
+    let handle_combo = match handler_fn {
+        Ok(Some(handler_fn)) => quote! {
+            #handler_fn(self, kc, state)
+                .or_else(|| self.trigger_handler.handle(kc))
+                .unwrap_or_default()
+        },
+        Ok(None) => quote! { self.trigger_handler.handle(kc).unwrap_or_default() },
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    let key_handlers = match key_handlers(&input) {
+        Ok(key_handlers) => key_handlers,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+    let action_gen_dispatch = key_handlers
+        .iter()
+        .filter(|handler| !handler.is_tx_gen)
+        .map(|handler| {
+            let (field, key) = (&handler.field, &handler.key);
+            quote! {
+                if #key
+                    .parse::<crate::events::KeyPattern>()
+                    .map_or(false, |pattern| pattern.matches_ref(kc))
+                {
+                    return (self.#field.1)(&[], state).unwrap_or_default();
+                }
+            }
+        });
+    let constructor = generate_constructor(&input, &key_handlers);
+    let get_display = match display_fn {
+        Ok(Some(display_fn)) => quote! { #display_fn(self, state) },
+        Ok(None) => quote! { Default::default() },
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
     let expanded = quote! {
         impl #struct_name {
             pub fn id() -> &'static str {
                 std::stringify!(#mode_id)
             }
+
+            #constructor
         }
 
         impl crate::editor::editor_mode::EditorMode for #struct_name {
@@ -48,15 +112,119 @@ pub fn create_basic_editor_mode(input: TokenStream) -> TokenStream {
                 Self::id()
             }
 
-            fn handle_combo(&mut self, kc: &crate::events::KeyCombo, _state: &crate::editor::EditorStateSummary)
+            fn handle_combo(&mut self, kc: &crate::events::KeyCombo, state: &crate::editor::EditorStateSummary)
                 -> crate::editor::EditorAction {
-                self.trigger_handler.handle(kc).unwrap_or_default()
+                let _ = state;
+                #(#action_gen_dispatch)*
+                #handle_combo
+            }
+
+            fn get_display(&self, state: &crate::editor::EditorStateSummary) -> crate::editor::EditorDisplay {
+                let _ = state;
+                #get_display
             }
 
-            fn get_display(&self, _: &crate::editor::EditorStateSummary) -> crate::editor::EditorDisplay {
-                Default::default()
+            fn fallthrough(&self) -> bool {
+                self.trigger_handler.falls_through()
             }
         }
     };
     TokenStream::from(expanded)
 }
+
+/// A key pattern that a generator field is bound to with `#[handler(key = "...")]`.
+struct KeyHandler {
+    field: Ident,
+    key: syn::LitStr,
+    is_tx_gen: bool,
+}
+
+/// Collects the key patterns that the generator fields of the struct are bound to.
+fn key_handlers(input: &DeriveInput) -> syn::Result<Vec<KeyHandler>> {
+    let syn::Data::Struct(data) = &input.data else {
+        return Ok(vec![]);
+    };
+    let mut key_handlers = vec![];
+    for field in &data.fields {
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("handler"))
+        {
+            let is_tx_gen = match &field.ty {
+                syn::Type::Path(ty) if ty.path.is_ident("TransactionGenerator") => true,
+                syn::Type::Path(ty) if ty.path.is_ident("ActionGenerator") => false,
+                ty => {
+                    return Err(syn::Error::new_spanned(
+                        ty,
+                        "only the generator fields can be bound to keys",
+                    ))
+                }
+            };
+            let mut key = None;
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("key") {
+                    key = Some(meta.value()?.parse::<syn::LitStr>()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `key = \"...\"`"))
+                }
+            })?;
+            key_handlers.push(KeyHandler {
+                field: field.ident.clone().unwrap(),
+                key: key.ok_or_else(|| syn::Error::new_spanned(attr, "missing the key"))?,
+                is_tx_gen,
+            });
+        }
+    }
+    Ok(key_handlers)
+}
+
+/// Generates `new()` for a struct with generator fields that are bound to keys. The action
+/// generators are dispatched in `handle_combo`, so their patterns are added to the trigger handler
+/// without commands only to keep the combos that may still match them.
+fn generate_constructor(
+    input: &DeriveInput,
+    key_handlers: &[KeyHandler],
+) -> proc_macro2::TokenStream {
+    let syn::Data::Struct(data) = &input.data else {
+        return quote! {};
+    };
+    if key_handlers.is_empty() {
+        return quote! {};
+    }
+    let triggers = key_handlers.iter().map(|handler| {
+        let (field, key) = (&handler.field, &handler.key);
+        let cmds = if handler.is_tx_gen {
+            quote! { [crate::editor::EditorCmd::Transaction(mode.#field)] }
+        } else {
+            quote! { [] }
+        };
+        quote! {
+            .with(
+                #key.parse::<crate::events::KeyPattern>().expect("invalid key pattern"),
+                #cmds,
+            )
+        }
+    });
+    let fields = data
+        .fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .map(|field| {
+            if key_handlers.iter().any(|handler| handler.field == *field) {
+                let const_name = Ident::new(&field.to_string().to_uppercase(), field.span());
+                quote! { #field: #const_name }
+            } else {
+                quote! { #field: Default::default() }
+            }
+        });
+    quote! {
+        pub fn new() -> Self {
+            let mut mode = Self { #(#fields),* };
+            mode.trigger_handler = std::mem::take(&mut mode.trigger_handler)
+                #(#triggers)*;
+            mode
+        }
+    }
+}
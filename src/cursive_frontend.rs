@@ -1,7 +1,13 @@
-use std::sync::mpsc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
 
 use cursive::{
-    theme::{BorderStyle, ColorStyle, ColorType, Palette, Style},
+    theme::{BorderStyle, Color, ColorStyle, ColorType, Palette, Style},
     utils::markup::StyledString,
     CbSink, CursiveRunnable, With,
 };
@@ -62,7 +68,10 @@ impl From<crate::render_server::RGBAColor> for cursive::theme::Color {
 impl From<crate::render_server::ConcreteStyle> for cursive::theme::Style {
     fn from(value: crate::render_server::ConcreteStyle) -> Self {
         if value.highlight {
-            return Style::highlight();
+            return match value.highlight_color {
+                Some(color) => ColorStyle::new(Color::Rgb(0, 0, 0), Color::from(color)).into(),
+                None => Style::highlight(),
+            };
         }
         let mut style = Style::terminal_default();
         if let Some(color) = value.fg {
@@ -102,6 +111,11 @@ impl KeyEvt {
 pub struct CursiveFrontend {
     cb_sink: Option<CbSink>,
     evt_chan: mpsc::Sender<RendererEvent>,
+    /// Duration after which an error dialog is dismissed automatically.
+    pub error_dismiss_timeout: Option<Duration>,
+    num_errors: usize,
+    /// Set while the loading indicator is displayed, which keeps it spinning.
+    loading: Option<Arc<AtomicBool>>,
 }
 
 impl CursiveFrontend {
@@ -156,6 +170,9 @@ impl RendererFrontend for CursiveFrontend {
         CursiveFrontend {
             cb_sink: Option::None,
             evt_chan,
+            error_dismiss_timeout: Some(Duration::from_secs(3)),
+            num_errors: 0,
+            loading: None,
         }
     }
 
@@ -164,6 +181,14 @@ impl RendererFrontend for CursiveFrontend {
         new_state: &EditorStateSummary,
         styles: Vec<(usize, usize, crate::render_server::ConcreteStyle)>,
     ) {
+        let buf = new_state.curr_doc.get_buf();
+        let visible_lines = new_state.view.visible_line_range(buf);
+        let cursor_line = new_state
+            .curr_doc
+            .selections
+            .get(&0)
+            .map(|sel| DocumentView::y_offset(sel.0, buf))
+            .unwrap_or(0);
         let new_state = new_state.clone();
         self.send_cursive_callback(move |ctx| {
             // Stylize the current text.
@@ -171,24 +196,135 @@ impl RendererFrontend for CursiveFrontend {
             views::EditorTextView::get(ctx)
                 .get_inner_mut()
                 .set_content(stylized_str);
+            views::StatusBarView::get(ctx).set_segments(
+                new_state.display.status_left.clone().unwrap_or_default(),
+                new_state.display.status_right.clone().unwrap_or_default(),
+            );
             views::CmdBarView::get(ctx)
                 .set_content(new_state.display.btm_bar_text.clone().unwrap_or_default());
-            // views::LogView::get(ctx).set_content(format!("{}", new_state.curr_mode));
-            // new_state
-            //     .display
-            //     .mid_box_text
-            //     .map(|txt| views::LogView::get(ctx).set_content(txt));
+            if let Some(overlay_text) = new_state.display.overlay_text.clone() {
+                views::OverlayView::show(ctx, overlay_text);
+            } else {
+                views::OverlayView::hide(ctx);
+            }
+            let mut line_number_view = views::LineNumberView::get(ctx);
+            line_number_view.set_visible(new_state.settings.line_numbers);
+            // Leave out the space after the numbers.
+            line_number_view.get_inner_mut().min_width = new_state
+                .settings
+                .gutter_width(new_state.curr_doc.get_buf())
+                .saturating_sub(1);
+            line_number_view.get_inner_mut().mode = if new_state.settings.relative_line_numbers {
+                views::LineNumberMode::Relative
+            } else {
+                views::LineNumberMode::Absolute
+            };
+            let mut mid_box_view = views::MidBoxView::get(ctx);
+            mid_box_view.set_visible(new_state.display.mid_box_text.is_some());
+            mid_box_view
+                .get_inner_mut()
+                .get_inner_mut()
+                .set_content(new_state.display.mid_box_text.clone().unwrap_or_default());
+        });
+        self.update_line_numbers(*visible_lines.start(), visible_lines.end() + 1, cursor_line);
+    }
+
+    fn update_line_numbers(&mut self, first_line: usize, last_line: usize, cursor_line: usize) {
+        self.send_cursive_callback(move |ctx| {
+            views::LineNumberView::get(ctx).get_inner_mut().set_lines(
+                first_line,
+                last_line,
+                cursor_line,
+            );
+        });
+    }
+
+    fn notify(&mut self, msg: &str, duration: Duration) {
+        let msg = msg.to_string();
+        let displayed_msg = msg.clone();
+        self.send_cursive_callback(move |ctx| {
+            views::LogView::get(ctx).set_content(displayed_msg);
+        });
+        let cb_sink = self.cb_sink.clone().unwrap();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            cb_sink
+                .send(Box::new(move |ctx| {
+                    // Do not clear the notifications that were displayed later on.
+                    let mut log_view = views::LogView::get(ctx);
+                    if log_view.get_content().source() == msg {
+                        log_view.set_content("");
+                    }
+                }))
+                .ok();
+        });
+    }
+
+    fn loading(&mut self) {
+        if self.loading.is_some() {
+            return;
+        }
+        let is_loading = Arc::new(AtomicBool::new(true));
+        self.loading = Some(is_loading.clone());
+        self.send_cursive_callback(views::LoadingView::show);
+        let cb_sink = self.cb_sink.clone().unwrap();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(100));
+            if !is_loading.load(Ordering::Relaxed) {
+                break;
+            }
+            cb_sink
+                .send(Box::new(|ctx| {
+                    if let Some(mut loading_view) =
+                        ctx.find_name::<views::LoadingView>(views::LoadingView::view_name())
+                    {
+                        loading_view.advance();
+                    }
+                }))
+                .ok();
         });
     }
 
+    fn loading_finished(&mut self) {
+        if let Some(is_loading) = self.loading.take() {
+            is_loading.store(false, Ordering::Relaxed);
+            self.send_cursive_callback(views::LoadingView::hide);
+        }
+    }
+
+    fn popup(&mut self, text: &str) {
+        let text = text.to_string();
+        self.send_cursive_callback(move |ctx| views::PopupView::show(ctx, text));
+    }
+
     fn quit(&mut self) {
         self.send_cursive_callback(|ctx| ctx.quit());
     }
 
     fn error(&mut self, error: ModalEditorError) {
+        self.num_errors += 1;
+        let error_id = self.num_errors;
+        let evt_chan = self.evt_chan.clone();
         self.send_cursive_callback(move |ctx| {
-            views::LogView::get(ctx).set_content(format!("error: {}", error.to_string()));
+            // Replace the currently displayed error, if any.
+            views::ErrorDialogView::dismiss(ctx, None);
+            let mut dialog = views::ErrorDialogView::new(evt_chan);
+            dialog
+                .get_mut()
+                .set_error(error_id, format!("error: {}", error.to_string()));
+            views::RootStackView::get(ctx).add_layer(dialog);
         });
+        if let Some(timeout) = self.error_dismiss_timeout {
+            let cb_sink = self.cb_sink.clone().unwrap();
+            std::thread::spawn(move || {
+                std::thread::sleep(timeout);
+                cb_sink
+                    .send(Box::new(move |ctx| {
+                        views::ErrorDialogView::dismiss(ctx, Some(error_id))
+                    }))
+                    .ok();
+            });
+        }
     }
 }
 
@@ -3,11 +3,14 @@ use std::sync::mpsc;
 use cursive::{
     direction::Orientation,
     event,
-    theme::Style,
+    theme::{Effect, Style},
+    utils::markup::StyledString,
     view::{self, Nameable, Resizable},
     views, View,
 };
 
+use itertools::Itertools;
+
 use crate::{events::KeyEvt, render_server::RendererEvent};
 
 pub trait ViewBuilder {
@@ -66,7 +69,14 @@ impl ViewBuilder for EditorView {
 
     fn build(evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
         let inner_view = views::LinearLayout::new(Orientation::Vertical)
-            .child(EditorTextView::new(evt_chan.clone()).full_screen())
+            .child(
+                views::LinearLayout::new(Orientation::Horizontal)
+                    .child(LineNumberView::new(evt_chan.clone()))
+                    .child(EditorTextView::new(evt_chan.clone()).full_screen())
+                    .full_screen(),
+            )
+            .child(MidBoxView::new(evt_chan.clone()))
+            .child(StatusBarView::new(evt_chan.clone()))
             .child(CmdBarView::new(evt_chan.clone()))
             .child(LogView::new(evt_chan.clone()));
         EditorView {
@@ -81,7 +91,8 @@ impl view::ViewWrapper for EditorView {
 
     fn wrap_on_event(&mut self, evt: event::Event) -> event::EventResult {
         KeyEvt::try_from_cursive_evt(evt).map(|evt| {
-            self.evt_chan.send(RendererEvent::KeyEvent(evt)).unwrap();
+            // The renderer may have already stopped while the frontend is quitting.
+            self.evt_chan.send(RendererEvent::KeyEvent(evt)).ok();
         });
         event::EventResult::Consumed(None)
     }
@@ -89,7 +100,7 @@ impl view::ViewWrapper for EditorView {
     fn wrap_layout(&mut self, size: cursive::Vec2) {
         self.evt_chan
             .send(RendererEvent::Resized(size.x, size.y))
-            .unwrap();
+            .ok();
         self.inner_view.layout(size);
     }
 }
@@ -113,6 +124,337 @@ impl ViewBuilder for EditorTextView {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineNumberMode {
+    Absolute,
+    Relative,
+}
+
+pub struct LineNumberView {
+    inner_view: views::TextView,
+    pub mode: LineNumberMode,
+    /// The minimum width of the numbers, so that the gutter does not change its width while
+    /// scrolling.
+    pub min_width: usize,
+}
+
+impl LineNumberView {
+    /// Displays the numbers of the lines in the given range (end-exclusive) with the cursor line
+    /// emphasized. In relative mode, every line other than the cursor line displays its distance
+    /// to the cursor line.
+    pub fn set_lines(&mut self, first_line: usize, last_line: usize, cursor_line: usize) {
+        let line_nums = (first_line..last_line)
+            .map(|line_idx| match self.mode {
+                LineNumberMode::Relative if line_idx != cursor_line => {
+                    line_idx.abs_diff(cursor_line)
+                }
+                _ => line_idx + 1,
+            })
+            .collect_vec();
+        let width = line_nums
+            .iter()
+            .map(|line_num| line_num.to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max(self.min_width);
+        let mut content = StyledString::new();
+        for (line_idx, line_num) in (first_line..last_line).zip(line_nums) {
+            let line_num = format!("{:>width$} \n", line_num, width = width);
+            if line_idx == cursor_line {
+                content.append_styled(line_num, Effect::Bold);
+            } else {
+                content.append_styled(line_num, Effect::Dim);
+            }
+        }
+        self.inner_view.set_content(content);
+    }
+}
+
+impl ViewBuilder for LineNumberView {
+    type ViewType = views::HideableView<Self>;
+
+    fn view_name() -> &'static str {
+        "line_numbers"
+    }
+
+    fn build(_evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        views::HideableView::new(LineNumberView {
+            inner_view: views::TextView::new(""),
+            mode: LineNumberMode::Absolute,
+            min_width: 0,
+        })
+    }
+}
+
+impl view::ViewWrapper for LineNumberView {
+    cursive::wrap_impl!(self.inner_view: views::TextView);
+
+    fn wrap_required_size(&mut self, req: cursive::Vec2) -> cursive::Vec2 {
+        // Do not claim more rows than the text view, which can be scrolled.
+        let size = self.inner_view.required_size(req);
+        cursive::Vec2::new(size.x, std::cmp::min(size.y, req.y))
+    }
+}
+
+pub struct ErrorDialogView {
+    inner_view: views::Dialog,
+    evt_chan: mpsc::Sender<RendererEvent>,
+    id: usize,
+}
+
+impl ErrorDialogView {
+    /// Displays the given error message. The id is used to identify the dialog on dismissal.
+    pub fn set_error(&mut self, id: usize, msg: String) {
+        self.id = id;
+        self.inner_view.set_content(views::TextView::new(msg));
+    }
+
+    /// Removes the error dialog from the screen. If an id is given, the dialog is removed only if
+    /// it displays the error with the given id.
+    pub fn dismiss(ctx: &mut cursive::Cursive, id: Option<usize>) {
+        let should_dismiss = ctx
+            .find_name::<Self>(Self::view_name())
+            .map(|dialog| match id {
+                Some(id) => dialog.id == id,
+                None => true,
+            })
+            .unwrap_or(false);
+        if !should_dismiss {
+            return;
+        }
+        let mut root_stack = RootStackView::get(ctx);
+        if let Some(pos) = root_stack.find_layer_from_name(Self::view_name()) {
+            root_stack.remove_layer(pos);
+        }
+    }
+}
+
+impl ViewBuilder for ErrorDialogView {
+    type ViewType = Self;
+
+    fn view_name() -> &'static str {
+        "error_dialog"
+    }
+
+    fn build(evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        ErrorDialogView {
+            inner_view: views::Dialog::around(views::TextView::new("")).title("error"),
+            evt_chan,
+            id: 0,
+        }
+    }
+}
+
+impl view::ViewWrapper for ErrorDialogView {
+    cursive::wrap_impl!(self.inner_view: views::Dialog);
+
+    fn wrap_on_event(&mut self, evt: event::Event) -> event::EventResult {
+        if let Some(evt) = KeyEvt::try_from_cursive_evt(evt) {
+            // Dismiss the dialog and let the editor handle the key.
+            self.evt_chan.send(RendererEvent::KeyEvent(evt)).ok();
+            let id = self.id;
+            event::EventResult::with_cb(move |ctx| Self::dismiss(ctx, Some(id)))
+        } else {
+            event::EventResult::Ignored
+        }
+    }
+}
+
+pub struct OverlayView {
+    inner_view: views::Panel<views::TextView>,
+    evt_chan: mpsc::Sender<RendererEvent>,
+}
+
+impl OverlayView {
+    /// Displays the given text in the overlay, adding the overlay to the screen if necessary.
+    pub fn show(ctx: &mut cursive::Cursive, content: String) {
+        if let Some(mut overlay) = ctx.find_name::<Self>(Self::view_name()) {
+            overlay.inner_view.get_inner_mut().set_content(content);
+            return;
+        }
+        let evt_chan = EditorView::get(ctx).evt_chan.clone();
+        let mut overlay = Self::new(evt_chan);
+        overlay
+            .get_mut()
+            .inner_view
+            .get_inner_mut()
+            .set_content(content);
+        RootStackView::get(ctx).add_fullscreen_layer(overlay);
+    }
+
+    /// Removes the overlay from the screen, if it is displayed.
+    pub fn hide(ctx: &mut cursive::Cursive) {
+        let mut root_stack = RootStackView::get(ctx);
+        if let Some(pos) = root_stack.find_layer_from_name(Self::view_name()) {
+            root_stack.remove_layer(pos);
+        }
+    }
+}
+
+impl ViewBuilder for OverlayView {
+    type ViewType = Self;
+
+    fn view_name() -> &'static str {
+        "overlay"
+    }
+
+    fn build(evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        OverlayView {
+            inner_view: views::Panel::new(views::TextView::new("")),
+            evt_chan,
+        }
+    }
+}
+
+impl view::ViewWrapper for OverlayView {
+    cursive::wrap_impl!(self.inner_view: views::Panel<views::TextView>);
+
+    fn wrap_on_event(&mut self, evt: event::Event) -> event::EventResult {
+        // The overlay only displays the state of the editor, which handles the keys.
+        if let Some(evt) = KeyEvt::try_from_cursive_evt(evt) {
+            self.evt_chan.send(RendererEvent::KeyEvent(evt)).ok();
+        }
+        event::EventResult::Consumed(None)
+    }
+}
+
+/// Displays a text until a key is pressed, e.g., a drawing of the undo tree.
+pub struct PopupView {
+    inner_view: views::Dialog,
+}
+
+impl PopupView {
+    /// Displays the given text in the popup, replacing the displayed one, if any.
+    pub fn show(ctx: &mut cursive::Cursive, text: String) {
+        Self::hide(ctx);
+        let evt_chan = EditorView::get(ctx).evt_chan.clone();
+        let mut popup = Self::new(evt_chan);
+        popup
+            .get_mut()
+            .inner_view
+            .set_content(views::TextView::new(text));
+        RootStackView::get(ctx).add_layer(popup);
+    }
+
+    /// Removes the popup from the screen, if it is displayed.
+    pub fn hide(ctx: &mut cursive::Cursive) {
+        let mut root_stack = RootStackView::get(ctx);
+        if let Some(pos) = root_stack.find_layer_from_name(Self::view_name()) {
+            root_stack.remove_layer(pos);
+        }
+    }
+}
+
+impl ViewBuilder for PopupView {
+    type ViewType = Self;
+
+    fn view_name() -> &'static str {
+        "popup"
+    }
+
+    fn build(_evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        PopupView {
+            inner_view: views::Dialog::around(views::TextView::new("")),
+        }
+    }
+}
+
+impl view::ViewWrapper for PopupView {
+    cursive::wrap_impl!(self.inner_view: views::Dialog);
+
+    fn wrap_on_event(&mut self, evt: event::Event) -> event::EventResult {
+        // Any key dismisses the popup without reaching the editor.
+        if KeyEvt::try_from_cursive_evt(evt).is_some() {
+            event::EventResult::with_cb(Self::hide)
+        } else {
+            event::EventResult::Ignored
+        }
+    }
+}
+
+/// The frames of the loading indicator, which are cycled through while loading.
+const LOADING_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+pub struct LoadingView {
+    inner_view: views::Dialog,
+    evt_chan: mpsc::Sender<RendererEvent>,
+    frame_idx: usize,
+}
+
+impl LoadingView {
+    /// Displays the next frame of the indicator.
+    pub fn advance(&mut self) {
+        self.frame_idx = (self.frame_idx + 1) % LOADING_FRAMES.len();
+        self.inner_view.set_content(views::TextView::new(format!(
+            "{} loading",
+            LOADING_FRAMES[self.frame_idx]
+        )));
+    }
+
+    /// Displays the loading indicator unless it is already displayed.
+    pub fn show(ctx: &mut cursive::Cursive) {
+        if ctx.find_name::<Self>(Self::view_name()).is_some() {
+            return;
+        }
+        let evt_chan = EditorView::get(ctx).evt_chan.clone();
+        RootStackView::get(ctx).add_layer(Self::new(evt_chan));
+    }
+
+    /// Removes the loading indicator from the screen, if it is displayed.
+    pub fn hide(ctx: &mut cursive::Cursive) {
+        let mut root_stack = RootStackView::get(ctx);
+        if let Some(pos) = root_stack.find_layer_from_name(Self::view_name()) {
+            root_stack.remove_layer(pos);
+        }
+    }
+}
+
+impl ViewBuilder for LoadingView {
+    type ViewType = Self;
+
+    fn view_name() -> &'static str {
+        "loading"
+    }
+
+    fn build(evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        LoadingView {
+            inner_view: views::Dialog::around(views::TextView::new(format!(
+                "{} loading",
+                LOADING_FRAMES[0]
+            ))),
+            evt_chan,
+            frame_idx: 0,
+        }
+    }
+}
+
+impl view::ViewWrapper for LoadingView {
+    cursive::wrap_impl!(self.inner_view: views::Dialog);
+
+    fn wrap_on_event(&mut self, evt: event::Event) -> event::EventResult {
+        // The keys are queued by the editor until it is done loading.
+        if let Some(evt) = KeyEvt::try_from_cursive_evt(evt) {
+            self.evt_chan.send(RendererEvent::KeyEvent(evt)).ok();
+        }
+        event::EventResult::Consumed(None)
+    }
+}
+
+/// Displays a box above the status bar while the mode has a text for it, e.g., the completions.
+pub struct MidBoxView;
+
+impl ViewBuilder for MidBoxView {
+    type ViewType = views::HideableView<views::Panel<views::TextView>>;
+
+    fn view_name() -> &'static str {
+        "mid_box"
+    }
+
+    fn build(_evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        views::HideableView::new(views::Panel::new(views::TextView::new(""))).hidden()
+    }
+}
+
 pub struct LogView;
 
 impl ViewBuilder for LogView {
@@ -127,6 +469,48 @@ impl ViewBuilder for LogView {
     }
 }
 
+pub struct StatusBarView {
+    inner_view: views::LinearLayout,
+}
+
+impl StatusBarView {
+    /// Sets the contents of the left and right segments of the status bar.
+    pub fn set_segments(&mut self, left: String, right: String) {
+        self.set_segment(0, left);
+        self.set_segment(2, right);
+    }
+
+    fn set_segment(&mut self, child_idx: usize, content: String) {
+        if let Some(segment) = self
+            .inner_view
+            .get_child_mut(child_idx)
+            .and_then(|v| v.downcast_mut::<views::TextView>())
+        {
+            segment.set_content(content);
+        }
+    }
+}
+
+impl ViewBuilder for StatusBarView {
+    type ViewType = Self;
+
+    fn view_name() -> &'static str {
+        "status_bar"
+    }
+
+    fn build(_evt_chan: mpsc::Sender<RendererEvent>) -> Self::ViewType {
+        let inner_view = views::LinearLayout::new(Orientation::Horizontal)
+            .child(views::TextView::new(""))
+            .child(views::DummyView.full_width())
+            .child(views::TextView::new(""));
+        StatusBarView { inner_view }
+    }
+}
+
+impl view::ViewWrapper for StatusBarView {
+    cursive::wrap_impl!(self.inner_view: views::LinearLayout);
+}
+
 pub struct CmdBarView;
 
 impl ViewBuilder for CmdBarView {
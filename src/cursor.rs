@@ -5,15 +5,39 @@ use ropey::Rope;
 
 mod grapheme_iterator;
 pub mod movement;
+mod rope_ext;
 pub use grapheme_iterator::*;
+pub use rope_ext::*;
 
 use self::movement::right_grapheme;
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, serde::Serialize)]
 pub struct TextSelection(pub usize, pub Option<usize>);
 
+impl TextSelection {
+    /// Returns the selection with its head and tail clamped to the end of the buffer, e.g., after
+    /// the buffer is truncated.
+    pub fn clamp_to_buf(&self, buf: &Rope) -> TextSelection {
+        let len = buf.len_chars();
+        TextSelection(
+            std::cmp::min(self.0, len),
+            self.1.map(|tail| std::cmp::min(tail, len)),
+        )
+    }
+}
+
 pub trait SelectionIterator {
     fn collect_merged(self, buf: &Rope) -> Vec<(usize, usize)>;
+    fn collect_non_overlapping(self, buf: &Rope) -> Vec<TextSelection>;
+    #[cfg(test)]
+    fn zip_with_text(self, buf: &Rope) -> impl Iterator<Item = (TextSelection, String)>;
+}
+
+/// Returns the character range covered by the given selection, where the end is exclusive.
+pub fn selection_range(sel: &TextSelection, buf: &Rope) -> (usize, usize) {
+    let min = std::cmp::min(sel.0, sel.1.unwrap_or(sel.0));
+    let max = std::cmp::max(sel.0, sel.1.unwrap_or(sel.0));
+    (min, right_grapheme(max, buf).unwrap_or(max))
 }
 
 // Blanket implementation for all iterators that yield `TextSelection`s.
@@ -26,12 +50,7 @@ where
     fn collect_merged(self, buf: &Rope) -> Vec<(usize, usize)> {
         let sels = self
             .sorted_by_key(|sel| std::cmp::min(sel.0, sel.1.unwrap_or(sel.0)))
-            .map(|sel| {
-                let min = std::cmp::min(sel.0, sel.1.unwrap_or(sel.0));
-                let mut max = std::cmp::max(sel.0, sel.1.unwrap_or(sel.0));
-                max = right_grapheme(max, buf).unwrap_or(max);
-                (min, max)
-            })
+            .map(|sel| selection_range(&sel, buf))
             .collect_vec();
         let mut merged_sels = VecDeque::new();
         for (start, end) in sels {
@@ -57,4 +76,139 @@ where
         }
         merged_sels.into()
     }
+
+    /// Collects the selections into a vector sorted by their leftmost character, dropping every
+    /// selection that overlaps with a previously yielded one. Unlike `collect_merged`, the
+    /// kept selections are left untouched.
+    fn collect_non_overlapping(self, buf: &Rope) -> Vec<TextSelection> {
+        let mut kept: Vec<(TextSelection, (usize, usize))> = vec![];
+        for sel in self {
+            let (start, end) = selection_range(&sel, buf);
+            let overlaps = kept.iter().any(|(_, (kept_start, kept_end))| {
+                start == *kept_start || (start < *kept_end && *kept_start < end)
+            });
+            if !overlaps {
+                kept.push((sel, (start, end)));
+            }
+        }
+        kept.into_iter()
+            .sorted_by_key(|(_, (start, _))| *start)
+            .map(|(sel, _)| sel)
+            .collect_vec()
+    }
+
+    /// Pairs each selection with the text it covers, as given by `selection_range`.
+    #[cfg(test)]
+    fn zip_with_text(self, buf: &Rope) -> impl Iterator<Item = (TextSelection, String)> {
+        self.map(move |sel| {
+            let (start, end) = selection_range(&sel, buf);
+            (sel, buf.slice(start..end).to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_overlapping_keeps_first() {
+        let buf = Rope::from_str("abcdefgh");
+        let sels = [
+            TextSelection(4, Some(2)),
+            TextSelection(3, None),
+            TextSelection(0, None),
+            TextSelection(5, Some(7)),
+        ];
+        let collected = sels.into_iter().collect_non_overlapping(&buf);
+        let collected = collected.iter().map(|sel| (sel.0, sel.1)).collect_vec();
+        assert_eq!(collected, vec![(0, None), (4, Some(2)), (5, Some(7))]);
+    }
+
+    #[test]
+    fn non_overlapping_same_grapheme() {
+        let buf = Rope::from_str("abc");
+        let sels = [TextSelection(1, None), TextSelection(1, None)];
+        assert_eq!(sels.into_iter().collect_non_overlapping(&buf).len(), 1);
+        let sels = [TextSelection(3, None), TextSelection(3, None)];
+        assert_eq!(sels.into_iter().collect_non_overlapping(&buf).len(), 1);
+    }
+
+    #[test]
+    fn zipped_with_text() {
+        let buf = Rope::from_str("abcde\u{301}f");
+        let texts = [
+            TextSelection(3, Some(1)),
+            TextSelection(4, None),
+            TextSelection(0, Some(6)),
+        ]
+        .into_iter()
+        .zip_with_text(&buf)
+        .map(|(_, text)| text)
+        .collect_vec();
+        assert_eq!(texts, vec!["bcd", "e\u{301}", "abcde\u{301}f"]);
+    }
+
+    #[test]
+    fn paragraph_lines_runs() {
+        let buf = Rope::from_str("a\nb\n\n  \nc\n");
+        assert_eq!(movement::paragraph_lines(0, &buf), Some((0, 1)));
+        assert_eq!(movement::paragraph_lines(4, &buf), Some((2, 3)));
+        assert_eq!(movement::paragraph_lines(8, &buf), Some((4, 4)));
+        assert_eq!(movement::paragraph_lines(10, &buf), None);
+    }
+
+    #[test]
+    fn clamped_selection() {
+        let buf = Rope::from_str("abc");
+        let sel = TextSelection(5, Some(1)).clamp_to_buf(&buf);
+        assert_eq!((sel.0, sel.1), (3, Some(1)));
+        let sel = TextSelection(2, Some(7)).clamp_to_buf(&buf);
+        assert_eq!((sel.0, sel.1), (2, Some(3)));
+    }
+
+    #[test]
+    fn grapheme_n_movements() {
+        let buf = Rope::from_str("ae\u{301}bc");
+        assert_eq!(movement::right_grapheme_n(0, 2, &buf), Some(3));
+        assert_eq!(movement::right_grapheme_n(0, 10, &buf), Some(5));
+        assert_eq!(movement::right_grapheme_n(1, 0, &buf), Some(1));
+        assert_eq!(movement::left_grapheme_n(4, 2, &buf), Some(1));
+        assert_eq!(movement::left_grapheme_n(4, 10, &buf), Some(0));
+    }
+
+    #[test]
+    fn nth_occurrences() {
+        let buf = Rope::from_str("xaxbxc");
+        assert_eq!(movement::right_occurrence_n(1, "x", 0, &buf), Some(1));
+        assert_eq!(movement::right_occurrence_n(1, "x", 1, &buf), Some(2));
+        assert_eq!(movement::right_occurrence_n(1, "x", 2, &buf), Some(4));
+        assert_eq!(movement::right_occurrence_n(1, "x", 3, &buf), None);
+        assert_eq!(movement::left_occurrence_n(5, "x", 0, &buf), Some(5));
+        assert_eq!(movement::left_occurrence_n(5, "x", 1, &buf), Some(4));
+        assert_eq!(movement::left_occurrence_n(5, "x", 3, &buf), Some(0));
+        assert_eq!(movement::left_occurrence_n(5, "x", 4, &buf), None);
+    }
+
+    #[test]
+    fn goto_line_and_column() {
+        let buf = Rope::from_str("abc\nde\u{301}f\ng");
+        assert_eq!(movement::goto_line(0, &buf), Some(0));
+        assert_eq!(movement::goto_line(1, &buf), Some(4));
+        assert_eq!(movement::goto_line(10, &buf), Some(9));
+        assert_eq!(movement::column_to_char_idx(5, 0, &buf), Some(4));
+        assert_eq!(movement::column_to_char_idx(5, 2, &buf), Some(7));
+        assert_eq!(movement::column_to_char_idx(5, 10, &buf), Some(8));
+        assert_eq!(movement::column_to_char_idx(9, 3, &buf), Some(9));
+    }
+
+    #[test]
+    fn leading_whitespace() {
+        let buf = Rope::from_str("  \tab c\n\n   \nd");
+        assert_eq!(movement::line_leading_whitespace(0, &buf), "  \t");
+        assert_eq!(movement::line_leading_whitespace(5, &buf), "  \t");
+        assert_eq!(movement::line_leading_whitespace(8, &buf), "");
+        assert_eq!(movement::line_leading_whitespace(10, &buf), "   ");
+        assert_eq!(movement::line_leading_whitespace(13, &buf), "");
+    }
 }
@@ -20,6 +20,30 @@ pub fn left_grapheme(char_idx: usize, buf: &Rope) -> Option<usize> {
     Some(it.curr_idx())
 }
 
+/// Moves `count` graphemes to the right, stopping early at the end of the buffer.
+pub fn right_grapheme_n(char_idx: usize, count: usize, buf: &Rope) -> Option<usize> {
+    let mut curr_idx = char_idx;
+    for _ in 0..count {
+        match right_grapheme(curr_idx, buf) {
+            Some(next_idx) if next_idx != curr_idx => curr_idx = next_idx,
+            _ => break,
+        }
+    }
+    Some(curr_idx)
+}
+
+/// Moves `count` graphemes to the left, stopping early at the start of the buffer.
+pub fn left_grapheme_n(char_idx: usize, count: usize, buf: &Rope) -> Option<usize> {
+    let mut curr_idx = char_idx;
+    for _ in 0..count {
+        match left_grapheme(curr_idx, buf) {
+            Some(next_idx) if next_idx != curr_idx => curr_idx = next_idx,
+            _ => break,
+        }
+    }
+    Some(curr_idx)
+}
+
 fn jump_to_line(
     curr_char_idx: usize,
     curr_line_idx: usize,
@@ -96,6 +120,21 @@ pub fn line_end(char_idx: usize, buf: &Rope) -> Option<usize> {
     Some(line_start + buf.get_line(line_idx)?.len_chars().saturating_sub(1))
 }
 
+/// Returns the start of the line with the given index, clamped to the last line.
+pub fn goto_line(line_idx: usize, buf: &Rope) -> Option<usize> {
+    let line_idx = std::cmp::min(line_idx, buf.len_lines().saturating_sub(1));
+    buf.try_line_to_char(line_idx).ok()
+}
+
+/// Returns the grapheme at the given column of the line that contains the char, clamped to the
+/// end of the line.
+pub fn column_to_char_idx(char_idx: usize, column: usize, buf: &Rope) -> Option<usize> {
+    let line_start = line_start(char_idx, buf)?;
+    let line_end = line_end(char_idx, buf)?;
+    let column_idx = right_grapheme_n(line_start, column, buf)?;
+    Some(std::cmp::min(column_idx, line_end))
+}
+
 pub fn next_line_start(char_idx: usize, buf: &Rope) -> Option<usize> {
     let line_idx = buf.try_char_to_line(char_idx).ok()?;
     if line_idx == buf.len_lines().saturating_sub(1) {
@@ -127,6 +166,45 @@ pub fn left_occurrence(char_idx: usize, target: &str, buf: &Rope) -> Option<usiz
     Some(next_occurrence)
 }
 
+/// Returns true iff the grapheme at the given char ends with the target.
+fn is_occurrence(char_idx: usize, target: &str, buf: &Rope) -> bool {
+    buf.graphemes(char_idx)
+        .next()
+        .is_some_and(|g| g.ends_with(target))
+}
+
+/// Finds the `n`th occurrence of the target to the right, where the first one may be at the given
+/// char. Returns none if there are fewer occurrences.
+pub fn right_occurrence_n(char_idx: usize, target: &str, n: usize, buf: &Rope) -> Option<usize> {
+    let mut curr_idx = char_idx;
+    for i in 0..n {
+        let from = if i == 0 {
+            curr_idx
+        } else {
+            right_grapheme(curr_idx, buf)?
+        };
+        curr_idx =
+            right_occurrence(from, target, buf).filter(|idx| is_occurrence(*idx, target, buf))?;
+    }
+    Some(curr_idx)
+}
+
+/// Finds the `n`th occurrence of the target to the left, where the first one may be at the given
+/// char. Returns none if there are fewer occurrences.
+pub fn left_occurrence_n(char_idx: usize, target: &str, n: usize, buf: &Rope) -> Option<usize> {
+    let mut curr_idx = char_idx;
+    for i in 0..n {
+        let from = if i == 0 {
+            curr_idx
+        } else {
+            left_grapheme(curr_idx, buf).filter(|idx| *idx != curr_idx)?
+        };
+        curr_idx =
+            left_occurrence(from, target, buf).filter(|idx| is_occurrence(*idx, target, buf))?;
+    }
+    Some(curr_idx)
+}
+
 pub fn right_word_start(char_idx: usize, buf: &Rope) -> Option<usize> {
     if char_idx == buf.len_chars() {
         return None;
@@ -178,3 +256,79 @@ pub fn left_word_end(char_idx: usize, buf: &Rope) -> Option<usize> {
     let idx = it.curr_idx();
     return Some(idx);
 }
+
+fn is_blank_line(line_idx: usize, buf: &Rope) -> bool {
+    buf.get_line(line_idx)
+        .map(|line| line.chars().all(char::is_whitespace))
+        .unwrap_or(true)
+}
+
+/// Returns the first and the last line of the paragraph that contains the given character. A
+/// paragraph is a maximal run of either non-blank or blank lines.
+pub fn paragraph_lines(char_idx: usize, buf: &Rope) -> Option<(usize, usize)> {
+    let line_idx = buf.try_char_to_line(char_idx).ok()?;
+    // Ignore the empty line after the trailing newline.
+    let num_lines = match buf.get_line(buf.len_lines().saturating_sub(1)) {
+        Some(last_line) if last_line.len_chars() == 0 => buf.len_lines().saturating_sub(1),
+        _ => buf.len_lines(),
+    };
+    if line_idx >= num_lines {
+        return None;
+    }
+    let is_blank = is_blank_line(line_idx, buf);
+    let first_line = (0..line_idx)
+        .rev()
+        .take_while(|other_idx| is_blank_line(*other_idx, buf) == is_blank)
+        .last()
+        .unwrap_or(line_idx);
+    let last_line = (line_idx + 1..num_lines)
+        .take_while(|other_idx| is_blank_line(*other_idx, buf) == is_blank)
+        .last()
+        .unwrap_or(line_idx);
+    Some((first_line, last_line))
+}
+
+/// Returns the number of leading whitespace characters of the given line.
+fn indent_level(line_idx: usize, buf: &Rope) -> Option<usize> {
+    Some(
+        buf.get_line(line_idx)?
+            .chars()
+            .take_while(|c| c.is_whitespace() && *c != '\n' && *c != '\r')
+            .count(),
+    )
+}
+
+/// Returns the whitespace between the start of the line that contains the given char and the
+/// first non-whitespace character of the line.
+pub fn line_leading_whitespace(char_idx: usize, buf: &Rope) -> String {
+    let Ok(line_idx) = buf.try_char_to_line(char_idx) else {
+        return String::new();
+    };
+    let line_start = buf.line_to_char(line_idx);
+    let indent_level = indent_level(line_idx, buf).unwrap_or(0);
+    buf.slice(line_start..line_start + indent_level).to_string()
+}
+
+/// Returns the start of the next non-blank line that is indented at most as much as the current
+/// line.
+pub fn move_head_to_same_indent_forward(char_idx: usize, buf: &Rope) -> Option<usize> {
+    let line_idx = buf.try_char_to_line(char_idx).ok()?;
+    let curr_indent = indent_level(line_idx, buf)?;
+    let target_line = (line_idx + 1..buf.len_lines()).find(|other_idx| {
+        !is_blank_line(*other_idx, buf)
+            && matches!(indent_level(*other_idx, buf), Some(indent) if indent <= curr_indent)
+    })?;
+    buf.try_line_to_char(target_line).ok()
+}
+
+/// Returns the start of the previous non-blank line that is indented at most as much as the
+/// current line.
+pub fn move_head_to_same_indent_backward(char_idx: usize, buf: &Rope) -> Option<usize> {
+    let line_idx = buf.try_char_to_line(char_idx).ok()?;
+    let curr_indent = indent_level(line_idx, buf)?;
+    let target_line = (0..line_idx).rev().find(|other_idx| {
+        !is_blank_line(*other_idx, buf)
+            && matches!(indent_level(*other_idx, buf), Some(indent) if indent <= curr_indent)
+    })?;
+    buf.try_line_to_char(target_line).ok()
+}
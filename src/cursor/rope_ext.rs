@@ -0,0 +1,44 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+pub trait RopeExt {
+    #[cfg(test)]
+    fn lines_in_char_range(&self, start: usize, end: usize) -> std::ops::RangeInclusive<usize>;
+    fn line_char_range(&self, line_idx: usize) -> Range<usize>;
+}
+
+impl RopeExt for Rope {
+    /// Returns the indices of the lines that contain the characters in `start..=end`. The
+    /// characters past the end of the buffer are clamped to it.
+    #[cfg(test)]
+    fn lines_in_char_range(&self, start: usize, end: usize) -> std::ops::RangeInclusive<usize> {
+        let clamp = |char_idx: usize| std::cmp::min(char_idx, self.len_chars());
+        self.char_to_line(clamp(start))..=self.char_to_line(clamp(end))
+    }
+
+    /// Returns the character range of the given line, including its newline. The lines past the
+    /// end of the buffer are clamped to the last one.
+    fn line_char_range(&self, line_idx: usize) -> Range<usize> {
+        let line_idx = std::cmp::min(line_idx, self.len_lines().saturating_sub(1));
+        let start = self.line_to_char(line_idx);
+        start..start + self.line(line_idx).len_chars()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_ranges() {
+        let buf = Rope::from_str("ab\ncd\n\nef");
+        assert_eq!(buf.lines_in_char_range(0, 0), 0..=0);
+        assert_eq!(buf.lines_in_char_range(1, 4), 0..=1);
+        assert_eq!(buf.lines_in_char_range(2, 100), 0..=3);
+        assert_eq!(buf.line_char_range(0), 0..3);
+        assert_eq!(buf.line_char_range(2), 6..7);
+        assert_eq!(buf.line_char_range(3), 7..9);
+        assert_eq!(buf.line_char_range(10), 7..9);
+    }
+}
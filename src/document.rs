@@ -1,17 +1,118 @@
 use crate::cursor::GraphemeIterable;
 use crate::cursor::TextSelection;
+use itertools::Itertools;
+use primitive_mods::{BufMod, DocMapMod, PrimitiveMod, SelectionMod};
 use ropey::Rope;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use unicode_width::UnicodeWidthStr;
 
+mod diff;
 pub mod primitive_mods;
 mod transaction;
 
+pub use diff::DiffOp;
+pub use transaction::EditOp;
 pub use transaction::Transaction;
 pub use transaction::TransactionDep;
 
-#[derive(Clone, Debug, Default)]
-pub struct DocumentSource(Option<String>);
+/// Represents the line endings used in a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum LineEnding {
+    #[default]
+    Unix,
+    Windows,
+    Mixed,
+}
+
+/// Represents the text encoding of a file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    /// The superset of Latin-1 that the files labeled as Latin-1 are decoded with in practice.
+    Windows1252,
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+impl Encoding {
+    /// Decodes the given bytes, which must be in this encoding.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf8Bom => {
+                String::from_utf8_lossy(bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes)).into_owned()
+            }
+            Encoding::Windows1252 => encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+        }
+    }
+
+    /// Encodes the given string. Returns the first character that cannot be represented in this
+    /// encoding as the error.
+    pub fn encode(&self, s: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Encoding::Utf8 => Ok(s.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([UTF8_BOM, s.as_bytes()].concat()),
+            Encoding::Windows1252 => {
+                let (bytes, _, had_errors) = encoding_rs::WINDOWS_1252.encode(s);
+                if !had_errors {
+                    return Ok(bytes.into_owned());
+                }
+                Err(s
+                    .chars()
+                    .find(|c| {
+                        encoding_rs::WINDOWS_1252
+                            .encode(c.encode_utf8(&mut [0; 4]))
+                            .2
+                    })
+                    .unwrap_or(char::REPLACEMENT_CHARACTER))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Windows1252 => "windows-1252",
+        })
+    }
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Ok(Encoding::Utf8Bom),
+            // Latin-1 is decoded as Windows-1252, like the web browsers do.
+            "windows-1252" | "cp1252" | "latin1" | "latin-1" | "iso-8859-1" => {
+                Ok(Encoding::Windows1252)
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Represents the file that a document is read from, along with its original line endings.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DocumentSource(Option<String>, LineEnding);
+
+impl DocumentSource {
+    /// Returns the path of the file, if the document is read from one.
+    pub fn path(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
 
 impl std::fmt::Display for DocumentSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -23,23 +124,39 @@ impl std::fmt::Display for DocumentSource {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Serialize)]
 pub struct DocumentView {
     pub x_offset: usize,
     pub y_offset: usize,
     pub max_height: usize,
     pub max_width: usize,
+    /// Minimum number of columns kept between the cursor and the left/right edges of the view.
+    pub scroll_margin_x: usize,
+    /// Minimum number of lines kept between the cursor and the top/bottom edges of the view.
+    pub scroll_margin_y: usize,
+}
+
+impl Default for DocumentView {
+    fn default() -> Self {
+        DocumentView {
+            x_offset: 0,
+            y_offset: 0,
+            max_height: 0,
+            max_width: 0,
+            scroll_margin_x: 5,
+            scroll_margin_y: 3,
+        }
+    }
 }
 
 impl DocumentView {
     /// Returns the approximate number of chars displayed in the view.
     /// Can be used for optimization.
     pub fn approx_displayed_len_chars(&self, buf: &Rope) -> usize {
-        buf.lines()
-            .skip(self.y_offset)
-            .take(self.max_height)
-            .map(|line| {
-                line.chunks()
+        self.visible_line_range(buf)
+            .map(|line_idx| {
+                buf.line(line_idx)
+                    .chunks()
                     .map(|s| (s.chars().count(), s.width()))
                     .scan(0, |curr_width_sum, (char_count, w)| {
                         *curr_width_sum += w;
@@ -53,6 +170,27 @@ impl DocumentView {
             .sum()
     }
 
+    /// Returns the first and the last line displayed in the view, clamped to the lines of the
+    /// buffer. The range is empty if no line is displayed, e.g., if the view is scrolled past the
+    /// end of the buffer.
+    pub fn visible_line_range(&self, buf: &Rope) -> RangeInclusive<usize> {
+        let first_line = std::cmp::min(self.y_offset, buf.len_lines());
+        let end_line = std::cmp::min(self.y_offset + self.max_height, buf.len_lines());
+        if end_line <= first_line {
+            return first_line + 1..=first_line;
+        }
+        first_line..=end_line - 1
+    }
+
+    /// Returns the range of characters on the lines displayed in the view, where the end is
+    /// exclusive.
+    pub fn visible_char_range(&self, buf: &Rope) -> (usize, usize) {
+        let lines = self.visible_line_range(buf);
+        let end_line = std::cmp::min(lines.end() + 1, buf.len_lines());
+        let first_line = std::cmp::min(*lines.start(), end_line);
+        (buf.line_to_char(first_line), buf.line_to_char(end_line))
+    }
+
     pub fn map_to_visual_position(char_idx: usize, buf: &Rope) -> (usize, usize) {
         let y_offset = buf.try_char_to_line(char_idx).unwrap_or(0);
         let line_start = buf.try_line_to_char(y_offset).unwrap_or(0);
@@ -65,7 +203,7 @@ impl DocumentView {
                 curr_sum.1 += width;
                 Some(*curr_sum)
             })
-            .take_while(|(c_sum, _)| *c_sum < char_offset_at_line)
+            .take_while(|(c_sum, _)| *c_sum <= char_offset_at_line)
             .map(|(_, w_sum)| w_sum)
             .last()
             .unwrap_or(0);
@@ -78,14 +216,37 @@ impl DocumentView {
     }
 }
 
+/// The text and the selections of a document at some point, which the document can be restored
+/// to, e.g., after previewing a modification.
 #[derive(Clone, Debug)]
+pub struct DocumentSnapshot {
+    pub buf: Rope,
+    pub selections: HashMap<usize, TextSelection>,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Document {
     pub source: DocumentSource,
     pub selections: HashMap<usize, TextSelection>,
     pub dirty: bool,
+    /// The encoding that the document is saved with.
+    pub encoding: Encoding,
+    /// Whether the file that the document is read from cannot be written to.
+    read_only: bool,
+    /// Whether the document lists the results of a `:grep`, which can be jumped to.
+    pub grep_results: bool,
+    #[serde(rename = "text", serialize_with = "serialize_rope")]
     inner_buf: Rope,
 }
 
+/// The id of the primary selection, which the view follows.
+pub const PRIMARY_SEL_ID: usize = 0;
+
+/// Serializes a rope as a plain string.
+fn serialize_rope<S: serde::Serializer>(rope: &Rope, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(rope)
+}
+
 impl Document {
     pub fn new_empty() -> Self {
         Document {
@@ -93,34 +254,279 @@ impl Document {
             inner_buf: ropey::Rope::new(),
             source: Default::default(),
             dirty: false,
+            encoding: Default::default(),
+            read_only: false,
+            grep_results: false,
         }
     }
 
     pub fn new_from_file(file_path: &str) -> Self {
-        if let Ok(file_str) = std::fs::read_to_string(file_path) {
-            Document {
+        if let Ok(file_bytes) = std::fs::read(file_path) {
+            let encoding = Self::detect_encoding(&file_bytes);
+            let mut doc = Document {
                 selections: HashMap::from([(0, TextSelection::default())]),
-                inner_buf: ropey::Rope::from_str(&file_str),
-                source: DocumentSource(Some(file_path.to_string())),
+                inner_buf: ropey::Rope::from_str(&encoding.decode(&file_bytes)),
+                source: DocumentSource(Some(file_path.to_string()), LineEnding::Unix),
                 dirty: false,
-            }
+                encoding,
+                read_only: std::fs::metadata(file_path)
+                    .map(|m| m.permissions().readonly())
+                    .unwrap_or(false),
+                grep_results: false,
+            };
+            // Work with `\n` only and restore the original line endings on save.
+            doc.source.1 = doc.detect_line_ending();
+            doc.normalize_line_endings();
+            doc
         } else {
             Self::new_empty()
         }
     }
 
+    /// Detects the encoding of the given file contents. Contents that are not valid UTF-8 are
+    /// assumed to be Windows-1252.
+    pub fn detect_encoding(bytes: &[u8]) -> Encoding {
+        if std::str::from_utf8(bytes).is_err() {
+            Encoding::Windows1252
+        } else if bytes.starts_with(UTF8_BOM) {
+            Encoding::Utf8Bom
+        } else {
+            Encoding::Utf8
+        }
+    }
+
+    /// Detects the line endings of the document by sampling its first 100 lines.
+    pub fn detect_line_ending(&self) -> LineEnding {
+        let (num_windows, num_unix) = self
+            .inner_buf
+            .lines()
+            .take(100)
+            .filter(|line| line.chars().last() == Some('\n'))
+            .fold((0, 0), |(num_windows, num_unix), line| {
+                let len = line.len_chars();
+                if len >= 2 && line.char(len - 2) == '\r' {
+                    (num_windows + 1, num_unix)
+                } else {
+                    (num_windows, num_unix + 1)
+                }
+            });
+        match (num_windows, num_unix) {
+            (0, _) => LineEnding::Unix,
+            (_, 0) => LineEnding::Windows,
+            _ => LineEnding::Mixed,
+        }
+    }
+
+    /// Converts all the `\r\n` line endings into `\n` in-place.
+    pub fn normalize_line_endings(&mut self) {
+        let buf_str = self.inner_buf.to_string();
+        if buf_str.contains("\r\n") {
+            self.inner_buf = Rope::from_str(&buf_str.replace("\r\n", "\n"));
+        }
+    }
+
+    /// Returns the contents of the document with the line endings of its source. Documents with
+    /// mixed line endings are saved with `\n`.
+    fn contents_to_save(&self) -> String {
+        let buf_str = self.inner_buf.to_string();
+        match self.source.1 {
+            LineEnding::Windows => buf_str.replace('\n', "\r\n"),
+            LineEnding::Unix | LineEnding::Mixed => buf_str,
+        }
+    }
+
+    /// Returns the encoded contents of the document, or an error if they cannot be represented in
+    /// its encoding.
+    fn bytes_to_save(&self) -> Result<Vec<u8>, std::io::Error> {
+        self.encoding.encode(&self.contents_to_save()).map_err(|c| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("`{}` cannot be encoded in {}", c, self.encoding),
+            )
+        })
+    }
+
+    /// Returns true iff the document is read from a file without write permission, in which case
+    /// its text cannot be modified.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Locks or unlocks the text of the document regardless of the permissions of its file.
+    pub fn set_read_only(&mut self, flag: bool) {
+        self.read_only = flag;
+    }
+
     pub fn get_buf(&self) -> &Rope {
         &self.inner_buf
     }
 
+    /// Returns the primary selection, unless it is removed.
+    pub fn primary_selection(&self) -> Option<&TextSelection> {
+        self.selections.get(&PRIMARY_SEL_ID)
+    }
+
+    /// Returns a snapshot of the text and the selections. Cloning the text is cheap, as the rope
+    /// shares its nodes until either copy is modified.
+    pub fn create_snapshot(&self) -> DocumentSnapshot {
+        DocumentSnapshot {
+            buf: self.inner_buf.clone(),
+            selections: self.selections.clone(),
+        }
+    }
+
+    /// Replaces the text and the selections with those of the given snapshot. Unlike undoing,
+    /// this does not involve the history at all.
+    pub fn restore_snapshot(&mut self, snap: DocumentSnapshot) {
+        self.inner_buf = snap.buf;
+        self.selections = snap.selections;
+        self.dirty = true;
+    }
+
     pub fn get_buf_mut(&mut self) -> &mut Rope {
         self.dirty = true;
         &mut self.inner_buf
     }
 
+    /// Directly applies the given selection or text modification to the document without
+    /// computing its inverse. Document map modifications cannot be applied to a single document.
+    /// Returns true iff the modification is applied successfully.
+    pub fn apply_mod(&mut self, pm: &PrimitiveMod) -> bool {
+        match pm {
+            PrimitiveMod::Sel(_, sel_id, sel_mod) => {
+                let Some(sel) = self.selections.get_mut(sel_id) else {
+                    return false;
+                };
+                match sel_mod {
+                    SelectionMod::SetHead(head) => sel.0 = *head,
+                    SelectionMod::SetTail(tail) => sel.1 = *tail,
+                }
+                true
+            }
+            PrimitiveMod::Text(_, buf_mod) => {
+                let buf = self.get_buf_mut();
+                match buf_mod {
+                    BufMod::InsText(char_idx, text) => buf.try_insert(*char_idx, text).is_ok(),
+                    BufMod::DelRange(start, end) => buf.try_remove(*start..*end).is_ok(),
+                    BufMod::ReplaceRange(start, end, text) => {
+                        buf.try_remove(*start..*end).is_ok() && buf.try_insert(*start, text).is_ok()
+                    }
+                    BufMod::ReplaceRegex(pattern, replacement) => {
+                        let Ok(regex) = regex::Regex::new(pattern) else {
+                            return false;
+                        };
+                        let new_txt = regex
+                            .replace_all(&buf.to_string(), replacement.as_str())
+                            .into_owned();
+                        *buf = Rope::from_str(&new_txt);
+                        true
+                    }
+                    BufMod::ReplaceAll(text) => {
+                        *buf = Rope::from_str(text);
+                        true
+                    }
+                }
+            }
+            PrimitiveMod::DocMap(_) => false,
+        }
+    }
+
+    /// Returns the lines in `start_line..=end_line` along with the indices of their first
+    /// characters. The lines outside the document are left out.
+    pub fn lines_in_range(&self, start_line: usize, end_line: usize) -> Vec<(usize, String)> {
+        (start_line..=end_line)
+            .map_while(|line_idx| {
+                let line_start = self.inner_buf.try_line_to_char(line_idx).ok()?;
+                let line = self.inner_buf.get_line(line_idx)?;
+                Some((line_start, line.to_string()))
+            })
+            .collect()
+    }
+
+    /// Returns the start (inclusive), the end (exclusive) and the text of the word that contains
+    /// the given character or ends right before it. Words consist of alphanumeric characters and
+    /// underscores.
+    pub fn word_at(&self, char_idx: usize) -> Option<(usize, usize, String)> {
+        let buf = &self.inner_buf;
+        let is_word_char =
+            |idx: usize| matches!(buf.get_char(idx), Some(c) if c.is_alphanumeric() || c == '_');
+        let anchor_idx = if is_word_char(char_idx) {
+            char_idx
+        } else if char_idx > 0 && is_word_char(char_idx - 1) {
+            char_idx - 1
+        } else {
+            return None;
+        };
+        let start = (0..anchor_idx)
+            .rev()
+            .take_while(|idx| is_word_char(*idx))
+            .last()
+            .unwrap_or(anchor_idx);
+        let end = (anchor_idx..buf.len_chars())
+            .take_while(|idx| is_word_char(*idx))
+            .last()
+            .unwrap_or(anchor_idx)
+            + 1;
+        Some((start, end, buf.slice(start..end).to_string()))
+    }
+
+    /// Returns the start (inclusive) and the end (exclusive) of the first occurrence of the
+    /// pattern that starts at or after the given character.
+    pub fn search_forward(&self, pattern: &str, from: usize) -> Option<(usize, usize)> {
+        let buf = &self.inner_buf;
+        let pattern_len = pattern.chars().count();
+        if pattern_len == 0 {
+            return None;
+        }
+        let last_start = buf.len_chars().checked_sub(pattern_len)?;
+        (from..=last_start)
+            .find(|start| {
+                buf.chars_at(*start)
+                    .zip(pattern.chars())
+                    .all(|(a, b)| a == b)
+            })
+            .map(|start| (start, start + pattern_len))
+    }
+
+    /// Returns the transaction that replaces the selections of the document with the given id
+    /// with one selection for each non-overlapping occurrence of the pattern. Returns an empty
+    /// transaction if there are no occurrences.
+    pub fn create_selection_for_all_occurrences(
+        &self,
+        doc_id: usize,
+        pattern: &str,
+    ) -> Transaction {
+        let mut occurrences = vec![];
+        let mut from = 0;
+        while let Some((start, end)) = self.search_forward(pattern, from) {
+            occurrences.push((start, end));
+            from = end;
+        }
+        if occurrences.is_empty() {
+            return Transaction::new();
+        }
+        let delete_mods = self
+            .selections
+            .keys()
+            .sorted()
+            .map(|sel_id| PrimitiveMod::DocMap(DocMapMod::DeleteSel(doc_id, *sel_id)));
+        // The selections are inclusive, so the head is on the last character of the occurrence.
+        let create_mods = occurrences
+            .into_iter()
+            .enumerate()
+            .map(|(sel_id, (start, end))| {
+                PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                    doc_id,
+                    sel_id,
+                    TextSelection(end - 1, Some(start)),
+                ))
+            });
+        Transaction::new().with_mods(delete_mods.chain(create_mods))
+    }
+
     pub fn save(&mut self) -> Result<(), std::io::Error> {
-        if let DocumentSource(Some(path)) = &self.source {
-            std::fs::write(path, self.inner_buf.to_string())?;
+        if let DocumentSource(Some(path), _) = &self.source {
+            std::fs::write(path, self.bytes_to_save()?)?;
             self.dirty = false;
             Ok(())
         } else {
@@ -132,8 +538,8 @@ impl Document {
     }
 
     pub fn save_as(&mut self, new_file_path: &str) -> Result<(), std::io::Error> {
-        std::fs::write(new_file_path, self.inner_buf.to_string())?;
-        self.source = DocumentSource(Some(new_file_path.to_string()));
+        std::fs::write(new_file_path, self.bytes_to_save()?)?;
+        self.source = DocumentSource(Some(new_file_path.to_string()), self.source.1);
         self.dirty = false;
         Ok(())
     }
@@ -156,9 +562,23 @@ impl From<DocumentSource> for Document {
     }
 }
 
-/// Represents a collection of documents.
+/// Represents a change recorded in the event log of a document map.
+#[derive(Clone, Debug)]
+pub enum DocumentMapEvent {
+    TransactionApplied(Transaction),
+    /// The document with the given id was added to the map, e.g., opened from a file. A document
+    /// added by a transaction is logged before the transaction itself.
+    DocumentLoaded(usize),
+}
+
+/// Represents a collection of documents along with the optional event log.
 #[derive(Clone, Debug)]
-pub struct DocumentMap(usize, HashMap<usize, Document>, DocumentView);
+pub struct DocumentMap(
+    usize,
+    HashMap<usize, Document>,
+    DocumentView,
+    Option<Vec<DocumentMapEvent>>,
+);
 
 impl Default for DocumentMap {
     fn default() -> Self {
@@ -166,6 +586,7 @@ impl Default for DocumentMap {
             0,
             HashMap::from([(0, Document::new_empty())]),
             Default::default(),
+            None,
         )
     }
 }
@@ -183,13 +604,15 @@ impl DocumentMap {
         self.0 = new_doc_id;
     }
 
-    fn get_unused_id(&self) -> usize {
+    /// Returns the id that the next inserted document will get.
+    pub fn get_unused_id(&self) -> usize {
         self.1.keys().max().map(|buf_id| buf_id + 1).unwrap_or(0)
     }
 
     pub fn insert(&mut self, doc: Document) -> usize {
         let new_id = self.get_unused_id();
         self.1.insert(new_id, doc);
+        self.log_event(DocumentMapEvent::DocumentLoaded(new_id));
         new_id
     }
 
@@ -201,6 +624,43 @@ impl DocumentMap {
         self.1.get(id)
     }
 
+    /// Iterates over the documents along with their ids, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Document)> {
+        self.1.iter().map(|(doc_id, doc)| (*doc_id, doc))
+    }
+
+    /// Iterates over the mutable documents along with their ids, in no particular order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut Document)> {
+        self.1.iter_mut().map(|(doc_id, doc)| (*doc_id, doc))
+    }
+
+    /// Returns the non-overlapping occurrences of the pattern in all the documents as
+    /// `(doc_id, start, end)` tuples, sorted by the document and the start. The ends are exclusive.
+    pub fn search_all_documents(&self, pattern: &str) -> Vec<(usize, usize, usize)> {
+        self.1
+            .iter()
+            .flat_map(|(doc_id, doc)| {
+                let mut occurrences = vec![];
+                let mut from = 0;
+                while let Some((start, end)) = doc.search_forward(pattern, from) {
+                    occurrences.push((*doc_id, start, end));
+                    from = end;
+                }
+                occurrences
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Returns the number of documents.
+    pub fn len(&self) -> usize {
+        self.1.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.1.is_empty()
+    }
+
     pub fn get_mut(&mut self, id: &usize) -> Option<&mut Document> {
         self.1.get_mut(id)
     }
@@ -220,4 +680,198 @@ impl DocumentMap {
     pub fn get_view_mut(&mut self) -> &mut DocumentView {
         &mut self.2
     }
+
+    /// Enables the event log, which starts with the documents that are already loaded. The
+    /// sequence number of an event is its index.
+    pub fn with_event_log(mut self) -> Self {
+        if self.3.is_none() {
+            self.3 = Some(
+                self.1
+                    .keys()
+                    .copied()
+                    .sorted()
+                    .map(DocumentMapEvent::DocumentLoaded)
+                    .collect(),
+            );
+        }
+        self
+    }
+
+    /// Appends the event to the log if it is enabled.
+    pub fn log_event(&mut self, event: DocumentMapEvent) {
+        if let Some(event_log) = &mut self.3 {
+            event_log.push(event);
+        }
+    }
+
+    /// Returns the logged events starting from the given sequence number.
+    pub fn events_since(&self, seq: usize) -> &[DocumentMapEvent] {
+        self.3
+            .as_ref()
+            .and_then(|event_log| event_log.get(seq..))
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of logged events, i.e., the sequence number of the next one.
+    pub fn event_count(&self) -> usize {
+        self.3.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Drops the events logged after the given number of events, e.g., those of a transaction
+    /// that is rolled back.
+    pub fn truncate_events(&mut self, len: usize) {
+        if let Some(event_log) = &mut self.3 {
+            event_log.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc_with(content: &str) -> Document {
+        let mut doc = Document::new_empty();
+        doc.inner_buf = Rope::from_str(content);
+        doc
+    }
+
+    #[test]
+    fn visible_lines() {
+        let buf = Rope::from_str("a\nb\nc\nd");
+        let view = |y_offset, max_height| DocumentView {
+            y_offset,
+            max_height,
+            ..Default::default()
+        };
+        assert_eq!(view(1, 2).visible_line_range(&buf), 1..=2);
+        assert_eq!(view(1, 2).visible_char_range(&buf), (2, 6));
+        assert_eq!(view(2, 10).visible_line_range(&buf), 2..=3);
+        assert_eq!(view(2, 10).visible_char_range(&buf), (4, 7));
+        assert!(view(4, 10).visible_line_range(&buf).is_empty());
+        assert_eq!(view(4, 10).visible_char_range(&buf), (7, 7));
+        assert!(view(0, 0).visible_line_range(&buf).is_empty());
+        assert_eq!(view(0, 0).approx_displayed_len_chars(&buf), 0);
+    }
+
+    #[test]
+    fn search_in_all_documents() {
+        let mut doc_map = DocumentMap::default();
+        doc_map.get_mut(&0).unwrap().inner_buf = Rope::from_str("abab");
+        doc_map.insert(doc_with("b\nab"));
+        doc_map.insert(doc_with("cd"));
+        assert_eq!(
+            doc_map.search_all_documents("ab"),
+            vec![(0, 0, 2), (0, 2, 4), (1, 2, 4)]
+        );
+        assert!(doc_map.search_all_documents("x").is_empty());
+    }
+
+    #[test]
+    fn line_ending_detection() {
+        assert_eq!(doc_with("a\nb").detect_line_ending(), LineEnding::Unix);
+        assert_eq!(
+            doc_with("a\r\nb\r\n").detect_line_ending(),
+            LineEnding::Windows
+        );
+        assert_eq!(doc_with("a\r\nb\n").detect_line_ending(), LineEnding::Mixed);
+    }
+
+    #[test]
+    fn lines_in_range() {
+        let doc = doc_with("ab\ncd\nef");
+        assert_eq!(
+            doc.lines_in_range(1, 5),
+            vec![(3, "cd\n".to_string()), (6, "ef".to_string())]
+        );
+    }
+
+    #[test]
+    fn word_at_positions() {
+        let doc = doc_with("let foo_bar = 1;");
+        let foo_bar = Some((4, 11, "foo_bar".to_string()));
+        // In the middle of the word.
+        assert_eq!(doc.word_at(6), foo_bar);
+        // At the start of the word.
+        assert_eq!(doc.word_at(4), foo_bar);
+        // Right after the end of the word.
+        assert_eq!(doc.word_at(11), foo_bar);
+        assert_eq!(doc.word_at(12), None);
+        assert_eq!(doc.word_at(15), Some((14, 15, "1".to_string())));
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let latin1_bytes = b"caf\xe9";
+        let encoding = Document::detect_encoding(latin1_bytes);
+        assert_eq!(encoding, Encoding::Windows1252);
+        assert_eq!(encoding.decode(latin1_bytes), "café");
+        assert_eq!(encoding.encode("café"), Ok(latin1_bytes.to_vec()));
+        // The characters outside the encoding are not written as something else.
+        assert_eq!(encoding.encode("€ café ā"), Err('ā'));
+        let bom_bytes = b"\xef\xbb\xbfcaf\xc3\xa9";
+        let encoding = Document::detect_encoding(bom_bytes);
+        assert_eq!(encoding, Encoding::Utf8Bom);
+        assert_eq!(encoding.decode(bom_bytes), "café");
+        assert_eq!(encoding.encode("café"), Ok(bom_bytes.to_vec()));
+    }
+
+    #[test]
+    fn line_endings_restored_on_save() {
+        let mut doc = doc_with("a\r\nb\r\n");
+        doc.source.1 = doc.detect_line_ending();
+        doc.normalize_line_endings();
+        assert_eq!(doc.get_buf().to_string(), "a\nb\n");
+        assert_eq!(doc.contents_to_save(), "a\r\nb\r\n");
+    }
+
+    #[test]
+    fn restored_snapshot() {
+        let mut doc = doc_with("abc");
+        let snap = doc.create_snapshot();
+        doc.get_buf_mut().remove(0..2);
+        doc.selections.insert(1, TextSelection(1, Some(0)));
+        doc.restore_snapshot(snap);
+        assert_eq!(doc.get_buf().to_string(), "abc");
+        assert_eq!(doc.selections.keys().collect_vec(), [&0]);
+        assert!(doc.dirty);
+    }
+
+    #[test]
+    fn document_serialization() {
+        let json = serde_json::to_value(doc_with("ab\n")).unwrap();
+        assert_eq!(json["text"], "ab\n");
+        assert_eq!(json["selections"]["0"], serde_json::json!([0, null]));
+        assert_eq!(json["dirty"], false);
+    }
+
+    #[test]
+    fn select_all_occurrences() {
+        let doc = doc_with("abab aba");
+        assert_eq!(doc.search_forward("ab", 1), Some((2, 4)));
+        assert_eq!(doc.search_forward("aba", 6), None);
+        assert_eq!(doc.search_forward("", 0), None);
+        let mut doc_map = DocumentMap::default();
+        let doc_id = doc_map.insert(doc);
+        let tx = doc_map
+            .get(&doc_id)
+            .unwrap()
+            .create_selection_for_all_occurrences(doc_id, "aba");
+        assert!(tx.apply_tx(&mut doc_map).is_some());
+        let sels = doc_map
+            .get(&doc_id)
+            .unwrap()
+            .selections
+            .iter()
+            .map(|(sel_id, sel)| (*sel_id, sel.0, sel.1))
+            .sorted()
+            .collect_vec();
+        assert_eq!(sels, vec![(0, 2, Some(0)), (1, 7, Some(5))]);
+        assert!(doc_map
+            .get(&doc_id)
+            .unwrap()
+            .create_selection_for_all_occurrences(doc_id, "x")
+            .primitive_mods
+            .is_empty());
+    }
 }
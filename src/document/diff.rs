@@ -0,0 +1,225 @@
+use std::ops::Range;
+
+use itertools::Itertools;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::Document;
+
+/// The maximum number of graphemes on either side of a changed block of lines for which the
+/// changes are narrowed down to graphemes. Larger blocks are replaced as a whole.
+const MAX_GRAPHEME_DIFF_LEN: usize = 4096;
+
+/// Represents a change to a document. The char indices refer to the original document, the
+/// ranges are exclusive at the end, and the ops of a diff are ordered and do not overlap. Applying
+/// the ops from the last to the first thus keeps the indices of the remaining ops valid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffOp {
+    /// Inserts the text at the given char.
+    Insert(usize, String),
+    Delete(usize, usize),
+    Replace(usize, usize, String),
+}
+
+/// Returns the blocks of `a` and `b` that differ, using the Myers diff algorithm. The blocks
+/// are ordered and consist of the removed range of `a` and the inserted range of `b`.
+fn myers_diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(Range<usize>, Range<usize>)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    // The furthest reaching x on each diagonal k = x - y, indexed by k + max_d.
+    let max_d = n + m;
+    let mut v = vec![0; 2 * max_d as usize + 2];
+    let idx = |k: isize| (k + max_d) as usize;
+    // The relevant part of v before each step, i.e., the diagonals -d..=d.
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'search: for d in 0..=max_d {
+        trace.push(v[idx(-d)..=idx(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+    // Backtrack the single-element edits, each given by its starting point and whether it is a
+    // deletion.
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, prev_v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let prev_x_at = |k: isize| prev_v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && prev_x_at(k - 1) < prev_x_at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = prev_x_at(prev_k);
+        let prev_y = prev_x - prev_k;
+        edits.push((prev_x as usize, prev_y as usize, prev_k == k - 1));
+        (x, y) = (prev_x, prev_y);
+    }
+    // Merge the adjacent edits into blocks.
+    let mut blocks: Vec<(Range<usize>, Range<usize>)> = vec![];
+    for (x, y, is_deletion) in edits.into_iter().rev() {
+        let (a_range, b_range) = match blocks.last_mut() {
+            Some((a_range, b_range)) if a_range.end == x && b_range.end == y => (a_range, b_range),
+            _ => {
+                blocks.push((x..x, y..y));
+                let (a_range, b_range) = blocks.last_mut().unwrap();
+                (a_range, b_range)
+            }
+        };
+        if is_deletion {
+            a_range.end += 1;
+        } else {
+            b_range.end += 1;
+        }
+    }
+    blocks
+}
+
+/// Returns the op that replaces the chars in `start..end` with the given text, if any.
+fn diff_op(start: usize, end: usize, text: String) -> Option<DiffOp> {
+    match (start == end, text.is_empty()) {
+        (true, true) => None,
+        (true, false) => Some(DiffOp::Insert(start, text)),
+        (false, true) => Some(DiffOp::Delete(start, end)),
+        (false, false) => Some(DiffOp::Replace(start, end, text)),
+    }
+}
+
+/// Returns the ops that turn `old` into `new`, where `old` starts at the char `offset`.
+fn diff_graphemes(offset: usize, old: &str, new: &str) -> Vec<DiffOp> {
+    let old_graphemes = old.graphemes(true).collect_vec();
+    let new_graphemes = new.graphemes(true).collect_vec();
+    if old_graphemes.len() > MAX_GRAPHEME_DIFF_LEN || new_graphemes.len() > MAX_GRAPHEME_DIFF_LEN {
+        return diff_op(offset, offset + old.chars().count(), new.to_string())
+            .into_iter()
+            .collect();
+    }
+    // The char offset of each grapheme of the old text, including the end.
+    let char_offsets = old_graphemes
+        .iter()
+        .scan(offset, |char_idx, g| {
+            let start = *char_idx;
+            *char_idx += g.chars().count();
+            Some(start)
+        })
+        .chain([offset + old.chars().count()])
+        .collect_vec();
+    myers_diff(&old_graphemes, &new_graphemes)
+        .into_iter()
+        .filter_map(|(old_range, new_range)| {
+            diff_op(
+                char_offsets[old_range.start],
+                char_offsets[old_range.end],
+                new_graphemes[new_range].concat(),
+            )
+        })
+        .collect()
+}
+
+impl Document {
+    /// Returns the ops that turn the text of this document into the text of the other one. The
+    /// changed lines are found first and then narrowed down to the changed graphemes.
+    pub fn diff(&self, other: &Document) -> Vec<DiffOp> {
+        let old_lines = self.inner_buf.lines().map(|l| l.to_string()).collect_vec();
+        let new_lines = other.inner_buf.lines().map(|l| l.to_string()).collect_vec();
+        myers_diff(&old_lines, &new_lines)
+            .into_iter()
+            .flat_map(|(old_range, new_range)| {
+                let offset = self.inner_buf.line_to_char(old_range.start);
+                diff_graphemes(
+                    offset,
+                    &old_lines[old_range].concat(),
+                    &new_lines[new_range].concat(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::*;
+
+    fn doc_with(content: &str) -> Document {
+        let mut doc = Document::new_empty();
+        doc.inner_buf = Rope::from_str(content);
+        doc
+    }
+
+    fn apply_diff(content: &str, ops: &[DiffOp]) -> String {
+        let mut buf = Rope::from_str(content);
+        for op in ops.iter().rev() {
+            match op {
+                DiffOp::Insert(idx, text) => buf.insert(*idx, text),
+                DiffOp::Delete(start, end) => buf.remove(*start..*end),
+                DiffOp::Replace(start, end, text) => {
+                    buf.remove(*start..*end);
+                    buf.insert(*start, text);
+                }
+            }
+        }
+        buf.to_string()
+    }
+
+    #[test]
+    fn diff_ops() {
+        let old = doc_with("abc\ndef\nghi\n");
+        let new = doc_with("abc\ndxf\nghi\njk\n");
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                DiffOp::Replace(5, 6, "x".to_string()),
+                DiffOp::Insert(12, "jk\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            doc_with("ab\ncd").diff(&doc_with("cd")),
+            vec![DiffOp::Delete(0, 3)]
+        );
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn applied_diff_yields_other() {
+        // Generate pseudo-random pairs of texts from a small alphabet, so that they share lines.
+        let alphabet = ['a', 'b', '\n', 'é', '\u{301}'];
+        let mut seed: u64 = 42;
+        let mut random_text = || {
+            let mut next = || {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (seed >> 33) as usize
+            };
+            let len = next() % 24;
+            (0..len)
+                .map(|_| alphabet[next() % alphabet.len()])
+                .collect::<String>()
+        };
+        for _ in 0..500 {
+            let (old, new) = (random_text(), random_text());
+            let ops = doc_with(&old).diff(&doc_with(&new));
+            assert_eq!(apply_diff(&old, &ops), new, "{:?} -> {:?}", old, new);
+            // The ops are ordered and do not overlap.
+            let ranges = ops.iter().map(|op| match op {
+                DiffOp::Insert(idx, _) => (*idx, *idx),
+                DiffOp::Delete(start, end) | DiffOp::Replace(start, end, _) => (*start, *end),
+            });
+            assert!(ranges
+                .tuple_windows()
+                .all(|((_, prev_end), (next_start, _))| prev_end <= next_start));
+        }
+    }
+}
@@ -1,17 +1,36 @@
+#![deny(unreachable_patterns)]
+
+use regex::Regex;
 use ropey::Rope;
 
 use crate::cursor::TextSelection;
 
-use super::{Document, DocumentMap};
+use super::{Document, DocumentMap, DocumentSource};
 
 #[derive(Clone, Debug)]
 pub enum BufMod {
     InsText(usize, String),
     DelRange(usize, usize),
+    /// Replaces the chars in `start..end` with the text in one step.
+    ReplaceRange(usize, usize, String),
+    /// Replaces all the matches of the pattern in one go by rebuilding the buffer. This is much
+    /// cheaper than a deletion and an insertion per match, but the inverse restores the whole
+    /// buffer and the replacements cannot be mapped onto the character indices.
+    ReplaceRegex(String, String),
+    /// Replaces the whole content of the buffer.
+    ReplaceAll(String),
 }
 
 impl BufMod {
-    fn apply(&self, buf: &mut Rope) -> Option<Self> {
+    /// Applies the modification to a copy of the given text, e.g., to preview it. Returns none
+    /// if the modification fails.
+    pub fn apply_to_string(&self, s: &str) -> Option<String> {
+        let mut buf = Rope::from_str(s);
+        self.apply(&mut buf)?;
+        Some(buf.to_string())
+    }
+
+    pub fn apply(&self, buf: &mut Rope) -> Option<Self> {
         match self {
             BufMod::InsText(char_idx, s) => buf
                 .try_insert(*char_idx, &s)
@@ -29,6 +48,28 @@ impl BufMod {
                     None
                 }
             }
+            BufMod::ReplaceRange(start_char_idx, end_char_idx, new_txt) => {
+                let old_txt = buf.get_slice(start_char_idx..end_char_idx)?.to_string();
+                buf.try_remove(start_char_idx..end_char_idx).ok()?;
+                buf.try_insert(*start_char_idx, new_txt).ok()?;
+                Some(BufMod::ReplaceRange(
+                    *start_char_idx,
+                    start_char_idx + new_txt.chars().count(),
+                    old_txt,
+                ))
+            }
+            BufMod::ReplaceRegex(pattern, replacement) => {
+                let regex = Regex::new(pattern).ok()?;
+                let old_txt = buf.to_string();
+                let new_txt = regex.replace_all(&old_txt, replacement.as_str());
+                *buf = Rope::from_str(&new_txt);
+                Some(BufMod::ReplaceAll(old_txt))
+            }
+            BufMod::ReplaceAll(new_txt) => {
+                let old_txt = buf.to_string();
+                *buf = Rope::from_str(new_txt);
+                Some(BufMod::ReplaceAll(old_txt))
+            }
         }
     }
 }
@@ -40,7 +81,7 @@ pub enum SelectionMod {
 }
 
 impl SelectionMod {
-    fn apply(&self, sel: &mut TextSelection) -> Option<Self> {
+    pub fn apply(&self, sel: &mut TextSelection) -> Option<Self> {
         match self {
             SelectionMod::SetHead(new_char_idx) => {
                 let old_pos = sel.0;
@@ -60,6 +101,8 @@ impl SelectionMod {
 pub enum DocMapMod {
     SwitchDoc(usize),
     CreateDoc(Document),
+    /// Inserts a copy of the given document that is not associated with any source.
+    DuplicateDoc(usize),
     PopDoc(usize),
     DeleteSel(usize, usize),
     CreateSel(usize, usize, TextSelection),
@@ -86,6 +129,13 @@ impl DocMapMod {
                 };
                 Some(DocMapMod::PopDoc(new_doc_id))
             }
+            DocMapMod::DuplicateDoc(doc_id) => {
+                let mut new_doc = doc_map.get(doc_id)?.clone();
+                new_doc.dirty = true;
+                new_doc.source = DocumentSource::default();
+                new_doc.read_only = false;
+                Some(DocMapMod::PopDoc(doc_map.insert(new_doc)))
+            }
             DocMapMod::PopDoc(doc_id) => doc_map
                 .remove(doc_id)
                 .map(|removed_doc| DocMapMod::CreateDoc(removed_doc)),
@@ -114,18 +164,144 @@ pub enum PrimitiveMod {
 impl PrimitiveMod {
     pub fn apply(&self, doc_map: &mut DocumentMap) -> Option<Self> {
         match self {
-            PrimitiveMod::Sel(doc_id, sel_id, sel_mod) => doc_map
+            PrimitiveMod::Sel(doc_id, _, _) | PrimitiveMod::Text(doc_id, _) => doc_map
                 .get_mut(doc_id)
-                .and_then(|doc| doc.selections.get_mut(sel_id))
+                .and_then(|doc| self.apply_to_doc(doc)),
+            PrimitiveMod::DocMap(doc_map_mod) => doc_map_mod
+                .apply(doc_map)
+                .map(|doc_map_mod| PrimitiveMod::DocMap(doc_map_mod)),
+        }
+    }
+
+    /// Applies a selection or text modification to the given document, ignoring the document id
+    /// of the modification. Document map modifications cannot be applied to a single document.
+    pub fn apply_to_doc(&self, doc: &mut Document) -> Option<Self> {
+        match self {
+            PrimitiveMod::Sel(doc_id, sel_id, sel_mod) => doc
+                .selections
+                .get_mut(sel_id)
                 .and_then(|sel| sel_mod.apply(sel))
                 .map(|sel_mod| PrimitiveMod::Sel(*doc_id, *sel_id, sel_mod)),
-            PrimitiveMod::Text(doc_id, text_mod) => doc_map
-                .get_mut(doc_id)
-                .and_then(|doc| text_mod.apply(doc.get_buf_mut()))
+            PrimitiveMod::Text(doc_id, text_mod) => text_mod
+                .apply(doc.get_buf_mut())
                 .map(|text_mod| PrimitiveMod::Text(*doc_id, text_mod)),
-            PrimitiveMod::DocMap(editor_mod) => editor_mod
-                .apply(doc_map)
-                .map(|editor_mod| PrimitiveMod::DocMap(editor_mod)),
+            PrimitiveMod::DocMap(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::document::Transaction;
+
+    use super::*;
+
+    #[test]
+    fn buf_mods_on_strings() {
+        assert_eq!(
+            BufMod::InsText(0, "X".into()).apply_to_string("abc"),
+            Some("Xabc".into())
+        );
+        assert_eq!(
+            BufMod::DelRange(1, 3).apply_to_string("abcd"),
+            Some("ad".into())
+        );
+        assert_eq!(BufMod::DelRange(2, 5).apply_to_string("abc"), None);
+        assert_eq!(
+            BufMod::ReplaceRange(1, 3, "xyz".into()).apply_to_string("abcd"),
+            Some("axyzd".into())
+        );
+        assert_eq!(
+            BufMod::ReplaceRange(2, 5, "x".into()).apply_to_string("abc"),
+            None
+        );
+        assert_eq!(
+            BufMod::ReplaceRegex("b+".into(), "x".into()).apply_to_string("abbcb"),
+            Some("axcx".into())
+        );
+    }
+
+    #[test]
+    fn transactions_on_strings() {
+        let tx = Transaction::new().with_mods([
+            PrimitiveMod::Text(0, BufMod::DelRange(0, 1)),
+            PrimitiveMod::Text(1, BufMod::InsText(0, "other".into())),
+            PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(5)),
+            PrimitiveMod::Text(0, BufMod::InsText(2, "X".into())),
+        ]);
+        assert_eq!(tx.apply_to_string(0, "abc"), Some("bcX".into()));
+        assert_eq!(tx.apply_to_string(1, ""), Some("other".into()));
+        assert_eq!(tx.apply_to_string(0, ""), None);
+    }
+
+    /// Returns a modification of each kind. The matches stop compiling when a variant is added
+    /// without being listed here.
+    fn mod_of_each_kind() -> Vec<PrimitiveMod> {
+        let mods = vec![
+            PrimitiveMod::Text(0, BufMod::InsText(0, "abc".into())),
+            PrimitiveMod::Text(0, BufMod::DelRange(0, 1)),
+            PrimitiveMod::Text(0, BufMod::ReplaceRange(0, 1, "é".into())),
+            PrimitiveMod::Text(0, BufMod::ReplaceRegex("b".into(), "x".into())),
+            PrimitiveMod::Text(0, BufMod::ReplaceAll("def".into())),
+            PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(1)),
+            PrimitiveMod::Sel(0, 0, SelectionMod::SetTail(Some(0))),
+            PrimitiveMod::DocMap(DocMapMod::SwitchDoc(0)),
+            PrimitiveMod::DocMap(DocMapMod::CreateDoc(Document::new_empty())),
+            PrimitiveMod::DocMap(DocMapMod::DuplicateDoc(0)),
+            PrimitiveMod::DocMap(DocMapMod::CreateSel(0, 1, TextSelection(0, None))),
+            PrimitiveMod::DocMap(DocMapMod::DeleteSel(0, 1)),
+            PrimitiveMod::DocMap(DocMapMod::PopDoc(2)),
+        ];
+        for pm in &mods {
+            match pm {
+                PrimitiveMod::Text(_, buf_mod) => match buf_mod {
+                    BufMod::InsText(..)
+                    | BufMod::DelRange(..)
+                    | BufMod::ReplaceRange(..)
+                    | BufMod::ReplaceRegex(..)
+                    | BufMod::ReplaceAll(_) => {}
+                },
+                PrimitiveMod::Sel(_, _, sel_mod) => match sel_mod {
+                    SelectionMod::SetHead(_) | SelectionMod::SetTail(_) => {}
+                },
+                PrimitiveMod::DocMap(doc_map_mod) => match doc_map_mod {
+                    DocMapMod::SwitchDoc(_)
+                    | DocMapMod::CreateDoc(_)
+                    | DocMapMod::DuplicateDoc(_)
+                    | DocMapMod::PopDoc(_)
+                    | DocMapMod::DeleteSel(..)
+                    | DocMapMod::CreateSel(..) => {}
+                },
+            }
+        }
+        mods
+    }
+
+    #[test]
+    fn mods_of_each_kind_apply() {
+        let mut doc_map = DocumentMap::default();
+        for pm in mod_of_each_kind() {
+            assert!(pm.apply(&mut doc_map).is_some(), "{:?}", pm);
+        }
+        assert_eq!(doc_map.get(&0).unwrap().get_buf().to_string(), "def");
+        assert!(doc_map.get(&1).is_some() && doc_map.get(&2).is_none());
+    }
+
+    #[test]
+    fn doc_map_variant_naming() {
+        // The variant used to be called `Editor`, catch any use of the old name in the sources.
+        let stale = ["PrimitiveMod", "::Editor("].concat();
+        let mut dirs = vec![std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    let src = std::fs::read_to_string(&path).unwrap();
+                    assert!(!src.contains(&stale), "{} uses {}", path.display(), stale);
+                }
+            }
         }
     }
 }
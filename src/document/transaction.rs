@@ -1,8 +1,12 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
 
 use itertools::Itertools;
+use ropey::Rope;
 
-use super::{primitive_mods::*, DocumentMap};
+use super::{primitive_mods::*, Document, DocumentMap};
 
 /// Represents a transaction dependency.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -13,6 +17,27 @@ pub enum TransactionDep {
     DocumentMap,
 }
 
+/// Represents a text edit in terms of line and column positions, where the columns are counted in
+/// chars. The positions refer to the buffer after the preceding edits of the same script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EditOp {
+    pub start_line: usize,
+    pub start_char: usize,
+    pub end_line: usize,
+    pub end_char: usize,
+    pub new_text: String,
+}
+
+impl EditOp {
+    /// Returns the chars of the given buffer that the edit replaces, or none if the positions are
+    /// out of bounds.
+    pub fn char_range(&self, buf: &Rope) -> Option<Range<usize>> {
+        let start = buf.try_line_to_char(self.start_line).ok()? + self.start_char;
+        let end = buf.try_line_to_char(self.end_line).ok()? + self.end_char;
+        (start <= end && end <= buf.len_chars()).then_some(start..end)
+    }
+}
+
 /// Represents a sequence of primitive modifications.
 #[derive(Clone, Debug)]
 pub struct Transaction {
@@ -55,9 +80,47 @@ impl Transaction {
 
     /// Applies the transaction and returns the inverse transaction iff the application succeeds.
     pub fn apply_tx(&self, doc_map: &mut DocumentMap) -> Option<Transaction> {
+        self.apply_with(doc_map, PrimitiveMod::apply)
+    }
+
+    /// Returns true iff the transaction can be applied to the given document map, without
+    /// modifying it. Only the modified documents are copied unless the transaction modifies the
+    /// document map itself.
+    pub fn dry_run(&self, doc_map: &DocumentMap) -> bool {
+        if self
+            .primitive_mods
+            .iter()
+            .any(|pm| matches!(pm, PrimitiveMod::DocMap(_)))
+        {
+            return self.apply_tx(&mut doc_map.clone()).is_some();
+        }
+        self.split_by_document()
+            .into_iter()
+            .all(|(doc_id, doc_tx)| {
+                doc_map
+                    .get(&doc_id)
+                    .cloned()
+                    .and_then(|mut doc| doc_tx.apply_tx_to_doc(&mut doc))
+                    .is_some()
+            })
+    }
+
+    /// Applies the transaction, which must only consist of the selection and text modifications
+    /// of the given document. Returns the inverse transaction iff the application succeeds.
+    pub fn apply_tx_to_doc(&self, doc: &mut Document) -> Option<Transaction> {
+        self.apply_with(doc, PrimitiveMod::apply_to_doc)
+    }
+
+    /// Applies the modifications one by one with the given function, rolling them back if any of
+    /// them fails.
+    fn apply_with<T>(
+        &self,
+        target: &mut T,
+        apply_fn: impl Fn(&PrimitiveMod, &mut T) -> Option<PrimitiveMod>,
+    ) -> Option<Transaction> {
         let mut inv_primitives = vec![];
         for pm in &self.primitive_mods {
-            if let Some(pm_inv) = pm.apply(doc_map) {
+            if let Some(pm_inv) = apply_fn(pm, target) {
                 inv_primitives.push(pm_inv);
             } else {
                 break;
@@ -66,7 +129,7 @@ impl Transaction {
         inv_primitives.reverse();
         if inv_primitives.len() != self.primitive_mods.len() {
             for pm_inv in inv_primitives {
-                pm_inv.apply(doc_map);
+                apply_fn(&pm_inv, target);
             }
             None
         } else {
@@ -74,6 +137,101 @@ impl Transaction {
         }
     }
 
+    /// Applies the text modifications of the given document to a copy of the given text, e.g., to
+    /// preview them. Returns none if any of the modifications fails.
+    pub fn apply_to_string(&self, doc_id: usize, s: &str) -> Option<String> {
+        let mut buf = ropey::Rope::from_str(s);
+        let applied = self.primitive_mods.iter().all(|pm| match pm {
+            PrimitiveMod::Text(mod_doc_id, buf_mod) if *mod_doc_id == doc_id => {
+                buf_mod.apply(&mut buf).is_some()
+            }
+            _ => true,
+        });
+        applied.then(|| buf.to_string())
+    }
+
+    /// Returns the smallest char range that contains the text modifications of the given document,
+    /// or none if its text is not modified. An insertion covers the empty range at its index, and
+    /// the modifications that rebuild the whole buffer cover `0..usize::MAX`.
+    pub fn get_affected_char_range(&self, doc_id: usize) -> Option<Range<usize>> {
+        self.primitive_mods
+            .iter()
+            .filter_map(|pm| match pm {
+                PrimitiveMod::Text(mod_doc_id, buf_mod) if *mod_doc_id == doc_id => {
+                    Some(match buf_mod {
+                        BufMod::InsText(idx, _) => *idx..*idx,
+                        BufMod::DelRange(start, end) | BufMod::ReplaceRange(start, end, _) => {
+                            *start..*end
+                        }
+                        BufMod::ReplaceRegex(..) | BufMod::ReplaceAll(_) => 0..usize::MAX,
+                    })
+                }
+                _ => None,
+            })
+            .reduce(|range, other| {
+                std::cmp::min(range.start, other.start)..std::cmp::max(range.end, other.end)
+            })
+    }
+
+    /// Partitions the selection and text modifications by the documents they modify. Document map
+    /// modifications do not belong to a single document and are left out.
+    pub fn split_by_document(&self) -> HashMap<usize, Transaction> {
+        let mut doc_txs: HashMap<usize, Transaction> = HashMap::new();
+        for pm in &self.primitive_mods {
+            match pm {
+                PrimitiveMod::Sel(doc_id, _, _) | PrimitiveMod::Text(doc_id, _) => {
+                    doc_txs.entry(*doc_id).or_default().append_mod(pm.clone())
+                }
+                PrimitiveMod::DocMap(_) => {}
+            }
+        }
+        doc_txs
+    }
+
+    /// Returns the text modifications as a sequence of edits to the given buffer, e.g., to notify a
+    /// language server about them. The modifications are assumed to belong to the document of the
+    /// buffer, so a transaction that spans multiple documents should be split first. The buffer
+    /// wide replacements become a single edit of the whole buffer, and the script stops at the
+    /// first modification that cannot be applied.
+    pub fn to_edit_script(&self, buf_before: &Rope) -> Vec<EditOp> {
+        let position = |char_idx: usize, buf: &Rope| {
+            let line_idx = buf.try_char_to_line(char_idx).ok()?;
+            Some((line_idx, char_idx - buf.line_to_char(line_idx)))
+        };
+        let mut buf = buf_before.clone();
+        self.primitive_mods
+            .iter()
+            .filter_map(|pm| match pm {
+                PrimitiveMod::Text(_, buf_mod) => Some(buf_mod),
+                _ => None,
+            })
+            .map_while(|buf_mod| {
+                let (start, end) = match buf_mod {
+                    BufMod::InsText(idx, _) => (*idx, *idx),
+                    BufMod::DelRange(start, end) | BufMod::ReplaceRange(start, end, _) => {
+                        (*start, *end)
+                    }
+                    BufMod::ReplaceRegex(..) | BufMod::ReplaceAll(_) => (0, buf.len_chars()),
+                };
+                let (start_line, start_char) = position(start, &buf)?;
+                let (end_line, end_char) = position(end, &buf)?;
+                buf_mod.apply(&mut buf)?;
+                let new_text = match buf_mod {
+                    BufMod::InsText(_, text) | BufMod::ReplaceRange(_, _, text) => text.clone(),
+                    BufMod::DelRange(..) => String::new(),
+                    BufMod::ReplaceRegex(..) | BufMod::ReplaceAll(_) => buf.to_string(),
+                };
+                Some(EditOp {
+                    start_line,
+                    start_char,
+                    end_line,
+                    end_char,
+                    new_text,
+                })
+            })
+            .collect()
+    }
+
     /// Maps the given character index into a new index after the primitive modifications are applied.
     pub fn map_char_idx(&self, buf_id: &usize, old_idx: &usize) -> Option<usize> {
         let mut new_idx = *old_idx;
@@ -85,10 +243,15 @@ impl Transaction {
                             let added_txt_len = txt.chars().count();
                             new_idx += added_txt_len;
                         }
-                        BufMod::DelRange(start_idx, end_idx) if old_idx > end_idx => {
+                        BufMod::DelRange(start_idx, end_idx) if old_idx >= end_idx => {
                             let deleted_txt_len = end_idx - start_idx;
                             new_idx = new_idx.saturating_sub(deleted_txt_len);
                         }
+                        // The chars within the replaced range stay where they are.
+                        BufMod::ReplaceRange(start_idx, end_idx, txt) if old_idx >= end_idx => {
+                            let added_txt_len = txt.chars().count();
+                            new_idx = (new_idx + added_txt_len).saturating_sub(end_idx - start_idx);
+                        }
                         _ => {}
                     }
                 }
@@ -149,3 +312,98 @@ impl FromIterator<PrimitiveMod> for Transaction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affected_char_range() {
+        let tx = Transaction::new().with_mods([
+            PrimitiveMod::Text(0, BufMod::InsText(7, "ab".to_string())),
+            PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(1)),
+            PrimitiveMod::Text(0, BufMod::DelRange(3, 5)),
+            PrimitiveMod::Text(1, BufMod::DelRange(0, 20)),
+        ]);
+        assert_eq!(tx.get_affected_char_range(0), Some(3..7));
+        assert_eq!(tx.get_affected_char_range(1), Some(0..20));
+        assert_eq!(tx.get_affected_char_range(2), None);
+        let tx = Transaction::new().with_mod(PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(1)));
+        assert_eq!(tx.get_affected_char_range(0), None);
+    }
+
+    #[test]
+    fn replace_range_mapping() {
+        let tx = Transaction::new().with_mod(PrimitiveMod::Text(
+            0,
+            BufMod::ReplaceRange(2, 4, "xyz".into()),
+        ));
+        let mapped = [0, 2, 3, 4, 6].map(|idx| tx.map_char_idx(&0, &idx).unwrap());
+        assert_eq!(mapped, [0, 2, 3, 5, 7]);
+        // The inverse restores the replaced text.
+        let mut buf = Rope::from_str("abcdef");
+        let inv = BufMod::ReplaceRange(2, 4, "xyz".into())
+            .apply(&mut buf)
+            .unwrap();
+        assert_eq!(buf.to_string(), "abxyzef");
+        inv.apply(&mut buf).unwrap();
+        assert_eq!(buf.to_string(), "abcdef");
+    }
+
+    #[test]
+    fn edit_script() {
+        let buf = Rope::from_str("ab\ncd\n");
+        let tx = Transaction::new().with_mods([
+            PrimitiveMod::Text(0, BufMod::InsText(4, "xy\nz".to_string())),
+            PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(1)),
+            PrimitiveMod::Text(0, BufMod::DelRange(1, 5)),
+            PrimitiveMod::Text(0, BufMod::ReplaceRegex("z".to_string(), "w".to_string())),
+        ]);
+        let edit_op = |start_line, start_char, end_line, end_char, new_text: &str| EditOp {
+            start_line,
+            start_char,
+            end_line,
+            end_char,
+            new_text: new_text.to_string(),
+        };
+        let script = tx.to_edit_script(&buf);
+        assert_eq!(
+            script,
+            vec![
+                edit_op(1, 1, 1, 1, "xy\nz"),
+                edit_op(0, 1, 1, 2, ""),
+                edit_op(0, 0, 2, 0, "ay\nwd\n"),
+            ]
+        );
+        // Replaying the script yields the modified buffer.
+        let mut replayed = buf.clone();
+        for op in &script {
+            let range = op.char_range(&replayed).unwrap();
+            replayed.remove(range.clone());
+            replayed.insert(range.start, &op.new_text);
+        }
+        assert_eq!(replayed.to_string(), "ay\nwd\n");
+        // The script stops at the modification that fails.
+        let tx = Transaction::new().with_mods([
+            PrimitiveMod::Text(0, BufMod::DelRange(0, 1)),
+            PrimitiveMod::Text(0, BufMod::DelRange(0, 10)),
+        ]);
+        assert_eq!(tx.to_edit_script(&buf), vec![edit_op(0, 0, 0, 1, "")]);
+    }
+
+    #[test]
+    fn dry_run() {
+        let doc_map = DocumentMap::default();
+        let tx =
+            Transaction::new().with_mod(PrimitiveMod::Text(0, BufMod::InsText(0, "ab".into())));
+        assert!(tx.dry_run(&doc_map));
+        assert!(!tx
+            .clone()
+            .with_mod(PrimitiveMod::Text(0, BufMod::DelRange(0, 5)))
+            .dry_run(&doc_map));
+        assert!(!tx
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(1)))
+            .dry_run(&doc_map));
+        assert_eq!(doc_map.get_curr_doc().unwrap().get_buf().len_chars(), 0);
+    }
+}
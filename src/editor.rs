@@ -1,18 +1,36 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use crate::{
-    document::{Document, DocumentMap, DocumentView, Transaction},
-    events::{Key, KeyCombo, KeyEvt, KeyMods},
+    cursor::selection_range,
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod},
+        Document, DocumentMap, DocumentMapEvent, DocumentView, Encoding, Transaction,
+        PRIMARY_SEL_ID,
+    },
+    events::{Key, KeyCombo, KeyEvt, KeyMods, KeyPattern},
+    render_server::ConcreteStyle,
 };
 
-use self::editor_mode::EditorMode;
+use self::{
+    editor_mode::EditorMode, registers::RegisterBank, search::SearchState, session::Session,
+};
 
 mod editor_history;
 pub mod editor_mode;
 pub mod editor_server;
+mod registers;
+mod search;
+mod session;
+#[cfg(test)]
+pub mod test_utils;
 
 pub use editor_history::HistoricalEditorState;
 use itertools::Itertools;
+use ropey::Rope;
+use serde::Serialize;
 
 /// Represents a named function that outputs a transaction.
 #[derive(Clone, Copy)]
@@ -33,17 +51,125 @@ impl std::fmt::Debug for TransactionGenerator {
     }
 }
 
+/// Represents a configurable editor option.
+#[derive(Clone, Debug)]
+pub enum EditorOption {
+    ScrollMarginX(usize),
+    ScrollMarginY(usize),
+    LineNumbers(bool),
+    RelativeLineNumbers(bool),
+    /// Sets the encoding of the current document.
+    Encoding(Encoding),
+    /// Sets whether the text of the current document is locked.
+    ReadOnly(bool),
+    ShellTimeout(Duration),
+    /// Sets the number of modifications that are kept in the undo tree.
+    UndoLevels(usize),
+    /// Sets the name of the theme that the syntax is highlighted with.
+    Theme(String),
+}
+
+/// The theme that the syntax is highlighted with unless another one is selected.
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// Represents the editor-wide settings that are not tied to a document or a view.
+#[derive(Clone, Debug, Serialize)]
+pub struct EditorSettings {
+    pub line_numbers: bool,
+    pub relative_line_numbers: bool,
+    /// Duration after which the external commands are killed.
+    pub shell_timeout: Duration,
+    /// The name of the theme that the syntax is highlighted with.
+    pub theme: String,
+}
+
+impl EditorSettings {
+    /// Returns the number of columns that the line numbers of the given buffer take up next to
+    /// the text, i.e., the digits of the last line number and a space, or zero if they are hidden.
+    pub fn gutter_width(&self, buf: &Rope) -> usize {
+        if !self.line_numbers {
+            return 0;
+        }
+        buf.len_lines().to_string().len() + 1
+    }
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        EditorSettings {
+            line_numbers: true,
+            relative_line_numbers: false,
+            shell_timeout: Duration::from_secs(10),
+            theme: DEFAULT_THEME.to_string(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum EditorCmd {
     UndoCurrDocument,
     RedoCurrDocument,
+    /// Redoes the branch of the undo tree that was visited before the most recent one.
+    RedoAltCurrDocument,
     SaveCurrDocument(Option<String>),
     Transaction(TransactionGenerator),
+    /// Applies an already constructed transaction, e.g., one that depends on command arguments.
+    RawTransaction(Transaction),
+    /// Copies the text of the selections into the register that the key combo is qualified
+    /// with, e.g., `"ay`, or into the unnamed register.
+    YankSels,
+    /// Inserts the contents of the register that the key combo is qualified with after each
+    /// selection.
+    PasteAfterSels,
+    /// Inserts the contents of the register that the key combo is qualified with before each
+    /// selection.
+    PasteBeforeSels,
+    /// Remembers the search so that `n` and `N` can move between its matches.
+    SetSearch(SearchState),
+    /// Moves the heads to the next match of the last search.
+    SearchNext,
+    /// Moves the heads to the previous match of the last search.
+    SearchPrev,
+    /// Displays the visible lines of the current document as they would be after the
+    /// transaction in the middle box until the next key, without applying it.
+    PreviewTransaction(Transaction),
     PushMode(&'static str),
     PopMode,
+    /// Clears the mode stack and makes the given mode the base mode.
+    SetMode(&'static str),
     ResetCombo,
+    SetOption(EditorOption),
     Quit,
+    /// Fails the action with the given message, which is reported as `ModalEditorError::ModeError`.
     ThrowErr(String),
+    /// Displays a message in the log.
+    Notify(String),
+    /// Opens the file with the given path in a new document and switches to it.
+    OpenFile(String),
+    /// Lists the occurrences of the pattern in all the documents in a new document and switches
+    /// to it.
+    Grep(String),
+    /// Saves the open files and their cursors to the given path.
+    SaveSession(String),
+    /// Opens the files of the session saved at the given path.
+    LoadSession(String),
+    /// Forgets the undo history.
+    ClearHistory,
+    /// Displays the undo tree in a popup.
+    ShowUndoTree,
+    /// Remaps the key pattern to the given keys in the current mode.
+    AddMapping(KeyPattern, Vec<KeyEvt>),
+    /// Displays a loading indicator. The rest of the action runs after the indicator is
+    /// displayed, so this should precede the slow commands.
+    StartLoading,
+    /// Hides the loading indicator.
+    StopLoading,
+    /// Runs the action generator with the given arguments once the command is reached, e.g.,
+    /// after a loading indicator is displayed.
+    RunAction(ActionGenerator, Vec<String>),
+    /// Runs the last action that modified a document again, i.e., `.`, as many times as the
+    /// count of the combo.
+    RepeatLast,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -57,6 +183,14 @@ impl EditorAction {
     pub fn prepend(&mut self, cmd: EditorCmd) {
         self.0.insert(0, cmd)
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EditorCmd> {
+        self.0.iter()
+    }
 }
 
 impl FromIterator<EditorCmd> for EditorAction {
@@ -88,18 +222,36 @@ impl ActionGenerator {
     }
 }
 
+impl PartialEq for ActionGenerator {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
 impl std::fmt::Debug for ActionGenerator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("ActionGenerator({})", self.0))
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct EditorDisplay {
     pub btm_bar_text: Option<String>,
+    pub status_left: Option<String>,
+    pub status_right: Option<String>,
     pub right_box_text: Option<String>,
     pub mid_box_text: Option<String>,
+    /// Text displayed in a fullscreen overlay on top of the document.
+    pub overlay_text: Option<String>,
     pub cursor_text: Option<String>,
+    /// The style of the cursors, which defaults to the style of the selections.
+    pub cursor_style: Option<ConcreteStyle>,
+    /// The style of the cursors other than the primary one, which defaults to the style of the
+    /// secondary selections.
+    pub secondary_cursor_style: Option<ConcreteStyle>,
+    /// The pattern whose occurrences in the current document are highlighted, e.g., while
+    /// searching.
+    pub search_pattern: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -110,6 +262,18 @@ pub enum ModalEditorResult {
     ComboResetted,
     ModeUpdated(&'static str),
     DocumentSaved(usize),
+    OptionSet(EditorOption),
+    Notified(String),
+    LoadingStarted,
+    LoadingStopped,
+    /// Requests the given text to be displayed in a popup.
+    PopupRequested(String),
+    /// The result of a transaction is displayed without applying it.
+    Previewed,
+    /// The text of the selections is copied into a register.
+    Yanked,
+    /// The search is remembered for moving between its matches.
+    SearchSet,
 }
 
 #[derive(Clone, Debug)]
@@ -122,6 +286,7 @@ pub enum ModalEditorError {
     ModeError(String),
     InvalidMode(&'static str),
     CannotPopMode,
+    SessionError(String),
 }
 
 impl std::fmt::Display for ModalEditorError {
@@ -132,11 +297,67 @@ impl std::fmt::Display for ModalEditorError {
 
 impl std::error::Error for ModalEditorError {}
 
+/// Represents the failures of registering or unregistering a mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModeRegistrationError {
+    /// The mode is on the mode stack, so removing it would break the stack.
+    ModeIsActive,
+    NotRegistered,
+}
+
+impl std::fmt::Display for ModeRegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{:?}", self))
+    }
+}
+
+impl std::error::Error for ModeRegistrationError {}
+
+/// Returns a document that lists the occurrences of the pattern in all the documents, one per
+/// line as `path:line:column:text`, where the lines and the columns count from 1.
+fn grep_results_doc(doc_map: &DocumentMap, pattern: &str) -> Document {
+    let results = doc_map
+        .search_all_documents(pattern)
+        .into_iter()
+        .filter_map(|(doc_id, start, _)| {
+            let doc = doc_map.get(&doc_id)?;
+            let buf = doc.get_buf();
+            let line_idx = buf.try_char_to_line(start).ok()?;
+            let column = start - buf.line_to_char(line_idx);
+            let line = buf.line(line_idx).to_string();
+            Some(format!(
+                "{}:{}:{}:{}\n",
+                doc.source,
+                line_idx + 1,
+                column + 1,
+                line.trim_end_matches(['\n', '\r'])
+            ))
+        })
+        .collect::<String>();
+    let mut results_doc = Document::new_empty();
+    results_doc.get_buf_mut().insert(0, &results);
+    results_doc.grep_results = true;
+    results_doc
+}
+
 pub struct ModalEditor {
     historical_state: HistoricalEditorState,
     registered_modes: HashMap<&'static str, Box<dyn EditorMode>>,
-    active_modes: VecDeque<&'static str>,
+    /// The active modes, with the current mode at the end.
+    active_modes: Vec<&'static str>,
     curr_combo: KeyCombo,
+    settings: EditorSettings,
+    /// The key remappings, keyed by the modes they are active in.
+    mappings: HashMap<&'static str, Vec<(KeyPattern, Vec<KeyEvt>)>>,
+    /// The commands that follow a loading indicator, which are run on the next update.
+    pending_action: Option<EditorAction>,
+    /// The text displayed in the middle box until the next key, e.g., a preview.
+    preview_text: Option<String>,
+    /// The last action that modified a document without its mode changes, along with the combo
+    /// that triggered it, as the transaction generators depend on the combo.
+    last_modifying_action: Option<(EditorAction, KeyCombo)>,
+    /// The names of the themes that can be selected, as listed by the highlight server.
+    themes: Vec<String>,
 }
 
 impl ModalEditor {
@@ -144,50 +365,101 @@ impl ModalEditor {
         ModalEditor {
             historical_state,
             registered_modes: Default::default(),
-            active_modes: VecDeque::from([base_mode]),
+            active_modes: vec![base_mode],
             curr_combo: Default::default(),
+            settings: Default::default(),
+            mappings: Default::default(),
+            pending_action: None,
+            preview_text: None,
+            last_modifying_action: None,
+            themes: vec![],
         }
     }
 }
 
 impl ModalEditor {
     pub fn with_mode(mut self, mode: Box<dyn EditorMode>) -> Self {
-        self.registered_modes.insert(mode.id(), mode);
+        self.replace_mode(mode);
         self
     }
 
+    /// Removes the mode with the given id unless it is on the mode stack.
+    pub fn unregister_mode(
+        &mut self,
+        mode_id: &str,
+    ) -> Result<Box<dyn EditorMode>, ModeRegistrationError> {
+        if self.active_modes.contains(&mode_id) {
+            return Err(ModeRegistrationError::ModeIsActive);
+        }
+        self.registered_modes
+            .remove(mode_id)
+            .ok_or(ModeRegistrationError::NotRegistered)
+    }
+
+    /// Replaces the mode with the same id and returns the replaced mode, if any. An active mode
+    /// stays on the mode stack.
+    pub fn replace_mode(&mut self, mode: Box<dyn EditorMode>) -> Option<Box<dyn EditorMode>> {
+        self.registered_modes.insert(mode.id(), mode)
+    }
+
+    /// Adds a command to the command mode, e.g., from a plugin. Returns false if the command mode
+    /// is not registered.
+    pub fn register_command(&mut self, cmd_gen: ActionGenerator) -> bool {
+        self.registered_modes
+            .get_mut(editor_mode::CommandMode::id())
+            .map(|mode| mode.register_command(cmd_gen))
+            .is_some()
+    }
+
+    /// Sets the names of the themes that can be selected.
+    pub fn set_themes(&mut self, themes: Vec<String>) {
+        self.themes = themes;
+    }
+
     pub fn receive_key(&mut self, evt: KeyEvt) {
+        self.preview_text = None;
         self.curr_combo.add(evt)
     }
 
+    /// Returns the active modes from the base mode to the current mode.
+    pub fn mode_stack(&self) -> &[&'static str] {
+        &self.active_modes
+    }
+
     pub fn curr_mode_mut(&mut self) -> Option<&mut Box<dyn EditorMode>> {
-        let curr_mode_name = self.active_modes.front()?;
+        let curr_mode_name = self.active_modes.last()?;
         self.registered_modes.get_mut(curr_mode_name)
     }
 
     pub fn curr_mode(&self) -> Option<&Box<dyn EditorMode>> {
-        let curr_mode_name = self.active_modes.front()?;
+        let curr_mode_name = self.active_modes.last()?;
         self.registered_modes.get(curr_mode_name)
     }
 
     pub fn update_view(&mut self) {
         let curr_doc = self.historical_state.doc_map.get_curr_doc();
         let primary_head = curr_doc
-            .and_then(|doc| doc.selections.get(&0))
+            .and_then(|doc| doc.primary_selection())
             .map(|sel| sel.0)
             .unwrap_or(0);
         let (x, y) = curr_doc
             .map(|doc| doc.get_buf())
             .map(|buf| DocumentView::map_to_visual_position(primary_head, buf))
             .unwrap_or((0, 0));
-        let pillow = 10;
+        // The line numbers are displayed within the width of the view.
+        let gutter_width = curr_doc
+            .map(|doc| self.settings.gutter_width(doc.get_buf()))
+            .unwrap_or(0);
+        let text_width = self.get_view().max_width.saturating_sub(gutter_width);
+        let margin_x = self.get_view().scroll_margin_x;
+        let margin_y = self.get_view().scroll_margin_y;
         let x_boundaries = (
-            self.get_view().x_offset + pillow,
-            (self.get_view().x_offset + self.get_view().max_width).saturating_sub(pillow),
+            self.get_view().x_offset + margin_x,
+            (self.get_view().x_offset + text_width).saturating_sub(margin_x),
         );
         let y_boundaries = (
-            self.get_view().y_offset + pillow,
-            (self.get_view().y_offset + self.get_view().max_height).saturating_sub(pillow),
+            self.get_view().y_offset + margin_y,
+            (self.get_view().y_offset + self.get_view().max_height).saturating_sub(margin_y),
         );
         let x_offset_diff = (x.saturating_sub(x_boundaries.1) as isize)
             - (x_boundaries.0.saturating_sub(x) as isize);
@@ -203,17 +475,60 @@ impl ModalEditor {
         self.historical_state.doc_map.get_view_mut()
     }
 
+    pub fn get_doc(&self, doc_id: usize) -> Option<&Document> {
+        self.historical_state.doc_map.get(&doc_id)
+    }
+
+    /// Returns the logged document map events starting from the given sequence number.
+    pub fn events_since(&self, seq: usize) -> &[DocumentMapEvent] {
+        self.historical_state.doc_map.events_since(seq)
+    }
+
+    /// Returns the logged transactions, which are empty if the event log is disabled.
+    pub fn transaction_log(&self) -> Vec<Transaction> {
+        self.historical_state.transaction_log()
+    }
+
     pub fn get_view(&self) -> &DocumentView {
         &self.historical_state.doc_map.get_view()
     }
 
+    /// Returns the commands of the action that can be repeated, or none if the action moves in the
+    /// history, repeats itself or only changes the mode. The mode changes are left out so that
+    /// repeating never changes the mode.
+    fn repeatable_cmds(action: &EditorAction) -> Option<EditorAction> {
+        let moves_in_history = action.iter().any(|cmd| {
+            matches!(
+                cmd,
+                EditorCmd::UndoCurrDocument
+                    | EditorCmd::RedoCurrDocument
+                    | EditorCmd::RedoAltCurrDocument
+                    | EditorCmd::RepeatLast
+            )
+        });
+        let repeatable_cmds: EditorAction = action
+            .iter()
+            .filter(|cmd| {
+                !matches!(
+                    cmd,
+                    EditorCmd::PushMode(_) | EditorCmd::PopMode | EditorCmd::SetMode(_)
+                )
+            })
+            .cloned()
+            .collect();
+        (!moves_in_history && !repeatable_cmds.is_empty()).then_some(repeatable_cmds)
+    }
+
     /// Updates the editor with the given action.
     fn update_with_action(
         &mut self,
         action: EditorAction,
     ) -> Result<Vec<ModalEditorResult>, ModalEditorError> {
+        let repeatable_cmds = Self::repeatable_cmds(&action);
+        let trigger = self.curr_combo.clone();
         let mut results = vec![];
-        for cmd in action {
+        let mut cmds: VecDeque<EditorCmd> = action.into_iter().collect();
+        while let Some(cmd) = cmds.pop_front() {
             let result = match cmd {
                 EditorCmd::UndoCurrDocument => {
                     if let Some(tx) = self.historical_state.undo() {
@@ -229,6 +544,13 @@ impl ModalEditor {
                         Err(ModalEditorError::RedoError)
                     }
                 }
+                EditorCmd::RedoAltCurrDocument => {
+                    if let Some(tx) = self.historical_state.redo_alt() {
+                        Ok(ModalEditorResult::TxApplied(tx))
+                    } else {
+                        Err(ModalEditorError::RedoError)
+                    }
+                }
                 EditorCmd::Transaction(tx_gen) => {
                     if let Some(tx) = self
                         .historical_state
@@ -239,9 +561,83 @@ impl ModalEditor {
                         Err(ModalEditorError::TxError)
                     }
                 }
+                EditorCmd::RawTransaction(tx) => {
+                    if self.historical_state.modify_with_tx(&tx) {
+                        Ok(ModalEditorResult::TxApplied(tx))
+                    } else {
+                        Err(ModalEditorError::TxError)
+                    }
+                }
+                EditorCmd::YankSels => {
+                    let text = registers::yank_sels(&self.historical_state.doc_map)
+                        .ok_or(ModalEditorError::TxError)?;
+                    let register = RegisterBank::register_of(&self.curr_combo);
+                    self.historical_state.registers.set(register, text);
+                    Ok(ModalEditorResult::Yanked)
+                }
+                EditorCmd::PasteAfterSels | EditorCmd::PasteBeforeSels => {
+                    let text = self
+                        .historical_state
+                        .registers
+                        .get(RegisterBank::register_of(&self.curr_combo));
+                    let doc_map = &self.historical_state.doc_map;
+                    let tx = if matches!(cmd, EditorCmd::PasteAfterSels) {
+                        registers::paste_after(text, doc_map)
+                    } else {
+                        registers::paste_before(text, doc_map)
+                    };
+                    match tx {
+                        Some(tx) if self.historical_state.modify_with_tx(&tx) => {
+                            Ok(ModalEditorResult::TxApplied(tx))
+                        }
+                        _ => Err(ModalEditorError::TxError),
+                    }
+                }
+                EditorCmd::SetSearch(search) => {
+                    self.historical_state.search = Some(search);
+                    Ok(ModalEditorResult::SearchSet)
+                }
+                EditorCmd::SearchNext | EditorCmd::SearchPrev => {
+                    let tx = self.historical_state.search.as_ref().and_then(|search| {
+                        search::repeat_search(
+                            search,
+                            matches!(cmd, EditorCmd::SearchPrev),
+                            &self.historical_state.doc_map,
+                        )
+                    });
+                    match tx {
+                        Some(tx) if self.historical_state.modify_with_tx(&tx) => {
+                            Ok(ModalEditorResult::TxApplied(tx))
+                        }
+                        _ => Err(ModalEditorError::TxError),
+                    }
+                }
+                EditorCmd::PreviewTransaction(tx) => {
+                    let doc_id = self.historical_state.doc_map.curr_doc_id();
+                    let view = *self.get_view();
+                    let doc = self
+                        .historical_state
+                        .doc_map
+                        .get_curr_doc_mut()
+                        .ok_or(ModalEditorError::TxError)?;
+                    let (snap, dirty) = (doc.create_snapshot(), doc.dirty);
+                    let doc_tx = tx.split_by_document().remove(&doc_id).unwrap_or_default();
+                    let preview_text = doc_tx.apply_tx_to_doc(doc).map(|_| {
+                        let buf = doc.get_buf();
+                        view.visible_line_range(buf)
+                            .map(|line_idx| buf.line(line_idx).to_string())
+                            .collect::<String>()
+                    });
+                    doc.restore_snapshot(snap);
+                    doc.dirty = dirty;
+                    self.preview_text = Some(preview_text.ok_or(ModalEditorError::TxError)?);
+                    Ok(ModalEditorResult::Previewed)
+                }
                 EditorCmd::PushMode(new_mode) => {
                     if self.registered_modes.contains_key(new_mode) {
-                        self.active_modes.push_front(new_mode);
+                        self.active_modes.push(new_mode);
+                        let summary = self.summarize();
+                        self.curr_mode_mut().unwrap().on_enter(&summary);
                         Ok(ModalEditorResult::ModeUpdated(new_mode))
                     } else {
                         Err(ModalEditorError::InvalidMode(new_mode))
@@ -249,14 +645,34 @@ impl ModalEditor {
                 }
                 EditorCmd::PopMode => {
                     if self.active_modes.len() > 1 {
-                        self.active_modes.pop_front();
+                        let summary = self.summarize();
+                        self.curr_mode_mut().unwrap().on_exit(&summary);
+                        self.active_modes.pop();
                         Ok(ModalEditorResult::ModeUpdated(
-                            self.active_modes.front().unwrap(),
+                            self.active_modes.last().unwrap(),
                         ))
                     } else {
                         Err(ModalEditorError::CannotPopMode)
                     }
                 }
+                EditorCmd::SetMode(new_mode) => {
+                    if self.registered_modes.contains_key(new_mode) {
+                        // Exit the modes from the current one down to the base mode.
+                        while !self.active_modes.is_empty() {
+                            let summary = self.summarize();
+                            if let Some(mode) = self.curr_mode_mut() {
+                                mode.on_exit(&summary);
+                            }
+                            self.active_modes.pop();
+                        }
+                        self.active_modes.push(new_mode);
+                        let summary = self.summarize();
+                        self.curr_mode_mut().unwrap().on_enter(&summary);
+                        Ok(ModalEditorResult::ModeUpdated(new_mode))
+                    } else {
+                        Err(ModalEditorError::InvalidMode(new_mode))
+                    }
+                }
                 EditorCmd::ResetCombo => {
                     self.curr_combo.reset();
                     Ok(ModalEditorResult::ComboResetted)
@@ -276,17 +692,230 @@ impl ModalEditor {
                         self.historical_state.doc_map.curr_doc_id(),
                     ))
                 }
+                EditorCmd::SetOption(option) => {
+                    match option.clone() {
+                        EditorOption::ScrollMarginX(margin) => {
+                            self.get_view_mut().scroll_margin_x = margin
+                        }
+                        EditorOption::ScrollMarginY(margin) => {
+                            self.get_view_mut().scroll_margin_y = margin
+                        }
+                        EditorOption::LineNumbers(enabled) => self.settings.line_numbers = enabled,
+                        EditorOption::RelativeLineNumbers(enabled) => {
+                            self.settings.relative_line_numbers = enabled
+                        }
+                        EditorOption::ShellTimeout(timeout) => {
+                            self.settings.shell_timeout = timeout
+                        }
+                        EditorOption::UndoLevels(levels) => {
+                            self.historical_state.history.set_max_states(levels)
+                        }
+                        EditorOption::Theme(theme) => self.settings.theme = theme,
+                        EditorOption::Encoding(encoding) => {
+                            let curr_doc = self
+                                .historical_state
+                                .doc_map
+                                .get_curr_doc_mut()
+                                .ok_or(ModalEditorError::TxError)?;
+                            if curr_doc.encoding != encoding {
+                                curr_doc.encoding = encoding;
+                                curr_doc.dirty = true;
+                            }
+                        }
+                        EditorOption::ReadOnly(flag) => self
+                            .historical_state
+                            .doc_map
+                            .get_curr_doc_mut()
+                            .ok_or(ModalEditorError::TxError)?
+                            .set_read_only(flag),
+                    }
+                    Ok(ModalEditorResult::OptionSet(option))
+                }
                 EditorCmd::Quit => Ok(ModalEditorResult::QuitRequested),
                 EditorCmd::ThrowErr(err_msg) => Err(ModalEditorError::ModeError(err_msg)),
+                EditorCmd::Notify(msg) => Ok(ModalEditorResult::Notified(msg)),
+                EditorCmd::SaveSession(session_path) => {
+                    Session::from_doc_map(&self.historical_state.doc_map)
+                        .save(&session_path)
+                        .map_err(ModalEditorError::SessionError)?;
+                    Ok(ModalEditorResult::Notified(format!(
+                        "saved session {}",
+                        session_path
+                    )))
+                }
+                EditorCmd::LoadSession(session_path) => {
+                    let tx = Session::load(&session_path)
+                        .and_then(|session| session.restore_tx(&self.historical_state.doc_map))
+                        .map_err(ModalEditorError::SessionError)?;
+                    if self.historical_state.modify_with_tx(&tx) {
+                        Ok(ModalEditorResult::TxApplied(tx))
+                    } else {
+                        Err(ModalEditorError::TxError)
+                    }
+                }
+                EditorCmd::ClearHistory => {
+                    self.historical_state.clear_history();
+                    Ok(ModalEditorResult::Notified(
+                        "cleared the history".to_string(),
+                    ))
+                }
+                EditorCmd::ShowUndoTree => Ok(ModalEditorResult::PopupRequested(
+                    self.historical_state.history.to_display_string(),
+                )),
+                EditorCmd::AddMapping(lhs, rhs) => {
+                    // Commands are generated after leaving the command mode, so the current mode
+                    // is the one that the mapping was requested in.
+                    let curr_mode = *self.active_modes.last().ok_or(ModalEditorError::NoMode)?;
+                    let msg = format!(
+                        "mapped {} to {} in {} mode",
+                        lhs.to_display_string(),
+                        KeyCombo(rhs.clone()).to_display_string(),
+                        curr_mode
+                    );
+                    let mode_mappings = self.mappings.entry(curr_mode).or_default();
+                    mode_mappings.retain(|(other_lhs, _)| *other_lhs != lhs);
+                    mode_mappings.push((lhs, rhs));
+                    Ok(ModalEditorResult::Notified(msg))
+                }
+                EditorCmd::OpenFile(file_path) => {
+                    let new_doc_id = self.historical_state.doc_map.get_unused_id();
+                    let tx = Transaction::new()
+                        .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateDoc(
+                            Document::new_from_file(&file_path),
+                        )))
+                        .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(new_doc_id)));
+                    if self.historical_state.modify_with_tx(&tx) {
+                        Ok(ModalEditorResult::TxApplied(tx))
+                    } else {
+                        Err(ModalEditorError::TxError)
+                    }
+                }
+                EditorCmd::Grep(pattern) => {
+                    let doc_map = &self.historical_state.doc_map;
+                    let results_doc = grep_results_doc(doc_map, &pattern);
+                    if results_doc.get_buf().len_chars() == 0 {
+                        Ok(ModalEditorResult::Notified(format!(
+                            "no matches for `{}`",
+                            pattern
+                        )))
+                    } else {
+                        let tx = Transaction::new()
+                            .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateDoc(results_doc)))
+                            .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(
+                                doc_map.get_unused_id(),
+                            )));
+                        if self.historical_state.modify_with_tx(&tx) {
+                            Ok(ModalEditorResult::TxApplied(tx))
+                        } else {
+                            Err(ModalEditorError::TxError)
+                        }
+                    }
+                }
+                EditorCmd::StartLoading => {
+                    self.pending_action = Some(cmds.drain(..).collect());
+                    Ok(ModalEditorResult::LoadingStarted)
+                }
+                EditorCmd::StopLoading => Ok(ModalEditorResult::LoadingStopped),
+                EditorCmd::RunAction(action_gen, args) => {
+                    let args = args.iter().map(String::as_str).collect_vec();
+                    let action = (action_gen.1)(&args, &self.summarize()).ok_or_else(|| {
+                        ModalEditorError::ModeError("couldn't apply action".to_string())
+                    })?;
+                    // Run the generated commands in place of this one.
+                    for cmd in action.into_iter().rev() {
+                        cmds.push_front(cmd);
+                    }
+                    continue;
+                }
+                EditorCmd::RepeatLast => {
+                    let Some((action, combo)) = self.last_modifying_action.clone() else {
+                        continue;
+                    };
+                    // Replay the action as many times as the count, e.g., `3.`, with the combo
+                    // that it was originally triggered with.
+                    let count = self.curr_combo.extract_count();
+                    let trigger = std::mem::take(&mut self.curr_combo);
+                    let repeated_results = (0..count)
+                        .map(|_| {
+                            self.curr_combo = combo.clone();
+                            self.update_with_action(action.clone())
+                        })
+                        .flatten_ok()
+                        .collect::<Result<Vec<_>, _>>();
+                    self.curr_combo = trigger;
+                    results.extend(repeated_results?);
+                    continue;
+                }
             }?;
             results.push(result);
         }
+        // Only the actions that actually modified a document, or saved one, are repeated.
+        let modified = results.iter().any(|result| match result {
+            ModalEditorResult::TxApplied(tx) => tx
+                .primitive_mods
+                .iter()
+                .any(|pm| matches!(pm, PrimitiveMod::Text(..))),
+            ModalEditorResult::DocumentSaved(_) => true,
+            _ => false,
+        });
+        if let Some(repeatable_cmds) = repeatable_cmds.filter(|_| modified) {
+            self.last_modifying_action = Some((repeatable_cmds, trigger));
+        }
         Ok(results)
     }
 
-    /// Updates the editor with the action induced by the current mode.
-    /// May also change the mode or reset the current key combo if appropriate.
+    /// Returns true iff there are commands that are deferred after a loading indicator.
+    pub fn has_pending_action(&self) -> bool {
+        self.pending_action.is_some()
+    }
+
+    /// Runs the commands that are deferred after a loading indicator.
+    pub fn resume_pending_action(&mut self) -> Result<Vec<ModalEditorResult>, ModalEditorError> {
+        match self.pending_action.take() {
+            Some(action) => self.update_with_action(action),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Updates the editor with the action induced by the current mode. A combo that matches a key
+    /// mapping of the current mode is expanded first, and the combos that may still complete into
+    /// a mapping are held back.
     pub fn update(&mut self) -> Result<Vec<ModalEditorResult>, ModalEditorError> {
+        let curr_mode = self.active_modes.last().copied().unwrap_or_default();
+        let Some(mode_mappings) = self.mappings.get(curr_mode).filter(|m| !m.is_empty()) else {
+            return self.update_unmapped();
+        };
+        if let Some((_, rhs)) = mode_mappings
+            .iter()
+            .find(|(lhs, _)| lhs.matches_ref(&self.curr_combo))
+        {
+            // Feed the mapped keys one by one without remapping them, as the mode may change in
+            // between.
+            let rhs = rhs.clone();
+            self.curr_combo.reset();
+            let mut results = vec![ModalEditorResult::ComboResetted];
+            for evt in rhs {
+                self.receive_key(evt);
+                results.extend(self.update_unmapped()?);
+                // The following keys depend on the deferred commands, so they cannot wait.
+                while self.has_pending_action() {
+                    results.extend(self.resume_pending_action()?);
+                }
+            }
+            return Ok(results);
+        }
+        if mode_mappings
+            .iter()
+            .any(|(lhs, _)| lhs.is_prefix_of(&self.curr_combo))
+        {
+            return Ok(vec![]);
+        }
+        self.update_unmapped()
+    }
+
+    /// Updates the editor with the action induced by the current mode, ignoring the key mappings.
+    /// May also change the mode or reset the current key combo if appropriate.
+    fn update_unmapped(&mut self) -> Result<Vec<ModalEditorResult>, ModalEditorError> {
         // Get the current state summary.
         let state_summary = self.summarize();
         // Reset key clears the combo.
@@ -298,16 +927,28 @@ impl ModalEditor {
             self.curr_combo.reset();
             return Ok(vec![ModalEditorResult::ComboResetted]);
         }
-        // Try to handle the current key combo with the current mode.
+        // Try to handle the current key combo with the current mode. Modes that fall through pass
+        // the combos they do not handle to the mode below them.
         let curr_combo = self.curr_combo.clone();
-        let results = if let Some(curr_mode) = self.curr_mode_mut() {
-            let action = curr_mode.handle_combo(&curr_combo, &state_summary);
-            let results = self.update_with_action(action)?;
-            Ok(results)
+        let mut action = None;
+        for mode_name in self.active_modes.clone().into_iter().rev() {
+            if let Some(mode) = self.registered_modes.get_mut(mode_name) {
+                let mode_action = mode.handle_combo(&curr_combo, &state_summary);
+                let falls_through = mode_action.is_empty() && mode.fallthrough();
+                action = Some(mode_action);
+                if falls_through {
+                    continue;
+                }
+            }
+            break;
+        }
+        let results = if let Some(action) = action {
+            self.update_with_action(action)
         } else {
             Err(ModalEditorError::NoMode)
         };
-        if results.as_ref().map(|r| r.len() > 0).unwrap_or(false) {
+        // A failed action is not retried with the following keys.
+        if results.as_ref().map(|r| r.len() > 0).unwrap_or(true) {
             self.curr_combo.reset();
         }
         results
@@ -323,36 +964,237 @@ impl ModalEditor {
                 .unwrap_or(Document::new_empty()),
             curr_buffer_idx: self.historical_state.doc_map.curr_doc_id(),
             curr_mode: self.curr_mode().map(|mode| mode.id()).unwrap_or_default(),
+            mode_stack: self.mode_stack().to_vec(),
             curr_combo: self.curr_combo.clone(),
             display: EditorDisplay::default(),
             view: *self.get_view(),
+            settings: self.settings.clone(),
+            themes: self.themes.clone(),
         };
         if let Some(display) = self.curr_mode().map(|m| m.get_display(&summary)) {
             summary.display = display
         }
+        if let Some(preview_text) = &self.preview_text {
+            summary.display.mid_box_text = Some(preview_text.clone());
+        }
+        // Fill in the status bar unless the mode has its own.
+        let buf = summary.curr_doc.get_buf();
+        if summary.display.status_left.is_none() {
+            summary.display.status_left = Some(format!(
+                "[{}] {}{}{}{}",
+                summary
+                    .mode_stack
+                    .iter()
+                    .map(|mode| mode.to_uppercase())
+                    .join(" > "),
+                summary.curr_doc.source,
+                if summary.curr_doc.dirty { " [+]" } else { "" },
+                if summary.curr_doc.is_read_only() {
+                    " [RO]"
+                } else {
+                    ""
+                },
+                // Warn about the files that are not saved as UTF-8.
+                if summary.curr_doc.encoding == Encoding::Windows1252 {
+                    format!(" [{}]", summary.curr_doc.encoding)
+                } else {
+                    String::new()
+                }
+            ));
+        }
+        if summary.display.status_right.is_none() {
+            let primary_head = summary
+                .curr_doc
+                .selections
+                .get(&0)
+                .map(|sel| sel.0)
+                .unwrap_or(0);
+            let (col, row) = DocumentView::map_to_visual_position(primary_head, buf);
+            summary.display.status_right =
+                Some(format!("{}:{} / {}", row + 1, col + 1, buf.len_lines()));
+        }
         summary
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct EditorStateSummary {
     pub curr_doc: Document,
     pub curr_buffer_idx: usize,
     pub curr_mode: &'static str,
+    pub mode_stack: Vec<&'static str>,
     pub curr_combo: KeyCombo,
     pub display: EditorDisplay,
     pub view: DocumentView,
+    pub settings: EditorSettings,
+    /// The names of the themes that can be selected.
+    pub themes: Vec<String>,
+}
+
+impl EditorStateSummary {
+    /// Returns the text under the given selection of the current document. A selection without
+    /// a tail is zero-width and yields an empty string.
+    pub fn selection_text(&self, sel_id: usize) -> Option<String> {
+        let sel = self.curr_doc.selections.get(&sel_id)?;
+        if sel.1.is_none() {
+            return Some(String::new());
+        }
+        let buf = self.curr_doc.get_buf();
+        let (start, end) = selection_range(sel, buf);
+        buf.get_slice(start..end).map(|slice| slice.to_string())
+    }
+
+    /// Returns the text under the primary selection of the current document.
+    pub fn primary_selection_text(&self) -> Option<String> {
+        self.selection_text(PRIMARY_SEL_ID)
+    }
 }
 
 impl Default for EditorStateSummary {
     fn default() -> Self {
         EditorStateSummary {
             curr_mode: "none",
+            mode_stack: vec![],
             curr_doc: Document::new_empty(),
             curr_buffer_idx: 0,
             curr_combo: Default::default(),
             display: Default::default(),
             view: Default::default(),
+            settings: Default::default(),
+            themes: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macros::action_generator;
+
+    use crate::{
+        document::primitive_mods::BufMod,
+        editor::{
+            editor_mode::{CommandMode, InsertMode, NormalMode, SelectionMode},
+            editor_server::{EditorServerMsg, EditorServerReq},
+            test_utils::{test_editor, TestServer},
+        },
+        events::{Key, KeyMods},
+    };
+
+    use super::*;
+
+    #[test]
+    fn mode_registration() {
+        let mut editor = test_editor();
+        assert!(matches!(
+            editor.unregister_mode(NormalMode::id()),
+            Err(ModeRegistrationError::ModeIsActive)
+        ));
+        assert!(editor.replace_mode(Box::new(NormalMode::new())).is_some());
+        assert!(editor.replace_mode(Box::new(CommandMode::new())).is_none());
+        assert!(matches!(
+            editor
+                .unregister_mode(InsertMode::id())
+                .map(|mode| mode.id()),
+            Ok("insert")
+        ));
+        assert!(matches!(
+            editor.unregister_mode(InsertMode::id()),
+            Err(ModeRegistrationError::NotRegistered)
+        ));
+    }
+
+    #[test]
+    fn mode_reset() {
+        let mut editor = test_editor()
+            .with_mode(Box::new(SelectionMode::new()))
+            .with_mode(Box::new(CommandMode::new()));
+        for ch in "v:".chars() {
+            editor.receive_key(KeyEvt::Char(ch, KeyMods::NONE));
+            editor.update().unwrap();
+        }
+        assert_eq!(editor.mode_stack(), ["normal", "selection", "command"]);
+        for ch in "reset_modes".chars() {
+            editor.receive_key(KeyEvt::Char(ch, KeyMods::NONE));
+            editor.update().unwrap();
+        }
+        editor.receive_key(KeyEvt::Key(Key::Enter, KeyMods::NONE));
+        editor.update().unwrap();
+        assert_eq!(editor.mode_stack(), ["normal"]);
+    }
+
+    #[action_generator]
+    fn insert_args(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+        let tx = Transaction::new().with_mod(PrimitiveMod::Text(
+            state.curr_buffer_idx,
+            BufMod::InsText(0, args.join(" ")),
+        ));
+        Some([EditorCmd::RawTransaction(tx)].into_iter().collect())
+    }
+
+    #[action_generator]
+    fn load_args(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+        Some(
+            [
+                EditorCmd::StartLoading,
+                EditorCmd::RunAction(
+                    INSERT_ARGS,
+                    args.iter().map(|arg| arg.to_string()).collect(),
+                ),
+                EditorCmd::StopLoading,
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+
+    #[test]
+    fn loading_defers_commands() {
+        let mut editor = test_editor().with_mode(Box::new(CommandMode::new()));
+        editor.register_command(LOAD_ARGS);
+        let server = TestServer::run(editor);
+        server.send_keys(":load_args ab\r");
+        let mut results = vec![];
+        loop {
+            if let Ok(EditorServerMsg::EditorResult(result, state)) = server.conn().receive_msg() {
+                let is_stopped = matches!(result, ModalEditorResult::LoadingStopped);
+                results.push((result, state.curr_doc.get_buf().to_string()));
+                if is_stopped {
+                    break;
+                }
+            }
         }
+        // The indicator is broadcast before the deferred commands are run.
+        let loading_idx = results
+            .iter()
+            .position(|(result, _)| matches!(result, ModalEditorResult::LoadingStarted))
+            .unwrap();
+        assert_eq!(results[loading_idx].1, "");
+        assert!(matches!(
+            results[loading_idx + 1],
+            (ModalEditorResult::TxApplied(_), ref text) if text == "ab"
+        ));
+        assert_eq!(results.len(), loading_idx + 3);
+    }
+
+    #[test]
+    fn scrolling_with_line_numbers() {
+        let server = TestServer::run(test_editor());
+        server
+            .conn()
+            .send_req(EditorServerReq::UpdateViewEvent(20, 10));
+        server.send_keys(&format!("i{}", "a".repeat(16)));
+        // Resize to receive the view.
+        server
+            .conn()
+            .send_req(EditorServerReq::UpdateViewEvent(20, 11));
+        let view = loop {
+            if let Ok(EditorServerMsg::ViewUpdated(view, _)) = server.conn().receive_msg() {
+                if view.max_height == 11 {
+                    break view;
+                }
+            }
+        };
+        // The line numbers take up two columns, so the text scrolls by the margin earlier.
+        assert_eq!(view.x_offset, 3);
     }
 }
@@ -1,57 +1,282 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    document::{DocumentMap, Transaction},
+    document::{primitive_mods::PrimitiveMod, DocumentMap, DocumentMapEvent, Transaction},
     events::KeyCombo,
 };
 
-use super::TransactionGenerator;
+use super::{registers::RegisterBank, search::SearchState, TransactionGenerator};
 
-#[derive(Clone, Debug, Default)]
-pub struct EditorHistory {
-    prev: VecDeque<Transaction>,
-    next: VecDeque<Transaction>,
+/// Represents a state in the undo tree, which is reached from its parent with a transaction.
+#[derive(Clone, Debug)]
+struct HistoryNode {
+    /// The transaction that reaches the parent from this state.
+    inverse: Transaction,
+    /// The transaction that reaches this state from the parent, which is known once the state is
+    /// undone.
+    redo: Option<Transaction>,
+    parent: Option<usize>,
+    /// The children of the state, the most recently visited one first.
+    children: Vec<usize>,
+    /// When the state was last the current state, see [`EditorHistoryTree::clock`].
+    last_visit: usize,
+}
+
+/// The default number of states that are kept besides the root.
+pub const DEFAULT_MAX_STATES: usize = 1000;
+
+/// Keeps the undoable and redoable transactions as a tree, so that making a modification after
+/// undoing starts a new branch instead of discarding the redoable transactions.
+#[derive(Clone, Debug)]
+pub struct EditorHistoryTree {
+    /// The states keyed by their ids, which are never reused.
+    nodes: HashMap<usize, HistoryNode>,
+    /// The oldest state that can be reached.
+    root: usize,
+    /// The current state.
+    cursor: usize,
+    next_id: usize,
+    /// Increases with every move of the current state, which orders the visits.
+    clock: usize,
+    /// The maximum number of states besides the root, which also limits the number of
+    /// modifications that can be undone.
+    max_states: usize,
+}
+
+impl Default for EditorHistoryTree {
+    fn default() -> Self {
+        EditorHistoryTree {
+            nodes: HashMap::from([(
+                0,
+                HistoryNode {
+                    inverse: Transaction::new(),
+                    redo: None,
+                    parent: None,
+                    children: vec![],
+                    last_visit: 0,
+                },
+            )]),
+            root: 0,
+            cursor: 0,
+            next_id: 1,
+            clock: 0,
+            max_states: DEFAULT_MAX_STATES,
+        }
+    }
 }
 
-impl EditorHistory {
-    /// Undoes the state. Returns the applied transaction.
+impl EditorHistoryTree {
+    /// Makes the given state the current one.
+    fn visit(&mut self, node_idx: usize) {
+        self.clock += 1;
+        if let Some(node) = self.nodes.get_mut(&node_idx) {
+            node.last_visit = self.clock;
+        }
+        self.cursor = node_idx;
+    }
+
+    /// Undoes the state by moving to the parent. Returns the applied transaction.
     fn undo(&mut self, doc_map: &mut DocumentMap) -> Option<Transaction> {
-        let prev_tx = self.prev.pop_front();
-        prev_tx
-            .clone()
-            .and_then(|m| m.apply_tx(doc_map))
-            .map(|m_inv| {
-                self.next.push_front(m_inv);
-            });
-        prev_tx
+        let node = self.nodes.get_mut(&self.cursor)?;
+        let parent = node.parent?;
+        let inverse = node.inverse.clone();
+        node.redo = Some(inverse.apply_tx(doc_map)?);
+        self.visit(parent);
+        Some(inverse)
     }
 
-    /// Redoes the state. Returns the applied transaction.
+    /// Redoes the state by moving to the most recently visited child. Returns the applied
+    /// transaction.
     fn redo(&mut self, doc_map: &mut DocumentMap) -> Option<Transaction> {
-        let next_tx = self.next.pop_front();
-        next_tx
-            .clone()
-            .and_then(|m| m.apply_tx(doc_map))
-            .map(|m_inv| {
-                self.prev.push_front(m_inv);
-            });
-        next_tx
+        let child = *self.nodes[&self.cursor].children.first()?;
+        let node = self.nodes.get_mut(&child)?;
+        let redo = node.redo.clone()?;
+        node.inverse = redo.apply_tx(doc_map)?;
+        self.visit(child);
+        Some(redo)
+    }
+
+    /// Redoes the state by moving to the child that was visited before the most recent one, so
+    /// that repeating the undo and this cycles through the branches. Returns the applied
+    /// transaction.
+    fn redo_alt(&mut self, doc_map: &mut DocumentMap) -> Option<Transaction> {
+        let children = &mut self.nodes.get_mut(&self.cursor)?.children;
+        if children.len() < 2 {
+            return None;
+        }
+        children.rotate_left(1);
+        let redo_tx = self.redo(doc_map);
+        if redo_tx.is_none() {
+            if let Some(node) = self.nodes.get_mut(&self.cursor) {
+                node.children.rotate_right(1);
+            }
+        }
+        redo_tx
+    }
+
+    /// Returns the transaction that undoing would apply.
+    fn undo_tx(&self) -> Option<&Transaction> {
+        let node = &self.nodes[&self.cursor];
+        node.parent.map(|_| &node.inverse)
+    }
+
+    /// Returns the transaction that redoing would apply, following the branch that was visited
+    /// before the most recent one if `alt` is set.
+    fn redo_tx(&self, alt: bool) -> Option<&Transaction> {
+        let child = self.nodes[&self.cursor].children.get(usize::from(alt))?;
+        self.nodes[child].redo.as_ref()
+    }
+
+    /// Returns the states from the current one up to the root, excluding the root.
+    fn path_to_root(&self) -> impl Iterator<Item = &HistoryNode> + '_ {
+        std::iter::successors(Some(&self.nodes[&self.cursor]), |node| {
+            node.parent.map(|parent| &self.nodes[&parent])
+        })
+        .filter(|node| node.parent.is_some())
+    }
+
+    /// Returns true iff the given state is the current one or one of its ancestors.
+    fn is_ancestor(&self, node_idx: usize) -> bool {
+        std::iter::successors(Some(self.cursor), |idx| self.nodes[idx].parent)
+            .any(|idx| idx == node_idx)
+    }
+
+    /// Forgets all the undoable and redoable transactions.
+    pub fn clear(&mut self) {
+        *self = EditorHistoryTree {
+            max_states: self.max_states,
+            ..Default::default()
+        };
+    }
+
+    /// Limits the number of states that are kept besides the root, dropping the surplus ones.
+    pub fn set_max_states(&mut self, max_states: usize) {
+        self.max_states = max_states;
+        self.prune();
+    }
+
+    /// Drops states until there are at most `max_states` besides the root. The least recently
+    /// visited states at the tips of the other branches are dropped first, and then the oldest
+    /// states, such that the child of the root towards the current state becomes the new root.
+    fn prune(&mut self) {
+        while self.nodes.len() - 1 > self.max_states {
+            let branch_tip = self
+                .nodes
+                .iter()
+                .filter(|(idx, node)| **idx != self.cursor && node.children.is_empty())
+                .min_by_key(|(_, node)| node.last_visit)
+                .map(|(idx, _)| *idx);
+            if let Some(tip_idx) = branch_tip {
+                let parent = self.nodes.remove(&tip_idx).and_then(|node| node.parent);
+                if let Some(parent_node) = parent.and_then(|idx| self.nodes.get_mut(&idx)) {
+                    parent_node.children.retain(|idx| *idx != tip_idx);
+                }
+                continue;
+            }
+            // Only the path from the root to the current state is left.
+            let old_root = self.nodes.remove(&self.root).unwrap();
+            let new_root = old_root.children[0];
+            let root_node = self.nodes.get_mut(&new_root).unwrap();
+            root_node.parent = None;
+            root_node.inverse = Transaction::new();
+            root_node.redo = None;
+            self.root = new_root;
+        }
+    }
+
+    /// Records an already applied transaction through its inverse as a child of the current
+    /// state, which becomes the current state.
+    fn record(&mut self, m_inv: Transaction) {
+        let node_idx = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(
+            node_idx,
+            HistoryNode {
+                inverse: m_inv,
+                redo: None,
+                parent: Some(self.cursor),
+                children: vec![],
+                last_visit: 0,
+            },
+        );
+        if let Some(node) = self.nodes.get_mut(&self.cursor) {
+            node.children.insert(0, node_idx);
+        }
+        self.visit(node_idx);
+        self.prune();
     }
 
     /// Moves forward with the given transaction. Returns true if the application
     /// is successful.
     fn next(&mut self, m: &Transaction, doc_map: &mut DocumentMap) -> bool {
-        self.next.clear();
         m.apply_tx(doc_map)
-            .map(|m_inv| self.prev.push_front(m_inv))
+            .map(|m_inv| self.record(m_inv))
             .is_some()
     }
+
+    /// Returns a text drawing of the tree with a line per state, where the current state is
+    /// marked with `*`. The children are listed from the most recently visited one.
+    pub fn to_display_string(&self) -> String {
+        let mut lines = vec![];
+        // The nodes to draw along with the prefix of their line and that of their descendants.
+        let mut stack = vec![(self.root, String::new(), String::new())];
+        while let Some((node_idx, prefix, child_prefix)) = stack.pop() {
+            let marker = if node_idx == self.cursor { " *" } else { "" };
+            let label = if node_idx == self.root {
+                "root".to_string()
+            } else {
+                node_idx.to_string()
+            };
+            lines.push(format!("{}{}{}", prefix, label, marker));
+            let children = &self.nodes[&node_idx].children;
+            for (child_pos, child_idx) in children.iter().enumerate().rev() {
+                let is_last = child_pos == children.len() - 1;
+                let (branch, indent) = if is_last {
+                    ("└─ ", "   ")
+                } else {
+                    ("├─ ", "│  ")
+                };
+                stack.push((
+                    *child_idx,
+                    format!("{}{}", child_prefix, branch),
+                    format!("{}{}", child_prefix, indent),
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Denotes that a script could not be applied as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    /// The index of the transaction that failed.
+    pub failed_at: usize,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "script failed at transaction {}",
+            self.failed_at
+        ))
+    }
 }
 
+impl std::error::Error for ScriptError {}
+
+/// Denotes a point in the undo history that a state can be restored to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointId(usize);
+
 #[derive(Clone, Debug)]
 pub struct HistoricalEditorState {
     pub doc_map: DocumentMap,
-    pub history: EditorHistory,
+    pub history: EditorHistoryTree,
+    /// The yanked texts, which are not part of the history.
+    pub registers: RegisterBank,
+    /// The last confirmed search, which is not part of the history either.
+    pub search: Option<SearchState>,
 }
 
 impl From<DocumentMap> for HistoricalEditorState {
@@ -59,21 +284,145 @@ impl From<DocumentMap> for HistoricalEditorState {
         HistoricalEditorState {
             doc_map: curr_state,
             history: Default::default(),
+            registers: Default::default(),
+            search: None,
         }
     }
 }
 
 impl HistoricalEditorState {
-    /// Moves the state one point back in the past.
+    /// Reconstructs the state by replaying the logged transactions on the initial document map,
+    /// i.e., the one the event log was enabled on. The undo history is not reconstructed, and the
+    /// event log is enabled and contains the replayed transactions.
+    pub fn from_log(initial: DocumentMap, log: Vec<Transaction>) -> Self {
+        let mut state = HistoricalEditorState::from(initial.with_event_log());
+        for tx in &log {
+            // The logged transactions succeeded once, so they only fail if the log is not
+            // recorded from the initial document map.
+            state.apply_without_history(tx);
+        }
+        state
+    }
+
+    /// Returns the applied transactions in the event log, which are empty if it is disabled.
+    pub fn transaction_log(&self) -> Vec<Transaction> {
+        self.doc_map
+            .events_since(0)
+            .iter()
+            .filter_map(|event| match event {
+                DocumentMapEvent::TransactionApplied(tx) => Some(tx.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Moves the state one point back in the past, unless it modifies a read-only document.
     /// Returns the applied transaction.
     pub fn undo(&mut self) -> Option<Transaction> {
-        self.history.undo(&mut self.doc_map)
+        if self
+            .history
+            .undo_tx()
+            .is_some_and(|tx| self.modifies_read_only_doc(tx))
+        {
+            return None;
+        }
+        let prev_tx = self.history.undo(&mut self.doc_map)?;
+        self.log_applied(&prev_tx);
+        Some(prev_tx)
     }
 
-    /// Moves the state one point forward in the future.
+    /// Moves the state one point forward in the future, following the most recent branch.
     /// Returns the applied transaction.
     pub fn redo(&mut self) -> Option<Transaction> {
-        self.history.redo(&mut self.doc_map)
+        if self
+            .history
+            .redo_tx(false)
+            .is_some_and(|tx| self.modifies_read_only_doc(tx))
+        {
+            return None;
+        }
+        let next_tx = self.history.redo(&mut self.doc_map)?;
+        self.log_applied(&next_tx);
+        Some(next_tx)
+    }
+
+    /// Moves the state one point forward in the future, following the branch that was visited
+    /// before the most recent one. Returns the applied transaction.
+    pub fn redo_alt(&mut self) -> Option<Transaction> {
+        if self
+            .history
+            .redo_tx(true)
+            .is_some_and(|tx| self.modifies_read_only_doc(tx))
+        {
+            return None;
+        }
+        let next_tx = self.history.redo_alt(&mut self.doc_map)?;
+        self.log_applied(&next_tx);
+        Some(next_tx)
+    }
+
+    /// Clears the undo history without modifying the documents.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Returns a copy of the state without any history, e.g., to preview modifications. The
+    /// modifications of the fork do not affect this state until the fork is merged.
+    pub fn fork(&self) -> HistoricalEditorState {
+        HistoricalEditorState {
+            doc_map: self.doc_map.clone(),
+            // The fork keeps all its modifications, which are merged as a single one.
+            history: EditorHistoryTree {
+                max_states: usize::MAX,
+                ..Default::default()
+            },
+            registers: self.registers.clone(),
+            search: self.search.clone(),
+        }
+    }
+
+    /// Takes over the documents of the fork and records its modifications as a single history
+    /// entry. Assumes that this state was not modified since the fork.
+    pub fn merge_fork(&mut self, fork: HistoricalEditorState) {
+        // The inverses are ordered from the most recent one, so applying them in order undoes
+        // the modifications of the fork.
+        let fork_inv = Transaction::new().with_mods(
+            fork.history
+                .path_to_root()
+                .flat_map(|node| node.inverse.primitive_mods.clone()),
+        );
+        // The fork logs its events on a copy of the event log as well.
+        self.doc_map = fork.doc_map;
+        if !fork_inv.primitive_mods.is_empty() {
+            self.history.record(fork_inv);
+        }
+    }
+
+    /// Returns the current point in the undo history. Unlike a fork, the state is not copied, which
+    /// suits the tentative modifications that are mostly discarded, e.g., live previews.
+    pub fn checkpoint(&self) -> CheckpointId {
+        CheckpointId(self.history.cursor)
+    }
+
+    /// Undoes the modifications recorded since the given checkpoint, which remain redoable.
+    /// Returns false iff the history no longer reaches back to the checkpoint, e.g., if it is
+    /// cleared or undone past the checkpoint in the meantime.
+    pub fn restore_checkpoint(&mut self, id: CheckpointId) -> bool {
+        if !self.history.nodes.contains_key(&id.0) || !self.history.is_ancestor(id.0) {
+            return false;
+        }
+        while self.history.cursor != id.0 {
+            if self.undo().is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Records the given applied transaction in the event log.
+    fn log_applied(&mut self, tx: &Transaction) {
+        self.doc_map
+            .log_event(DocumentMapEvent::TransactionApplied(tx.clone()));
     }
 
     /// Applies the transaction outputted by the given generator.
@@ -86,6 +435,89 @@ impl HistoricalEditorState {
         tx_gen.1(trigger, &self.doc_map).filter(|tx| self.modify_with_tx(&tx))
     }
 
+    /// Applies the given transaction without recording it in the history, e.g., while loading a
+    /// document. Returns true iff all the modifications are applied successfully. As no inverses
+    /// are computed, the modifications before a failing one are not rolled back.
+    pub fn apply_without_history(&mut self, tx: &Transaction) -> bool {
+        if self.modifies_read_only_doc(tx) {
+            return false;
+        }
+        let applied = tx.primitive_mods.iter().all(|pm| match pm {
+            PrimitiveMod::Sel(doc_id, _, _) | PrimitiveMod::Text(doc_id, _) => self
+                .doc_map
+                .get_mut(doc_id)
+                .is_some_and(|doc| doc.apply_mod(pm)),
+            PrimitiveMod::DocMap(_) => pm.apply(&mut self.doc_map).is_some(),
+        });
+        if applied {
+            self.clamp_selections(tx);
+            self.log_applied(tx);
+        }
+        applied
+    }
+
+    /// Applies the given transactions in order as a single history entry. If a transaction fails,
+    /// the preceding ones are rolled back and the state is left unchanged.
+    pub fn apply_script(&mut self, script: Vec<Transaction>) -> Result<(), ScriptError> {
+        let event_count = self.doc_map.event_count();
+        let mut inv_txs = vec![];
+        for (tx_idx, tx) in script.iter().enumerate() {
+            let inv_tx = if self.modifies_read_only_doc(tx) {
+                None
+            } else {
+                tx.apply_tx(&mut self.doc_map)
+            };
+            if let Some(inv_tx) = inv_tx {
+                inv_txs.push(inv_tx);
+            } else {
+                for inv_tx in inv_txs.iter().rev() {
+                    inv_tx.apply_tx(&mut self.doc_map);
+                }
+                self.doc_map.truncate_events(event_count);
+                return Err(ScriptError { failed_at: tx_idx });
+            }
+        }
+        // Undoing the script applies the inverses in the reverse order.
+        let script_inv = Transaction::new().with_mods(
+            inv_txs
+                .into_iter()
+                .rev()
+                .flat_map(|inv_tx| inv_tx.primitive_mods),
+        );
+        if !script_inv.primitive_mods.is_empty() {
+            self.history.record(script_inv);
+        }
+        for tx in &script {
+            self.log_applied(tx);
+        }
+        Ok(())
+    }
+
+    /// Applies a transaction that modifies multiple documents independently, document by
+    /// document. Returns the inverse transaction iff all the documents are modified successfully.
+    fn apply_split_tx(&mut self, doc_txs: HashMap<usize, Transaction>) -> Option<Transaction> {
+        let mut inv_txs = vec![];
+        for (doc_id, doc_tx) in doc_txs {
+            let inv_tx = self
+                .doc_map
+                .get_mut(&doc_id)
+                .and_then(|doc| doc_tx.apply_tx_to_doc(doc));
+            if let Some(inv_tx) = inv_tx {
+                inv_txs.push(inv_tx);
+            } else {
+                // Roll back the documents that were already modified.
+                for inv_tx in inv_txs {
+                    inv_tx.apply_tx(&mut self.doc_map);
+                }
+                return None;
+            }
+        }
+        Some(
+            Transaction::new()
+                .with_mods(inv_txs.into_iter().flat_map(|inv_tx| inv_tx.primitive_mods)),
+        )
+    }
+
     /// Applies the given transaction.
     /// Returns true iff the transaction is applied successfully
     pub fn modify_with_tx(&mut self, tx: &Transaction) -> bool {
@@ -93,7 +525,445 @@ impl HistoricalEditorState {
         if tx.primitive_mods.is_empty() {
             return true;
         }
-        // Apply the modification to the appropriate history.
-        self.history.next(tx, &mut self.doc_map)
+        if self.modifies_read_only_doc(tx) {
+            return false;
+        }
+        // Modifications of different documents do not depend on each other unless the document
+        // map itself is modified as well.
+        let modifies_doc_map = tx
+            .primitive_mods
+            .iter()
+            .any(|pm| matches!(pm, PrimitiveMod::DocMap(_)));
+        let doc_txs = tx.split_by_document();
+        let event_count = self.doc_map.event_count();
+        let applied = if doc_txs.len() > 1 && !modifies_doc_map {
+            self.apply_split_tx(doc_txs)
+                .map(|tx_inv| self.history.record(tx_inv))
+                .is_some()
+        } else {
+            // A failing transaction is rolled back without being recorded.
+            self.history.next(tx, &mut self.doc_map)
+        };
+        if applied {
+            self.clamp_selections(tx);
+            self.log_applied(tx);
+        } else {
+            // The documents loaded by the rolled back modifications are not in the map anymore.
+            self.doc_map.truncate_events(event_count);
+        }
+        applied
+    }
+
+    /// Returns true iff the given transaction modifies the text of a read-only document.
+    fn modifies_read_only_doc(&self, tx: &Transaction) -> bool {
+        tx.primitive_mods.iter().any(|pm| match pm {
+            PrimitiveMod::Text(doc_id, _) => self
+                .doc_map
+                .get(doc_id)
+                .map(|doc| doc.is_read_only())
+                .unwrap_or(false),
+            _ => false,
+        })
+    }
+
+    /// Keeps the selections of the documents modified by the given transaction, as well as those
+    /// of the current document, within their buffers, which the buffer modifications do not take
+    /// into account.
+    fn clamp_selections(&mut self, tx: &Transaction) {
+        let doc_ids = tx
+            .split_by_document()
+            .into_keys()
+            .chain([self.doc_map.curr_doc_id()])
+            .collect::<HashSet<_>>();
+        for doc_id in doc_ids {
+            if let Some(doc) = self.doc_map.get_mut(&doc_id) {
+                let buf = doc.get_buf().clone();
+                doc.selections
+                    .values_mut()
+                    .for_each(|sel| *sel = sel.clamp_to_buf(&buf));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::document::{
+        primitive_mods::{BufMod, DocMapMod, SelectionMod},
+        Document,
+    };
+
+    use super::*;
+
+    fn text_tx(buf_mod: BufMod) -> Transaction {
+        Transaction::new().with_mod(PrimitiveMod::Text(0, buf_mod))
+    }
+
+    fn curr_text(state: &HistoricalEditorState) -> String {
+        state.doc_map.get_curr_doc().unwrap().get_buf().to_string()
+    }
+
+    #[test]
+    fn script_is_undone_at_once() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        let script = vec![
+            text_tx(BufMod::InsText(0, "ab".to_string())),
+            text_tx(BufMod::InsText(2, "cd".to_string())),
+        ];
+        assert_eq!(state.apply_script(script), Ok(()));
+        assert_eq!(curr_text(&state), "abcd");
+        state.undo();
+        assert_eq!(curr_text(&state), "");
+    }
+
+    #[test]
+    fn multi_document_tx() {
+        let mut doc_map = DocumentMap::default();
+        let other_doc_id = doc_map.insert(Document::new_empty());
+        let mut state: HistoricalEditorState = doc_map.into();
+        let tx = text_tx(BufMod::InsText(0, "ab".to_string())).with_mod(PrimitiveMod::Text(
+            other_doc_id,
+            BufMod::InsText(0, "cd".to_string()),
+        ));
+        assert!(state.modify_with_tx(&tx));
+        let other_text = |state: &HistoricalEditorState| {
+            state
+                .doc_map
+                .get(&other_doc_id)
+                .unwrap()
+                .get_buf()
+                .to_string()
+        };
+        assert_eq!(curr_text(&state), "ab");
+        assert_eq!(other_text(&state), "cd");
+        state.undo();
+        assert_eq!(curr_text(&state), "");
+        assert_eq!(other_text(&state), "");
+        // A failing modification leaves all the documents unchanged.
+        let tx = text_tx(BufMod::InsText(0, "ab".to_string())).with_mod(PrimitiveMod::Text(
+            other_doc_id,
+            BufMod::InsText(10, "cd".to_string()),
+        ));
+        assert!(!state.modify_with_tx(&tx));
+        assert_eq!(curr_text(&state), "");
+        // The selections of all the modified documents are kept within their buffers.
+        let tx = text_tx(BufMod::InsText(0, "ab".to_string())).with_mods([
+            PrimitiveMod::Text(other_doc_id, BufMod::InsText(0, "cd".to_string())),
+            PrimitiveMod::Sel(other_doc_id, 0, SelectionMod::SetHead(2)),
+            PrimitiveMod::Text(other_doc_id, BufMod::DelRange(0, 2)),
+        ]);
+        assert!(state.modify_with_tx(&tx));
+        assert_eq!(
+            state.doc_map.get(&other_doc_id).unwrap().selections[&0].0,
+            0
+        );
+    }
+
+    #[test]
+    fn failing_script_rolls_back() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        let script = vec![
+            text_tx(BufMod::InsText(0, "ab".to_string())),
+            text_tx(BufMod::InsText(10, "cd".to_string())),
+        ];
+        assert_eq!(
+            state.apply_script(script),
+            Err(ScriptError { failed_at: 1 })
+        );
+        assert_eq!(curr_text(&state), "");
+        assert!(state.undo().is_none());
+    }
+
+    #[test]
+    fn tx_without_history() {
+        let mut state = HistoricalEditorState::from(DocumentMap::default().with_event_log());
+        assert!(state.apply_without_history(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        assert_eq!(curr_text(&state), "ab");
+        assert!(state.undo().is_none());
+        // The modifications before a failing one stay applied, but are not logged.
+        let tx = text_tx(BufMod::InsText(0, "c".to_string()))
+            .with_mod(PrimitiveMod::Text(0, BufMod::InsText(10, "d".to_string())));
+        assert!(!state.apply_without_history(&tx));
+        assert_eq!(curr_text(&state), "cab");
+        assert_eq!(state.transaction_log().len(), 1);
+        // Read-only documents are rejected as a whole.
+        state
+            .doc_map
+            .get_curr_doc_mut()
+            .unwrap()
+            .set_read_only(true);
+        assert!(!state.apply_without_history(&text_tx(BufMod::InsText(0, "e".to_string()))));
+        assert_eq!(curr_text(&state), "cab");
+    }
+
+    #[test]
+    fn cleared_history() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(2, "cd".to_string()))));
+        state.undo();
+        state.clear_history();
+        assert!(state.undo().is_none());
+        assert!(state.redo().is_none());
+        assert_eq!(curr_text(&state), "ab");
+    }
+
+    #[test]
+    fn failed_tx_keeps_redo() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        state.undo();
+        assert!(!state.modify_with_tx(&text_tx(BufMod::DelRange(0, 5))));
+        assert!(state.redo().is_some());
+        assert_eq!(curr_text(&state), "ab");
+    }
+
+    #[test]
+    fn undo_tree_branches() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        state.undo();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "cd".to_string()))));
+        assert_eq!(state.history.to_display_string(), "root\n├─ 2 *\n└─ 1");
+        // Redo follows the most recent branch, and the alternative redo the other one.
+        state.undo();
+        assert!(state.redo().is_some());
+        assert_eq!(curr_text(&state), "cd");
+        state.undo();
+        assert!(state.redo_alt().is_some());
+        assert_eq!(curr_text(&state), "ab");
+        assert_eq!(state.history.to_display_string(), "root\n├─ 1 *\n└─ 2");
+        assert!(state.redo_alt().is_none());
+    }
+
+    #[test]
+    fn limited_undo_depth() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        state.history.set_max_states(2);
+        let checkpoint = state.checkpoint();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "a".to_string()))));
+        state.undo();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "b".to_string()))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(1, "c".to_string()))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(2, "d".to_string()))));
+        // The other branch is dropped first, and then the oldest state.
+        assert_eq!(state.history.to_display_string(), "root\n└─ 3\n   └─ 4 *");
+        assert!(!state.restore_checkpoint(checkpoint));
+        state.undo();
+        state.undo();
+        assert!(state.undo().is_none());
+        assert_eq!(curr_text(&state), "b");
+        // Lowering the limit drops the states beyond it.
+        assert!(state.redo().is_some());
+        state.history.set_max_states(0);
+        assert!(state.undo().is_none());
+        assert_eq!(curr_text(&state), "bc");
+    }
+
+    #[test]
+    fn limited_undo_branches() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        state.history.set_max_states(3);
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "a".to_string()))));
+        // Each cycle starts a new branch from the same state, which stays reachable.
+        for ch in ["b", "c", "d", "e", "f"] {
+            assert!(state.modify_with_tx(&text_tx(BufMod::InsText(1, ch.to_string()))));
+            state.undo();
+        }
+        assert_eq!(state.history.nodes.len(), 4);
+        assert_eq!(
+            state.history.to_display_string(),
+            "root
+└─ 1 *
+   ├─ 6
+   └─ 5"
+        );
+        assert!(state.redo_alt().is_some());
+        assert_eq!(curr_text(&state), "ae");
+        state.undo();
+        state.undo();
+        assert_eq!(curr_text(&state), "");
+    }
+
+    #[test]
+    fn selections_after_deleting_all() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "abc".to_string()))));
+        assert!(
+            state.modify_with_tx(&Transaction::new().with_mod(PrimitiveMod::Sel(
+                0,
+                0,
+                SelectionMod::SetHead(3)
+            )))
+        );
+        assert!(state.modify_with_tx(&text_tx(BufMod::DelRange(0, 3))));
+        let head = state.doc_map.get_curr_doc().unwrap().selections[&0].0;
+        assert_eq!(head, 0);
+        // Moving and inserting at the head succeed on the empty buffer.
+        let buf = state.doc_map.get_curr_doc().unwrap().get_buf().clone();
+        assert_eq!(crate::cursor::movement::line_end(head, &buf), Some(0));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(head, "d".to_string()))));
+        assert_eq!(curr_text(&state), "d");
+    }
+
+    #[test]
+    fn merged_fork() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        let mut fork = state.fork();
+        assert!(fork.undo().is_none());
+        assert!(fork.modify_with_tx(&text_tx(BufMod::InsText(2, "cd".to_string()))));
+        assert!(fork.modify_with_tx(&text_tx(BufMod::DelRange(0, 1))));
+        assert_eq!(curr_text(&fork), "bcd");
+        assert_eq!(curr_text(&state), "ab");
+        state.merge_fork(fork);
+        assert_eq!(curr_text(&state), "bcd");
+        // The modifications of the fork are undone at once.
+        state.undo();
+        assert_eq!(curr_text(&state), "ab");
+        state.redo();
+        assert_eq!(curr_text(&state), "bcd");
+    }
+
+    #[test]
+    fn event_log() {
+        let mut state = HistoricalEditorState::from(DocumentMap::default().with_event_log());
+        let load_tx = Transaction::new().with_mod(PrimitiveMod::DocMap(DocMapMod::CreateDoc(
+            Document::new_empty(),
+        )));
+        assert!(state.apply_without_history(&load_tx));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        state.undo();
+        // Failed transactions are not logged.
+        assert!(!state.modify_with_tx(&text_tx(BufMod::DelRange(5, 10))));
+        assert!(!state.modify_with_tx(
+            &load_tx
+                .clone()
+                .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(10)))
+        ));
+        // The loaded documents are logged before the transactions that load them.
+        let events = state.doc_map.events_since(0);
+        assert!(matches!(
+            events,
+            [
+                DocumentMapEvent::DocumentLoaded(0),
+                DocumentMapEvent::DocumentLoaded(1),
+                DocumentMapEvent::TransactionApplied(_),
+                DocumentMapEvent::TransactionApplied(_),
+                DocumentMapEvent::TransactionApplied(_),
+            ]
+        ));
+        assert_eq!(state.doc_map.events_since(4).len(), 1);
+        assert!(state.doc_map.events_since(6).is_empty());
+        // The events of a fork are logged once it is merged.
+        let mut fork = state.fork();
+        assert!(fork.modify_with_tx(&text_tx(BufMod::InsText(0, "c".to_string()))));
+        assert_eq!(state.doc_map.events_since(5).len(), 0);
+        state.merge_fork(fork);
+        assert_eq!(state.doc_map.events_since(5).len(), 1);
+    }
+
+    #[test]
+    fn replayed_log() {
+        let mut state = HistoricalEditorState::from(DocumentMap::default().with_event_log());
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "abc".to_string()))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::DelRange(0, 1))));
+        state.undo();
+        assert!(
+            state.modify_with_tx(&Transaction::new().with_mod(PrimitiveMod::DocMap(
+                DocMapMod::CreateDoc(Document::new_empty())
+            )))
+        );
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(3, "d".to_string()))));
+        assert_eq!(state.transaction_log().len(), 5);
+        let replayed =
+            HistoricalEditorState::from_log(DocumentMap::default(), state.transaction_log());
+        assert_eq!(curr_text(&replayed), curr_text(&state));
+        assert_eq!(
+            replayed.doc_map.get(&0).unwrap().get_buf().to_string(),
+            "abcd"
+        );
+        assert_eq!(replayed.doc_map.curr_doc_id(), state.doc_map.curr_doc_id());
+        assert_eq!(replayed.transaction_log().len(), 5);
+    }
+
+    #[test]
+    fn read_only_doc() {
+        let path = std::env::temp_dir().join(format!("yaz-read-only-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        let doc = Document::new_from_file(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert!(doc.is_read_only());
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(
+            &Transaction::new()
+                .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateDoc(doc)))
+                .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(1)))
+        ));
+        assert!(!state.modify_with_tx(
+            &Transaction::new().with_mod(PrimitiveMod::Text(1, BufMod::DelRange(0, 1)))
+        ));
+        assert_eq!(curr_text(&state), "abc");
+        // The selections can still be moved.
+        assert!(
+            state.modify_with_tx(&Transaction::new().with_mod(PrimitiveMod::Sel(
+                1,
+                0,
+                SelectionMod::SetHead(2)
+            )))
+        );
+    }
+
+    #[test]
+    fn locked_undo_redo() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "ab".to_string()))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(2, "c".to_string()))));
+        state.undo();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(2, "d".to_string()))));
+        state.undo();
+        let lock = |state: &mut HistoricalEditorState, flag| {
+            state
+                .doc_map
+                .get_curr_doc_mut()
+                .unwrap()
+                .set_read_only(flag)
+        };
+        lock(&mut state, true);
+        assert!(state.undo().is_none());
+        assert!(state.redo().is_none());
+        assert!(state.redo_alt().is_none());
+        assert_eq!(curr_text(&state), "ab");
+        lock(&mut state, false);
+        assert!(state.redo_alt().is_some());
+        assert_eq!(curr_text(&state), "abc");
+    }
+
+    #[test]
+    fn restored_checkpoint() {
+        let mut state: HistoricalEditorState = DocumentMap::default().into();
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(0, "abc".to_string()))));
+        let checkpoint = state.checkpoint();
+        assert!(state.modify_with_tx(&text_tx(BufMod::DelRange(0, 1))));
+        assert!(state.modify_with_tx(&text_tx(BufMod::InsText(2, "de".to_string()))));
+        assert!(
+            state.modify_with_tx(&Transaction::new().with_mod(PrimitiveMod::Sel(
+                0,
+                0,
+                SelectionMod::SetHead(3)
+            )))
+        );
+        assert_eq!(curr_text(&state), "bcde");
+        assert!(state.restore_checkpoint(checkpoint));
+        assert_eq!(curr_text(&state), "abc");
+        assert_eq!(state.doc_map.get_curr_doc().unwrap().selections[&0].0, 0);
+        // Restoring again is a no-op, and the history before the checkpoint is kept.
+        assert!(state.restore_checkpoint(checkpoint));
+        assert!(state.undo().is_some());
+        assert_eq!(curr_text(&state), "");
+        assert!(!state.restore_checkpoint(checkpoint));
     }
 }
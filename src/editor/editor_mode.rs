@@ -1,38 +1,80 @@
+use itertools::Itertools;
+
 use crate::document::{DocumentMap, Transaction};
-use crate::editor::{EditorStateSummary, ModalEditorError};
+use crate::editor::EditorStateSummary;
 use crate::events::{KeyCombo, KeyPatternClause};
-use crate::events::{KeyMatcher, KeyPattern};
+use crate::events::{KeyMatcher, KeyMods, KeyPattern};
 
+mod block_selection_mode;
 mod command_mode;
+mod fuzzy_finder_mode;
 mod goto_mode;
 mod insert_mode;
 mod normal_mode;
+mod search_mode;
 mod selection_mode;
+mod split_mode;
+mod visual_line_mode;
 
+pub use block_selection_mode::BlockSelectionMode;
 pub use command_mode::CommandMode;
+pub use fuzzy_finder_mode::FuzzyFinderMode;
 pub use goto_mode::GotoMode;
 pub use insert_mode::InsertMode;
 pub use normal_mode::NormalMode;
+pub use search_mode::SearchMode;
 pub use selection_mode::SelectionMode;
+pub use split_mode::SplitMode;
+pub use visual_line_mode::VisualLineMode;
+
+use super::{ActionGenerator, EditorAction, EditorCmd, EditorDisplay};
 
-use super::{EditorAction, EditorCmd, EditorDisplay};
+/// Returns an instance of each mode of the editor.
+pub fn all_modes() -> Vec<Box<dyn EditorMode>> {
+    vec![
+        Box::new(InsertMode::new()),
+        Box::new(NormalMode::new()),
+        Box::new(GotoMode::new()),
+        Box::new(CommandMode::new()),
+        Box::new(SelectionMode::new()),
+        Box::new(VisualLineMode::new()),
+        Box::new(BlockSelectionMode::new()),
+        Box::new(FuzzyFinderMode::new()),
+        Box::new(SearchMode::new()),
+        Box::new(SplitMode::new()),
+    ]
+}
 
 pub trait EditorMode: Send {
     fn id(&self) -> &'static str;
     fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction;
     fn get_display(&self, state: &EditorStateSummary) -> EditorDisplay;
+    /// Returns true iff the combos that this mode does not handle (i.e., for which it returns an
+    /// empty action) should be handled by the mode below it on the mode stack.
+    fn fallthrough(&self) -> bool {
+        false
+    }
+    /// Called when the mode is pushed onto the mode stack.
+    fn on_enter(&mut self, _state: &EditorStateSummary) {}
+    /// Called when the mode is popped from the mode stack.
+    fn on_exit(&mut self, _state: &EditorStateSummary) {}
+    /// Adds a command to the mode. Only the command mode accepts commands.
+    fn register_command(&mut self, _cmd_gen: ActionGenerator) {}
 }
 
 /// Maps key patterns to editor actions.
 #[derive(Clone, Debug)]
 pub struct TriggerHandler {
     triggers: Vec<(KeyPattern, EditorAction)>,
+    /// Whether the combos that no pattern can match are left to the mode below.
+    fallthrough: bool,
 }
 
 impl Default for TriggerHandler {
     fn default() -> Self {
         TriggerHandler {
             triggers: Default::default(),
+            fallthrough: false,
         }
     }
 }
@@ -55,11 +97,86 @@ impl TriggerHandler {
         self
     }
 
-    /// Returns the editor command that matches with the given key input combination.
-    pub fn handle(&self, kc: &KeyCombo) -> Option<EditorAction> {
+    /// Associates a sequence of commands with the given key pattern, both as it is and preceded by
+    /// a count, e.g., `3j`. The commands read the count from the combo.
+    pub fn with_count<A, P, G>(self, clauses: P, action: A) -> Self
+    where
+        A: IntoIterator<Item = EditorCmd>,
+        P: IntoIterator<Item = G>,
+        G: IntoIterator<Item = KeyMatcher>,
+    {
+        let clauses = clauses
+            .into_iter()
+            .map(|clause| clause.into_iter().collect_vec())
+            .collect_vec();
+        let action = action.into_iter().collect_vec();
+        let counted_clauses = std::iter::once(vec![KeyMatcher::Number(KeyMods::NONE)])
+            .chain(clauses.clone())
+            .collect_vec();
+        self.with(clauses, action.clone())
+            .with(counted_clauses, action)
+    }
+
+    /// Leaves the combos that no pattern can match to the mode below instead of resetting them,
+    /// see [`EditorMode::fallthrough`].
+    pub fn with_fallthrough(mut self) -> Self {
+        self.fallthrough = true;
+        self
+    }
+
+    /// Returns true iff the combos that no pattern can match are left to the mode below.
+    pub fn falls_through(&self) -> bool {
+        self.fallthrough
+    }
+
+    /// Appends the triggers of the other handler. On conflicts, the triggers of this handler take
+    /// precedence.
+    pub fn extend(mut self, other: TriggerHandler) -> Self {
+        self.triggers.extend(other.triggers);
+        self
+    }
+
+    /// Removes the triggers with the given key pattern.
+    pub fn with_exclusion<P, G>(mut self, clauses: P) -> Self
+    where
+        P: IntoIterator<Item = G>,
+        G: IntoIterator<Item = KeyMatcher>,
+    {
+        let excluded_pattern: KeyPattern = clauses
+            .into_iter()
+            .map(|clause| clause.into_iter().collect())
+            .collect();
         self.triggers
+            .retain(|(pattern, _)| *pattern != excluded_pattern);
+        self
+    }
+
+    /// Removes the commands that do not satisfy the given predicate from all the triggers.
+    pub fn with_retained_cmds(mut self, pred: impl Fn(&EditorCmd) -> bool) -> Self {
+        for (_, action) in &mut self.triggers {
+            *action = action.iter().filter(|cmd| pred(cmd)).cloned().collect();
+        }
+        self
+    }
+
+    /// Returns the editor command that matches with the given key input combination. Returns
+    /// none while the combo can still be completed into a pattern. If no pattern can match the
+    /// combo anymore, returns a combo reset, or an empty action if the handler falls through.
+    pub fn handle(&self, kc: &KeyCombo) -> Option<EditorAction> {
+        let candidates = self
+            .triggers
             .iter()
-            .find(|(pattern, _)| pattern.matches(kc.clone()))
+            .filter(|(pattern, _)| pattern.is_prefix_of(kc))
+            .collect_vec();
+        if candidates.is_empty() && self.fallthrough {
+            return Some(EditorAction::default());
+        }
+        if candidates.is_empty() {
+            return Some([EditorCmd::ResetCombo].into_iter().collect());
+        }
+        candidates
+            .into_iter()
+            .find(|(pattern, _)| pattern.matches_ref(kc))
             .map(|(_, resp)| resp.clone())
     }
 }
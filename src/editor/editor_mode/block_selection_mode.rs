@@ -0,0 +1,215 @@
+use macros::tx_generator;
+use ropey::Rope;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    cursor::{GraphemeIterable, TextSelection},
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod, SelectionMod},
+        DocumentMap, DocumentView, Transaction,
+    },
+    editor::{EditorAction, EditorStateSummary},
+    events::{Key, KeyCombo, KeyEvt, KeyMatcher, KeyMods},
+};
+
+use super::{
+    normal_mode::*, EditorCmd, EditorMode, InsertMode, NormalMode, SelectionMode, TriggerHandler,
+};
+
+/// Returns the first and the last char of the graphemes of the given line that cover the visual
+/// columns in `min_col..=max_col`, or none if the line is not that wide.
+fn visual_column_range(
+    line_idx: usize,
+    min_col: usize,
+    max_col: usize,
+    buf: &Rope,
+) -> Option<(usize, usize)> {
+    let line_start = buf.try_line_to_char(line_idx).ok()?;
+    let mut first_last: Option<(usize, usize)> = None;
+    let (mut char_idx, mut col) = (line_start, 0);
+    for g in buf.graphemes(line_start) {
+        if g.starts_with(['\n', '\r']) || col > max_col {
+            break;
+        }
+        // Zero-width graphemes still occupy the column that they are displayed at.
+        let last_col = col + std::cmp::max(g.width(), 1) - 1;
+        if last_col >= min_col {
+            first_last = Some((first_last.map_or(char_idx, |(first, _)| first), char_idx));
+        }
+        char_idx += g.chars().count();
+        col += g.width();
+    }
+    first_last
+}
+
+/// Replaces the selections with a rectangular block that spans the tail and the head of the
+/// primary selection as its corners. The block consists of a selection on each line, which are
+/// oriented like the primary selection, and the primary selection stays on the line of the head.
+#[tx_generator]
+pub fn add_block_selection(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let doc_id = doc_map.curr_doc_id();
+    let doc = doc_map.get_curr_doc()?;
+    let buf = doc.get_buf();
+    let primary_sel = doc.primary_selection()?;
+    let (head_col, head_line) = DocumentView::map_to_visual_position(primary_sel.0, buf);
+    let (tail_col, tail_line) =
+        DocumentView::map_to_visual_position(primary_sel.1.unwrap_or(primary_sel.0), buf);
+    let (min_col, max_col) = (head_col.min(tail_col), head_col.max(tail_col));
+    let mut tx = Transaction::new().with_mods(
+        doc.selections
+            .keys()
+            .filter(|sel_id| **sel_id != 0)
+            .map(|sel_id| PrimitiveMod::DocMap(DocMapMod::DeleteSel(doc_id, *sel_id))),
+    );
+    let mut next_sel_id = 1;
+    for line_idx in head_line.min(tail_line)..=head_line.max(tail_line) {
+        let Some((first, last)) = visual_column_range(line_idx, min_col, max_col, buf) else {
+            // The primary selection stays on its line even if the line is too short.
+            if line_idx == head_line {
+                tx.append_mod(PrimitiveMod::Sel(doc_id, 0, SelectionMod::SetTail(None)));
+            }
+            continue;
+        };
+        let (head, tail) = if head_col >= tail_col {
+            (last, first)
+        } else {
+            (first, last)
+        };
+        if line_idx == head_line {
+            tx.append_mod(PrimitiveMod::Sel(doc_id, 0, SelectionMod::SetHead(head)));
+            tx.append_mod(PrimitiveMod::Sel(
+                doc_id,
+                0,
+                SelectionMod::SetTail(Some(tail)),
+            ));
+        } else {
+            tx.append_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                doc_id,
+                next_sel_id,
+                TextSelection(head, Some(tail)),
+            )));
+            next_sel_id += 1;
+        }
+    }
+    Some(tx)
+}
+
+/// Selects a rectangular block of text between the position that the mode was entered at and the
+/// head of the primary selection. The normal mode commands act on the selection of each line.
+pub struct BlockSelectionMode {
+    trigger_handler: TriggerHandler,
+    /// The fixed corner of the block.
+    anchor: usize,
+}
+
+impl BlockSelectionMode {
+    pub fn new() -> Self {
+        // Use the normal mode bindings without collapsing the selections.
+        let normal_handler = NormalMode::trigger_handler()
+            .with_exclusion([[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]])
+            .with_retained_cmds(|cmd| match cmd {
+                EditorCmd::Transaction(tx_gen) => *tx_gen != COLLAPSE_SELS,
+                EditorCmd::PushMode(mode_id) => {
+                    *mode_id != SelectionMode::id() && *mode_id != Self::id()
+                }
+                _ => true,
+            });
+        let trigger_handler = TriggerHandler::default()
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
+                [
+                    EditorCmd::Transaction(RESET_SELS),
+                    EditorCmd::Transaction(COLLAPSE_SELS),
+                    EditorCmd::PopMode,
+                ],
+            )
+            .extend(normal_handler);
+        BlockSelectionMode {
+            trigger_handler,
+            anchor: 0,
+        }
+    }
+
+    pub fn id() -> &'static str {
+        "blockselection"
+    }
+}
+
+impl EditorMode for BlockSelectionMode {
+    fn id(&self) -> &'static str {
+        Self::id()
+    }
+
+    fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
+        let Some(mut action) = self.trigger_handler.handle(kc) else {
+            return EditorAction::default();
+        };
+        if action.iter().any(|cmd| matches!(cmd, EditorCmd::PopMode)) {
+            return action;
+        }
+        let deletes = action.iter().any(|cmd| {
+            matches!(cmd, EditorCmd::Transaction(tx_gen)
+                if *tx_gen == DELETE_SELS || *tx_gen == DELETE_SELS_INDEPENDENTLY)
+        });
+        let inserts = action
+            .iter()
+            .any(|cmd| matches!(cmd, EditorCmd::PushMode(mode_id) if *mode_id == InsertMode::id()));
+        // The block is gone after an edit, so leave the mode as well. The insertions start at
+        // the heads, which the deletions collapse the selections onto.
+        if deletes || inserts {
+            if !deletes {
+                action.prepend(EditorCmd::Transaction(COLLAPSE_SELS));
+            }
+            action.prepend(EditorCmd::PopMode);
+            return action;
+        }
+        // Rebuild the block from the anchor and the moved head after the motions.
+        if action
+            .iter()
+            .any(|cmd| matches!(cmd, EditorCmd::Transaction(_)))
+        {
+            action.append(EditorCmd::RawTransaction(Transaction::new().with_mod(
+                PrimitiveMod::Sel(
+                    state.curr_buffer_idx,
+                    0,
+                    SelectionMod::SetTail(Some(self.anchor)),
+                ),
+            )));
+            action.append(EditorCmd::Transaction(ADD_BLOCK_SELECTION));
+        }
+        action
+    }
+
+    fn on_enter(&mut self, state: &EditorStateSummary) {
+        self.anchor = state.curr_doc.selections.get(&0).map_or(0, |sel| sel.0);
+    }
+
+    fn get_display(&self, _state: &EditorStateSummary) -> super::EditorDisplay {
+        Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::{
+        editor_mode::GotoMode,
+        test_utils::{test_editor, TestServer},
+    };
+
+    use super::*;
+
+    #[test]
+    fn block_selection() {
+        let server = TestServer::run(
+            test_editor()
+                .with_mode(Box::new(GotoMode::new()))
+                .with_mode(Box::new(BlockSelectionMode::new())),
+        );
+        server.send_keys("iabcd\nef\nijkl\x1bggl\x16jjld");
+        // The short line is cut at its end.
+        assert_eq!(server.text(0), Some("ad\ne\nil".to_string()));
+        // Inserting types at the head of each line of the block.
+        server.send_keys("gg\x16jiX\x1b");
+        assert_eq!(server.text(0), Some("Xad\nXe\nil".to_string()));
+    }
+}
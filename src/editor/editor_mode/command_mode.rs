@@ -1,25 +1,59 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
-use macros::action_generator;
+use macros::{action_generator, tx_generator};
 
 use crate::{
+    cursor::selection_range,
+    document::{
+        primitive_mods::{BufMod, DocMapMod, PrimitiveMod, SelectionMod},
+        Document, DocumentMap, Transaction,
+    },
     editor::{
-        ActionGenerator, EditorAction, EditorCmd, EditorDisplay, EditorStateSummary,
-        ModalEditorError,
+        ActionGenerator, EditorAction, EditorCmd, EditorDisplay, EditorOption, EditorStateSummary,
     },
-    events::{Key, KeyCombo, KeyEvt, KeyMods},
+    events::{Key, KeyCombo, KeyEvt, KeyMods, KeyParseError, KeyPattern},
 };
 
-use super::EditorMode;
+use super::{EditorMode, FuzzyFinderMode, NormalMode};
 
 #[action_generator]
 fn quit(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
     Some([EditorCmd::Quit].into_iter().collect())
 }
 
+/// The message shown when saving a read-only document to its own file.
+const READ_ONLY_SAVE_MSG: &str = "the document is read-only, use `:save!` to save it anyway";
+
+/// The size in bytes above which the files are opened behind a loading indicator.
+const LARGE_FILE_SIZE: u64 = 1 << 20;
+
+/// Returns true iff saving with the given arguments overwrites the file of a read-only document.
+fn saves_read_only_doc(args: &[&str], state: &EditorStateSummary) -> bool {
+    args.is_empty() && state.curr_doc.is_read_only()
+}
+
 #[action_generator]
-fn save(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+fn save(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+    if saves_read_only_doc(args, state) {
+        return Some(
+            [EditorCmd::ThrowErr(READ_ONLY_SAVE_MSG.to_string())]
+                .into_iter()
+                .collect(),
+        );
+    }
+    force_save(args, state)
+}
+
+/// Saves the document even if it is read-only, i.e., `:save!`.
+pub const FORCE_SAVE: ActionGenerator = ActionGenerator("save!", force_save);
+
+fn force_save(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
     Some(
         [EditorCmd::SaveCurrDocument(
             args.get(0).map(|path| path.to_string()),
@@ -29,18 +63,555 @@ fn save(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
     )
 }
 
+#[action_generator]
+fn set(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    let option_name = *args.first()?;
+    let option = match option_name {
+        "scrolloff" => EditorOption::ScrollMarginY(args.get(1)?.parse().ok()?),
+        "sidescrolloff" => EditorOption::ScrollMarginX(args.get(1)?.parse().ok()?),
+        "number" => EditorOption::LineNumbers(true),
+        "nonumber" => EditorOption::LineNumbers(false),
+        "relativenumber" => EditorOption::RelativeLineNumbers(true),
+        "norelativenumber" => EditorOption::RelativeLineNumbers(false),
+        "readonly" => EditorOption::ReadOnly(true),
+        "noreadonly" => EditorOption::ReadOnly(false),
+        "shelltimeout" => {
+            EditorOption::ShellTimeout(Duration::from_secs(args.get(1)?.parse().ok()?))
+        }
+        "undolevels" => EditorOption::UndoLevels(args.get(1)?.parse().ok()?),
+        "encoding" => match args.get(1)?.parse() {
+            Ok(encoding) => EditorOption::Encoding(encoding),
+            Err(_) => {
+                return Some(
+                    [EditorCmd::ThrowErr(format!(
+                        "invalid encoding `{}`",
+                        args[1]
+                    ))]
+                    .into_iter()
+                    .collect(),
+                )
+            }
+        },
+        _ => {
+            return Some(
+                [EditorCmd::ThrowErr(format!(
+                    "invalid option `{}`",
+                    option_name
+                ))]
+                .into_iter()
+                .collect(),
+            )
+        }
+    };
+    Some([EditorCmd::SetOption(option)].into_iter().collect())
+}
+
+/// Selects the theme that the syntax is highlighted with, e.g., `:theme Solarized (dark)`, or
+/// lists the themes without an argument. The theme name is the rest of the command line.
+#[action_generator]
+fn theme(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+    let Some(theme) = args.first() else {
+        return Some(
+            [EditorCmd::Notify(state.themes.join(", "))]
+                .into_iter()
+                .collect(),
+        );
+    };
+    let theme = theme.trim_end();
+    if !state.themes.iter().any(|name| name == theme) {
+        return Some(
+            [EditorCmd::ThrowErr(format!("unknown theme `{}`", theme))]
+                .into_iter()
+                .collect(),
+        );
+    }
+    Some(
+        [EditorCmd::SetOption(EditorOption::Theme(theme.to_string()))]
+            .into_iter()
+            .collect(),
+    )
+}
+
+#[tx_generator]
+fn duplicate_curr_doc(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let new_doc_id = doc_map.get_unused_id();
+    Some(
+        Transaction::new()
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::DuplicateDoc(
+                doc_map.curr_doc_id(),
+            )))
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(new_doc_id))),
+    )
+}
+
+#[action_generator]
+fn duplicate(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some(
+        [EditorCmd::Transaction(DUPLICATE_CURR_DOC)]
+            .into_iter()
+            .collect(),
+    )
+}
+
+/// Splits the argument of `:substitute /old/new/g` after its leading slash at the slashes, where
+/// `\/` stands for a slash itself. Returns none if there are no slashes.
+fn split_at_slashes(arg: &str) -> Option<Vec<String>> {
+    let mut parts = vec![String::new()];
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'/') => {
+                chars.next();
+                parts.last_mut().unwrap().push('/');
+            }
+            '/' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    (parts.len() > 1).then_some(parts)
+}
+
+/// Returns the transaction that replaces the non-overlapping occurrences of `old` in the given
+/// document with `new`, or only the first one unless `global` is set. Returns none if there are
+/// no occurrences.
+fn replace_occurrences_tx(
+    doc_id: usize,
+    doc: &Document,
+    old: &str,
+    new: &str,
+    global: bool,
+) -> Option<Transaction> {
+    let mut occurrences = vec![];
+    let mut from = 0;
+    while let Some((start, end)) = doc.search_forward(old, from) {
+        occurrences.push((start, end));
+        if !global {
+            break;
+        }
+        from = end;
+    }
+    if occurrences.is_empty() {
+        return None;
+    }
+    // Replace from the last occurrence so that the indices of the preceding ones stay valid.
+    let mut tx = Transaction::new().with_mods(occurrences.into_iter().rev().map(|(start, end)| {
+        PrimitiveMod::Text(doc_id, BufMod::ReplaceRange(start, end, new.to_string()))
+    }));
+    // Keep the selections on the same text.
+    let map = |char_idx: usize| tx.map_char_idx(&doc_id, &char_idx).unwrap_or(char_idx);
+    let sel_mods = doc
+        .selections
+        .iter()
+        .flat_map(|(sel_id, sel)| {
+            [
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetHead(map(sel.0))),
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetTail(sel.1.map(map))),
+            ]
+        })
+        .collect_vec();
+    tx.append_mods(sel_mods);
+    Some(tx)
+}
+
+/// Replaces the occurrences of a text in the current document, e.g., `:substitute /old/new/g` or
+/// `:%s/old/new/g`. Without the `g` flag, only the first occurrence is replaced. Without the
+/// leading slash, all the matches of a regex are replaced instead, e.g., `:substitute \s+$ `,
+/// where the replacement is everything after the space that ends the regex. With `--preview` in
+/// front, the result is only displayed. The argument is the rest of the command line as it is
+/// typed, so the texts may contain any spacing.
+#[action_generator]
+fn substitute(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+    let arg_str = *args.first()?;
+    let (preview, arg_str) = match arg_str.strip_prefix("--preview") {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+            (true, rest.trim_start())
+        }
+        _ => (false, arg_str),
+    };
+    if let Some(substitution) = arg_str.strip_prefix('/') {
+        let parts = split_at_slashes(substitution).unwrap_or_default();
+        let (old, new, global) = match parts.as_slice() {
+            [old, new] => (old, new, false),
+            [old, new, flags] if matches!(flags.trim_end(), "" | "g") => {
+                (old, new, flags.trim_end() == "g")
+            }
+            _ => {
+                return Some(
+                    [EditorCmd::ThrowErr(format!(
+                        "invalid substitution `{}`, expected `/old/new/g`",
+                        arg_str
+                    ))]
+                    .into_iter()
+                    .collect(),
+                )
+            }
+        };
+        // An empty text stands for the text of the primary selection, e.g., `:%s//new/g`.
+        let old = match old.as_str() {
+            "" => state.primary_selection_text().unwrap_or_default(),
+            _ => old.to_string(),
+        };
+        if old.is_empty() {
+            return Some(
+                [EditorCmd::ThrowErr("nothing to substitute".to_string())]
+                    .into_iter()
+                    .collect(),
+            );
+        }
+        let Some(tx) =
+            replace_occurrences_tx(state.curr_buffer_idx, &state.curr_doc, &old, new, global)
+        else {
+            return Some(
+                [EditorCmd::ThrowErr(format!("`{}` not found", old))]
+                    .into_iter()
+                    .collect(),
+            );
+        };
+        let cmd = if preview {
+            EditorCmd::PreviewTransaction(tx)
+        } else {
+            EditorCmd::RawTransaction(tx)
+        };
+        return Some([cmd].into_iter().collect());
+    }
+    let (pattern, replacement) = arg_str.split_once(' ').unwrap_or((arg_str, ""));
+    if pattern.is_empty() {
+        return None;
+    }
+    let regex = match regex::Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            return Some(
+                [EditorCmd::ThrowErr(format!("invalid pattern: {}", err))]
+                    .into_iter()
+                    .collect(),
+            )
+        }
+    };
+    if !regex.is_match(&state.curr_doc.get_buf().to_string()) {
+        return Some(
+            [EditorCmd::ThrowErr(format!("`{}` not found", pattern))]
+                .into_iter()
+                .collect(),
+        );
+    }
+    // The selections cannot be mapped through the replacement, so move them to the start.
+    let doc_id = state.curr_buffer_idx;
+    let tx = Transaction::new()
+        .with_mods(state.curr_doc.selections.keys().flat_map(|sel_id| {
+            [
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetTail(None)),
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetHead(0)),
+            ]
+        }))
+        .with_mod(PrimitiveMod::Text(
+            doc_id,
+            BufMod::ReplaceRegex(pattern.to_string(), replacement.to_string()),
+        ));
+    if preview {
+        return Some([EditorCmd::PreviewTransaction(tx)].into_iter().collect());
+    }
+    Some([EditorCmd::RawTransaction(tx)].into_iter().collect())
+}
+
+/// Runs the given command with `sh`, feeding it the given input. Returns the stdout and the stderr
+/// of the command, or an error message if it could not be run in time.
+fn run_shell_cmd(
+    cmd: &str,
+    input: Option<&str>,
+    timeout: Duration,
+) -> Result<(String, String), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("could not run `{}`: {}", cmd, err))?;
+    // Feed and drain the pipes on separate threads so that the command never blocks on them.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.unwrap_or_default().to_string();
+    std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take().unwrap();
+    let stdout_handle = std::thread::spawn(move || {
+        let mut out = String::new();
+        stdout.read_to_string(&mut out).map(|_| out)
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr_handle = std::thread::spawn(move || {
+        let mut out = String::new();
+        stderr.read_to_string(&mut out).map(|_| out)
+    });
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("`{}` timed out", cmd));
+            }
+            Err(err) => return Err(format!("could not run `{}`: {}", cmd, err)),
+        }
+    }
+    let stdout = stdout_handle
+        .join()
+        .unwrap()
+        .map_err(|_| format!("`{}` produced invalid output", cmd))?;
+    let stderr = stderr_handle.join().unwrap().unwrap_or_default();
+    Ok((stdout, stderr))
+}
+
+/// Inserts the output of a shell command at the selection heads, e.g., `:shell date` or `:!date`.
+/// The command is the rest of the command line as it is typed.
+#[action_generator]
+fn shell(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+    let cmd = *args.first()?;
+    let (output, err_output) = match run_shell_cmd(cmd, None, state.settings.shell_timeout) {
+        Ok(outputs) => outputs,
+        Err(err) => return Some([EditorCmd::ThrowErr(err)].into_iter().collect()),
+    };
+    let doc_id = state.curr_buffer_idx;
+    let output_num_chars = output.chars().count();
+    let mut tx = Transaction::new();
+    state
+        .curr_doc
+        .selections
+        .iter()
+        .sorted_by_key(|(_, sel)| sel.0)
+        .for_each(|(sel_id, sel)| {
+            let insert_index = tx.map_char_idx(&doc_id, &sel.0).unwrap_or(0);
+            tx.append_mods([
+                PrimitiveMod::Text(doc_id, BufMod::InsText(insert_index, output.clone())),
+                PrimitiveMod::Sel(
+                    doc_id,
+                    *sel_id,
+                    SelectionMod::SetHead(insert_index + output_num_chars),
+                ),
+            ]);
+        });
+    let mut action: EditorAction = [EditorCmd::RawTransaction(tx)].into_iter().collect();
+    if !err_output.trim().is_empty() {
+        action.append(EditorCmd::Notify(err_output.trim().to_string()));
+    }
+    Some(action)
+}
+
+/// Replaces the text of each selection with the output of a shell command that reads it from its
+/// stdin, e.g., `:filter sort`. The output is inserted at the selections without a tail. The
+/// command is the rest of the command line as it is typed.
+#[action_generator]
+fn filter(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+    let cmd = *args.first()?;
+    let doc_id = state.curr_buffer_idx;
+    let buf = state.curr_doc.get_buf();
+    let mut tx = Transaction::new();
+    let mut err_outputs = vec![];
+    // The number of characters added by the replacements so far.
+    let mut shift: isize = 0;
+    let mut prev_end = 0;
+    for (sel_id, sel) in state
+        .curr_doc
+        .selections
+        .iter()
+        .sorted_by_key(|(_, sel)| selection_range(sel, buf).0)
+    {
+        let (start, end) = match sel.1 {
+            Some(_) => selection_range(sel, buf),
+            None => (sel.0, sel.0),
+        };
+        // Overlapping selections cannot be replaced independently.
+        if start < prev_end {
+            continue;
+        }
+        prev_end = end;
+        let input = state.selection_text(*sel_id)?;
+        let (output, err_output) =
+            match run_shell_cmd(cmd, Some(&input), state.settings.shell_timeout) {
+                Ok(outputs) => outputs,
+                Err(err) => return Some([EditorCmd::ThrowErr(err)].into_iter().collect()),
+            };
+        if !err_output.trim().is_empty() {
+            err_outputs.push(err_output.trim().to_string());
+        }
+        let output_num_chars = output.chars().count();
+        let new_start = (start as isize + shift) as usize;
+        let new_end = new_start + output_num_chars.saturating_sub(1);
+        // Keep the direction of the selection.
+        let (new_head, new_tail) = if sel.0 < sel.1.unwrap_or(sel.0) {
+            (new_start, new_end)
+        } else {
+            (new_end, new_start)
+        };
+        tx.append_mods([
+            PrimitiveMod::Text(
+                doc_id,
+                BufMod::ReplaceRange(new_start, new_start + end - start, output),
+            ),
+            PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetHead(new_head)),
+            PrimitiveMod::Sel(
+                doc_id,
+                *sel_id,
+                SelectionMod::SetTail((output_num_chars > 0).then_some(new_tail)),
+            ),
+        ]);
+        shift += output_num_chars as isize - (end - start) as isize;
+    }
+    let mut action: EditorAction = [EditorCmd::RawTransaction(tx)].into_iter().collect();
+    if !err_outputs.is_empty() {
+        action.append(EditorCmd::Notify(err_outputs.join("\n")));
+    }
+    Some(action)
+}
+
+/// Leaves all the active modes for the normal mode, e.g., from a selection mode below the command
+/// mode.
+#[action_generator]
+fn reset_modes(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some([EditorCmd::SetMode(NormalMode::id())].into_iter().collect())
+}
+
+#[action_generator]
+fn find(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some(
+        [EditorCmd::PushMode(FuzzyFinderMode::id())]
+            .into_iter()
+            .collect(),
+    )
+}
+
+/// Lists the occurrences of a pattern in all the open documents in a new document, e.g.,
+/// `:grep TODO`. Pressing enter on a result in the normal mode jumps to it.
+#[action_generator]
+fn grep(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    let pattern = args.join(" ");
+    if pattern.is_empty() {
+        return None;
+    }
+    Some([EditorCmd::Grep(pattern)].into_iter().collect())
+}
+
+/// Opens the file with the given path in a new document, e.g., `:open src/main.rs`.
+#[action_generator]
+fn open(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    let path = args.join(" ");
+    if path.is_empty() {
+        return None;
+    }
+    let is_large = std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() > LARGE_FILE_SIZE);
+    let open_file = EditorCmd::OpenFile(path);
+    Some(if is_large {
+        [EditorCmd::StartLoading, open_file, EditorCmd::StopLoading]
+            .into_iter()
+            .collect()
+    } else {
+        [open_file].into_iter().collect()
+    })
+}
+
+/// Saves the open files and their cursors, e.g., `:mksession session.toml`.
+#[action_generator]
+fn mksession(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some(
+        [EditorCmd::SaveSession(args.first()?.to_string())]
+            .into_iter()
+            .collect(),
+    )
+}
+
+/// Opens the files of a saved session, e.g., `:source session.toml`.
+#[action_generator]
+fn source(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some(
+        [EditorCmd::LoadSession(args.first()?.to_string())]
+            .into_iter()
+            .collect(),
+    )
+}
+
+/// Remaps a key pattern to a sequence of keys in the current mode, e.g., `:map <C-s> :save<Enter>`.
+/// The keys may be given as multiple arguments, e.g., `:map Q :quit <Enter>`.
+#[action_generator(map)]
+fn map_keys(args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    let (lhs, rhs) = args.split_first()?;
+    if rhs.is_empty() {
+        return None;
+    }
+    let mapping = lhs.parse::<KeyPattern>().and_then(|lhs| {
+        let rhs = rhs
+            .iter()
+            .map(|keys| keys.parse::<KeyCombo>())
+            .collect::<Result<Vec<_>, KeyParseError>>()?;
+        Ok(EditorCmd::AddMapping(
+            lhs,
+            rhs.into_iter().flatten().collect(),
+        ))
+    });
+    let cmd = mapping.unwrap_or_else(|err| EditorCmd::ThrowErr(err.to_string()));
+    Some([cmd].into_iter().collect())
+}
+
+/// Forgets the undo history, e.g., after the initial edits of a template.
+#[action_generator(clearhistory)]
+fn clear_history(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some([EditorCmd::ClearHistory].into_iter().collect())
+}
+
+/// Displays the undo tree, e.g., to find the branches that `<A-U>` redoes.
+#[action_generator]
+fn undotree(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+    Some([EditorCmd::ShowUndoTree].into_iter().collect())
+}
+
 pub struct CommandMode {
     curr_cmd: String,
     cmd_generators: HashMap<&'static str, ActionGenerator>,
+    /// The arguments of the successfully executed commands, keyed by the command names, with the
+    /// most recent ones at the end.
+    arg_history: HashMap<&'static str, Vec<String>>,
+    /// The index of the recalled arguments in the history of the current command, counting from
+    /// the most recent ones, while cycling through them.
+    recalled_arg_idx: Option<usize>,
 }
 
-const ALL_COMMANDS: &[ActionGenerator] = &[QUIT, SAVE];
+/// The commands that run external programs. They are run behind a loading indicator, as they may
+/// take a while.
+const SLOW_COMMANDS: &[ActionGenerator] = &[SHELL, FILTER];
+
+/// The commands that take the rest of the command line as a single argument, keeping its spacing,
+/// instead of its whitespace-separated words.
+const RAW_ARG_COMMANDS: &[ActionGenerator] = &[SUBSTITUTE, SHELL, FILTER, THEME];
+
+const ALL_COMMANDS: &[ActionGenerator] = &[
+    QUIT,
+    SAVE,
+    FORCE_SAVE,
+    SET,
+    THEME,
+    DUPLICATE,
+    SUBSTITUTE,
+    SHELL,
+    FILTER,
+    RESET_MODES,
+    FIND,
+    GREP,
+    OPEN,
+    MKSESSION,
+    SOURCE,
+    MAP_KEYS,
+    CLEAR_HISTORY,
+    UNDOTREE,
+];
 
 impl CommandMode {
     pub fn new() -> Self {
         let mut cmd_mode = CommandMode {
             curr_cmd: String::new(),
             cmd_generators: Default::default(),
+            arg_history: Default::default(),
+            recalled_arg_idx: None,
         };
         for cmd in ALL_COMMANDS {
             cmd_mode.register_command(*cmd);
@@ -54,10 +625,6 @@ impl CommandMode {
         "command"
     }
 
-    pub fn register_command(&mut self, cmd_gen: ActionGenerator) {
-        self.cmd_generators.insert(cmd_gen.name(), cmd_gen);
-    }
-
     pub fn similar_cmd_generators(&self, limit: usize) -> Vec<&ActionGenerator> {
         use rust_fuzzy_search::fuzzy_search_best_n;
         let all_cmds = self.cmd_generators.keys().cloned().collect_vec();
@@ -68,6 +635,44 @@ impl CommandMode {
             .map(|cmd_key| self.cmd_generators.get(cmd_key).unwrap())
             .collect_vec()
     }
+
+    /// Adds the given arguments of the command to its history as the most recent ones.
+    fn remember_args(&mut self, cmd_name: &'static str, args: &[&str]) {
+        if args.is_empty() {
+            return;
+        }
+        let history = self.arg_history.entry(cmd_name).or_default();
+        let args = args.join(" ");
+        history.retain(|prev_args| *prev_args != args);
+        history.push(args);
+    }
+
+    /// Replaces the arguments of the current command with the next ones from its history. Only the
+    /// commands without arguments, or with recalled ones, are completed. Returns true iff the
+    /// arguments are replaced.
+    fn recall_args(&mut self) -> bool {
+        let mut parts = self.curr_cmd.split_whitespace();
+        let cmd_name = parts.next().unwrap_or_default();
+        let has_args = parts.next().is_some();
+        if self.recalled_arg_idx.is_none()
+            && (has_args || !self.curr_cmd.ends_with(char::is_whitespace))
+        {
+            return false;
+        }
+        let Some((cmd_name, history)) = self
+            .arg_history
+            .get_key_value(cmd_name)
+            .filter(|(_, history)| !history.is_empty())
+        else {
+            return false;
+        };
+        let idx = self
+            .recalled_arg_idx
+            .map_or(0, |idx| (idx + 1) % history.len());
+        self.curr_cmd = format!("{} {}", cmd_name, history[history.len() - 1 - idx]);
+        self.recalled_arg_idx = Some(idx);
+        true
+    }
 }
 
 impl EditorMode for CommandMode {
@@ -78,7 +683,6 @@ impl EditorMode for CommandMode {
     fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
         // Exit with discard
         if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Esc, KeyMods::NONE)]) {
-            self.curr_cmd = String::new();
             return [EditorCmd::PopMode].into_iter().collect();
         }
         // Exit with accept
@@ -89,11 +693,50 @@ impl EditorMode for CommandMode {
             // Extract the current command
             let mut full_cmd_str = String::new();
             std::mem::swap(&mut full_cmd_str, &mut self.curr_cmd);
-            let mut args = full_cmd_str.trim().split_whitespace();
-            let target_cmd = args.next().unwrap_or_default();
-            let args = args.collect_vec();
+            // `:!cmd` is a shorthand for `:shell cmd`.
+            if let Some(shell_cmd) = full_cmd_str.trim_start().strip_prefix('!') {
+                full_cmd_str = format!("{} {}", SHELL.name(), shell_cmd);
+            }
+            // `:%s/old/new/g` is a shorthand for `:substitute /old/new/g`.
+            if let Some(substitution) = full_cmd_str.trim_start().strip_prefix("%s/") {
+                full_cmd_str = format!("{} /{}", SUBSTITUTE.name(), substitution);
+            }
+            let full_cmd_str = full_cmd_str.trim_start();
+            let (target_cmd, raw_args) = full_cmd_str
+                .split_once(char::is_whitespace)
+                .unwrap_or((full_cmd_str, ""));
+            let raw_args = raw_args.trim_start();
             return if let Some(cmd_gen) = self.cmd_generators.get(&target_cmd) {
-                let mut generated_action = cmd_gen.1(&args, state).unwrap_or(
+                let args = if !RAW_ARG_COMMANDS.contains(cmd_gen) {
+                    raw_args.split_whitespace().collect_vec()
+                } else if raw_args.trim().is_empty() {
+                    vec![]
+                } else {
+                    vec![raw_args]
+                };
+                // The arguments of the slow commands are remembered before they are run.
+                if SLOW_COMMANDS.contains(cmd_gen) {
+                    let cmd_gen = *cmd_gen;
+                    self.remember_args(cmd_gen.name(), &args);
+                    return [
+                        EditorCmd::PopMode,
+                        EditorCmd::ResetCombo,
+                        EditorCmd::StartLoading,
+                        EditorCmd::RunAction(
+                            cmd_gen,
+                            args.iter().map(|arg| arg.to_string()).collect(),
+                        ),
+                        EditorCmd::StopLoading,
+                    ]
+                    .into_iter()
+                    .collect();
+                }
+                let generated_action = cmd_gen.1(&args, state);
+                // Remember the arguments of the successful commands, most recent last.
+                if generated_action.is_some() {
+                    self.remember_args(cmd_gen.name(), &args);
+                }
+                let mut generated_action = generated_action.unwrap_or(
                     [EditorCmd::ThrowErr("couldn't apply action".to_string())]
                         .into_iter()
                         .collect(),
@@ -110,27 +753,40 @@ impl EditorMode for CommandMode {
                 .collect()
             };
         }
-        // Autocomplete on tab.
+        // Autocomplete on tab, cycling through the previous arguments once the command is typed.
         if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Tab, KeyMods::NONE)]) {
+            if self.recall_args() {
+                return [EditorCmd::ResetCombo].into_iter().collect();
+            }
             if let Some(most_similar_cmd_gen) = self.similar_cmd_generators(1).first() {
                 self.curr_cmd = most_similar_cmd_gen.name().to_string();
             }
         }
+        self.recalled_arg_idx = None;
         // Delete the command on backspace.
         if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Backspace, KeyMods::NONE)]) {
             self.curr_cmd = self.curr_cmd[0..self.curr_cmd.len().saturating_sub(1)].to_string();
         }
-        // Mutate the command
-        let additional_txt = kc
-            .extract_text()
-            .replace("\n", "")
-            .replace("\t", " ")
-            .replace(":", "");
-        self.curr_cmd.push_str(&additional_txt);
+        // Mutate the command, where a leading `:` is the one that entered the mode.
+        let additional_txt = kc.extract_text().replace("\n", "").replace("\t", " ");
+        let additional_txt = match additional_txt.strip_prefix(':') {
+            Some(txt) if self.curr_cmd.is_empty() => txt,
+            _ => &additional_txt,
+        };
+        self.curr_cmd.push_str(additional_txt);
         return [EditorCmd::ResetCombo].into_iter().collect();
     }
 
-    fn get_display(&self, _state: &EditorStateSummary) -> EditorDisplay {
+    fn on_enter(&mut self, _state: &EditorStateSummary) {
+        self.curr_cmd = String::new();
+        self.recalled_arg_idx = None;
+    }
+
+    fn register_command(&mut self, cmd_gen: ActionGenerator) {
+        self.cmd_generators.insert(cmd_gen.name(), cmd_gen);
+    }
+
+    fn get_display(&self, state: &EditorStateSummary) -> EditorDisplay {
         let mut similar_cmds_str = self
             .similar_cmd_generators(5)
             .iter()
@@ -139,10 +795,218 @@ impl EditorMode for CommandMode {
         if similar_cmds_str.is_empty() {
             similar_cmds_str = "no similar command".into();
         }
+        let mut btm_bar_text = format!(":{}", self.curr_cmd);
+        // Show the pending key combo, if any, next to the command.
+        if !state.curr_combo.is_empty() {
+            btm_bar_text.push_str(&format!("  {}", state.curr_combo.to_display_string()));
+        }
+        // Ask for confirmation before overwriting the file of a read-only document.
+        let mut args = self.curr_cmd.split_whitespace();
+        if args.next() == Some(SAVE.name()) && saves_read_only_doc(&args.collect_vec(), state) {
+            similar_cmds_str = READ_ONLY_SAVE_MSG.to_string();
+        }
         EditorDisplay {
-            btm_bar_text: Some(format!(":{}", self.curr_cmd.clone())),
+            btm_bar_text: Some(btm_bar_text),
             mid_box_text: Some(similar_cmds_str),
             ..Default::default()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use macros::action_generator;
+
+    use crate::{
+        document::{
+            primitive_mods::{BufMod, PrimitiveMod},
+            DocumentMap, Transaction,
+        },
+        editor::{
+            editor_mode::{InsertMode, NormalMode, SelectionMode},
+            editor_server::{EditorServerMsg, EditorServerReq},
+            test_utils::{test_editor, TestServer},
+            EditorAction, EditorCmd, EditorStateSummary, ModalEditor, ModalEditorResult,
+        },
+    };
+
+    use super::*;
+
+    fn command_editor() -> ModalEditor {
+        test_editor().with_mode(Box::new(CommandMode::new()))
+    }
+
+    #[test]
+    fn key_mapping() {
+        let server = TestServer::run(command_editor());
+        server.send_keys(":map Q ixy<Esc>\nQQ");
+        assert_eq!(server.text(0), Some("xyxy".to_string()));
+    }
+
+    #[test]
+    fn mapping_to_command() {
+        let path = std::env::temp_dir().join(format!("yaz-map-save-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut doc_map = DocumentMap::default();
+        *doc_map.get_mut(&0).unwrap() = Document::new_from_file(&path.to_string_lossy());
+        let server = TestServer::run(
+            ModalEditor::new(doc_map.into(), NormalMode::id())
+                .with_mode(Box::new(NormalMode::new()))
+                .with_mode(Box::new(InsertMode::new()))
+                .with_mode(Box::new(CommandMode::new())),
+        );
+        server.send_keys(":map <C-s> :save<Enter>\nix\x1b\x13");
+        assert_eq!(server.text(0), Some("x".to_string()));
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved, "x");
+    }
+
+    /// Inserts its arguments at the start of the current document.
+    #[action_generator]
+    fn prepend(args: &[&str], state: &EditorStateSummary) -> Option<EditorAction> {
+        let tx = Transaction::new().with_mod(PrimitiveMod::Text(
+            state.curr_buffer_idx,
+            BufMod::InsText(0, args.concat()),
+        ));
+        Some([EditorCmd::RawTransaction(tx)].into_iter().collect())
+    }
+
+    #[test]
+    fn command_arg_history() {
+        let mut editor = command_editor();
+        editor.register_command(PREPEND);
+        let server = TestServer::run(editor);
+        server.send_keys(":prepend a\n:prepend b\n");
+        assert_eq!(server.text(0), Some("ba".to_string()));
+        // Recall the most recent arguments first, then cycle to the older ones.
+        server.send_keys(":prepend \t\n:prepend \t\t\n");
+        assert_eq!(server.text(0), Some("abba".to_string()));
+    }
+
+    #[test]
+    fn runtime_command() {
+        let mut editor = test_editor();
+        assert!(!editor.register_command(PREPEND));
+        let mut editor = editor.with_mode(Box::new(CommandMode::new()));
+        assert!(editor.register_command(PREPEND));
+        let server = TestServer::run(editor);
+        server.send_keys(":prepend x\n");
+        assert_eq!(server.text(0), Some("x".to_string()));
+    }
+
+    #[test]
+    fn read_only_lock() {
+        let server = TestServer::run(command_editor());
+        server.send_keys(":set readonly\nia\x1b");
+        assert_eq!(server.text(0), Some("".to_string()));
+        server.send_keys(":set noreadonly\nia\x1b");
+        assert_eq!(server.text(0), Some("a".to_string()));
+    }
+
+    #[test]
+    fn grep_documents() {
+        let server = TestServer::run(command_editor());
+        server.send_keys("iab\nxab\x1b:grep ab\n");
+        assert_eq!(
+            server.text(1),
+            Some("[scratch]:1:1:ab\n[scratch]:2:2:xab\n".to_string())
+        );
+        // Jump to the second result and insert there.
+        server.send_keys("j\niZ\x1b");
+        assert_eq!(server.text(0), Some("ab\nxZab".to_string()));
+        // Enter does not jump from a line that only looks like a result.
+        server.send_keys("i\n[scratch]:1:1:\x1b\niW\x1b");
+        assert!(server.text(0).unwrap().contains('W'));
+        assert_eq!(
+            server.text(1),
+            Some("[scratch]:1:1:ab\n[scratch]:2:2:xab\n".to_string())
+        );
+    }
+
+    #[test]
+    fn raw_shell_command() {
+        let server = TestServer::run(command_editor());
+        // The colons and the spacing reach the shell as they are typed.
+        server.send_keys(":!printf \"a  b:c\"\n");
+        assert_eq!(server.text(0), Some("a  b:c".to_string()));
+    }
+
+    #[test]
+    fn literal_substitute() {
+        let server = TestServer::run(command_editor());
+        // Only the first occurrence is replaced without the `g` flag.
+        server.send_keys("iaaa ab\x1b:%s/aa/b/\r");
+        assert_eq!(server.text(0), Some("ba ab".to_string()));
+        // The occurrences do not overlap, and the head stays after the last one.
+        server.send_keys(":substitute /a/xy/g\ri.\x1b");
+        assert_eq!(server.text(0), Some("bxy xyb.".to_string()));
+    }
+
+    #[test]
+    fn substitute_forms() {
+        let server = TestServer::run(command_editor());
+        // The texts keep their spacing.
+        server.send_keys("ia  b a/b\x1b:%s/a  b/x/g\r");
+        assert_eq!(server.text(0), Some("x a/b".to_string()));
+        // Without the leading slash, the argument is a regex even if it contains slashes, and the
+        // replacement is the rest of the argument.
+        server.send_keys(":substitute a/b c  d\r");
+        assert_eq!(server.text(0), Some("x c  d".to_string()));
+        // Nothing changes if the regex does not match, not even the selections.
+        server.send_keys("ll:substitute z+ y\ri.\x1b");
+        assert_eq!(server.text(0), Some("x .c  d".to_string()));
+    }
+
+    #[test]
+    fn substitute_primary_selection() {
+        let server = TestServer::run(command_editor().with_mode(Box::new(SelectionMode::new())));
+        // The empty text stands for the text of the primary selection.
+        server.send_keys("iab cab\x1bhhvl:%s//x/g\r");
+        assert_eq!(server.text(0), Some("x cx".to_string()));
+        // There is nothing to substitute without a selected text.
+        server.send_keys("\x1b:%s//y/g\r");
+        assert_eq!(server.text(0), Some("x cx".to_string()));
+    }
+
+    #[test]
+    fn select_theme() {
+        let server = TestServer::run(command_editor());
+        server.conn().send_req(EditorServerReq::ThemesListed(vec![
+            "dark".to_string(),
+            "light (high contrast)".to_string(),
+        ]));
+        // Unknown themes are rejected, and the names may contain spaces.
+        server.send_keys(":theme blue\r:theme light (high contrast)\r");
+        let mut rejected = false;
+        let theme = loop {
+            match server.conn().receive_msg() {
+                Ok(EditorServerMsg::ErrorThrown(_)) => rejected = true,
+                Ok(EditorServerMsg::EditorResult(ModalEditorResult::OptionSet(_), state)) => {
+                    break state.settings.theme
+                }
+                _ => {}
+            }
+        };
+        assert!(rejected);
+        assert_eq!(theme, "light (high contrast)");
+    }
+
+    #[test]
+    fn substitute_preview() {
+        let server = TestServer::run(command_editor());
+        server
+            .conn()
+            .send_req(EditorServerReq::UpdateViewEvent(80, 10));
+        server.send_keys("iab\ncb\x1b:substitute --preview b x\r");
+        let preview_text = loop {
+            if let Ok(EditorServerMsg::EditorResult(ModalEditorResult::Previewed, state)) =
+                server.conn().receive_msg()
+            {
+                break state.display.mid_box_text;
+            }
+        };
+        assert_eq!(preview_text, Some("ax\ncx".to_string()));
+        assert_eq!(server.text(0), Some("ab\ncb".to_string()));
+    }
+}
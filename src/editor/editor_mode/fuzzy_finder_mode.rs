@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+
+use crate::{
+    editor::{EditorAction, EditorCmd, EditorDisplay, EditorStateSummary},
+    events::{Key, KeyCombo, KeyEvt, KeyMods},
+};
+
+use super::EditorMode;
+
+/// Lists the files under the working directory and opens the one picked with a fuzzy search.
+pub struct FuzzyFinderMode {
+    query: String,
+    /// The files under the working directory, or none while they are being listed.
+    files: Arc<Mutex<Option<Vec<String>>>>,
+    selected_idx: usize,
+}
+
+impl FuzzyFinderMode {
+    pub fn new() -> Self {
+        FuzzyFinderMode {
+            query: String::new(),
+            files: Default::default(),
+            selected_idx: 0,
+        }
+    }
+
+    pub fn id() -> &'static str {
+        "fuzzyfinder"
+    }
+
+    /// Returns the files under the working directory that are not ignored by git.
+    fn list_files() -> Vec<String> {
+        ignore::WalkBuilder::new(".")
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|entry| {
+                let path = entry.path();
+                path.strip_prefix("./")
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Returns at most `limit` files that match the query, best match first.
+    fn matches(&self, limit: usize) -> Vec<String> {
+        use rust_fuzzy_search::fuzzy_search_best_n;
+        let files = self.files.lock().unwrap();
+        let Some(files) = files.as_ref() else {
+            return vec![];
+        };
+        if self.query.is_empty() {
+            return files.iter().take(limit).cloned().collect();
+        }
+        let files = files.iter().map(|file| file.as_str()).collect_vec();
+        fuzzy_search_best_n(&self.query, &files, limit)
+            .into_iter()
+            .map(|(file, _)| file.to_string())
+            .collect()
+    }
+
+    /// Returns the number of matches that fit on the screen.
+    fn max_matches(state: &EditorStateSummary) -> usize {
+        std::cmp::max(state.view.max_height.saturating_sub(4), 1)
+    }
+}
+
+impl EditorMode for FuzzyFinderMode {
+    fn id(&self) -> &'static str {
+        Self::id()
+    }
+
+    fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
+        let matches = self.matches(Self::max_matches(state));
+        // Exit with discard
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Esc, KeyMods::NONE)]) {
+            return [EditorCmd::PopMode].into_iter().collect();
+        }
+        // Exit with accept
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Enter, KeyMods::NONE)]) {
+            let mut action: EditorAction = [EditorCmd::PopMode, EditorCmd::ResetCombo]
+                .into_iter()
+                .collect();
+            if let Some(file) = matches.get(self.selected_idx) {
+                action.append(EditorCmd::OpenFile(file.clone()));
+            }
+            return action;
+        }
+        // Move between the matches.
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Down, KeyMods::NONE)]) {
+            self.selected_idx =
+                std::cmp::min(self.selected_idx + 1, matches.len().saturating_sub(1));
+        }
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Up, KeyMods::NONE)]) {
+            self.selected_idx = self.selected_idx.saturating_sub(1);
+        }
+        // Delete from the query on backspace.
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Backspace, KeyMods::NONE)]) {
+            self.query.pop();
+            self.selected_idx = 0;
+        }
+        // Mutate the query
+        let additional_txt = kc.extract_text().replace(['\n', '\t'], "");
+        if !additional_txt.is_empty() {
+            self.query.push_str(&additional_txt);
+            self.selected_idx = 0;
+        }
+        [EditorCmd::ResetCombo].into_iter().collect()
+    }
+
+    fn on_enter(&mut self, _state: &EditorStateSummary) {
+        self.query = String::new();
+        self.selected_idx = 0;
+        // List the files in the background, as it may take a while in large directories.
+        let files = Arc::new(Mutex::new(None));
+        self.files = files.clone();
+        std::thread::spawn(move || {
+            let listed_files = Self::list_files();
+            *files.lock().unwrap() = Some(listed_files);
+        });
+    }
+
+    fn get_display(&self, state: &EditorStateSummary) -> EditorDisplay {
+        let matches_text = if self.files.lock().unwrap().is_none() {
+            "listing files...".to_string()
+        } else {
+            self.matches(Self::max_matches(state))
+                .into_iter()
+                .enumerate()
+                .map(|(idx, file)| {
+                    let marker = if idx == self.selected_idx { ">" } else { " " };
+                    format!("{} {}", marker, file)
+                })
+                .join("\n")
+        };
+        // The overlay covers the bottom bar, so display the query within the overlay as well.
+        let query_text = format!("find: {}", self.query);
+        EditorDisplay {
+            overlay_text: Some(format!("{}\n\n{}", query_text, matches_text)),
+            btm_bar_text: Some(query_text),
+            ..Default::default()
+        }
+    }
+}
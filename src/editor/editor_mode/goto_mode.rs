@@ -54,6 +54,26 @@ impl GotoMode {
                     EditorCmd::PopMode,
                 ],
             )
+            .with(
+                [
+                    [KeyMatcher::Number(KeyMods::NONE)],
+                    [KeyMatcher::Exact(KeyEvt::Char('l', KeyMods::NONE))],
+                ],
+                [
+                    EditorCmd::Transaction(MOVE_HEAD_TO_LINE),
+                    EditorCmd::PopMode,
+                ],
+            )
+            .with(
+                [
+                    [KeyMatcher::Number(KeyMods::NONE)],
+                    [KeyMatcher::Exact(KeyEvt::Char('c', KeyMods::NONE))],
+                ],
+                [
+                    EditorCmd::Transaction(MOVE_HEAD_TO_COLUMN),
+                    EditorCmd::PopMode,
+                ],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
                 [EditorCmd::PopMode],
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use itertools::Itertools;
 use macros::{tx_generator, BasicEditorMode};
 use ropey::Rope;
@@ -6,13 +8,14 @@ use crate::{
     cursor::movement::*,
     document::{
         primitive_mods::{BufMod, PrimitiveMod, SelectionMod},
-        DocumentMap, Transaction,
+        Document, DocumentMap, Transaction, PRIMARY_SEL_ID,
     },
     events::{Key, KeyCombo, KeyEvt, KeyMatcher, KeyMods},
+    render_server::{ConcreteStyle, RGBAColor},
 };
 
 use super::normal_mode::*;
-use super::{EditorCmd, TriggerHandler};
+use super::{EditorAction, EditorCmd, EditorDisplay, EditorStateSummary, TriggerHandler};
 
 fn delete_at_side(
     doc_map: &DocumentMap,
@@ -49,39 +52,109 @@ fn delete_at_side(
     Some(modification)
 }
 
-#[tx_generator]
-fn insert_key(trigger: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    // Collect the text to insert from the trigger.
-    let text_to_insert = trigger.extract_text();
-    if text_to_insert.is_empty() {
-        return None;
-    }
+/// Inserts the text returned for the head of each selection at the head. The text is returned
+/// along with the number of its chars that the head is moved over.
+fn insert_at_heads(
+    doc_id: usize,
+    doc: &Document,
+    text_at: impl Fn(usize) -> (String, usize),
+) -> Transaction {
     let mut modification = Transaction::new();
-    let text_num_chars = text_to_insert.chars().count();
-    doc_map
-        .get_curr_doc()?
-        .selections
+    doc.selections
         .iter()
         .sorted_by_key(|(_, sel)| sel.0)
         .for_each(|(sel_id, sel)| {
-            let insert_index = modification
-                .map_char_idx(&doc_map.curr_doc_id(), &sel.0)
-                .unwrap_or(0);
-            // Move the head to the right of the inserted text
-            let new_head = insert_index + text_num_chars;
+            let (text_to_insert, head_offset) = text_at(sel.0);
+            let insert_index = modification.map_char_idx(&doc_id, &sel.0).unwrap_or(0);
             modification.append_mods([
-                PrimitiveMod::Text(
-                    doc_map.curr_doc_id(),
-                    BufMod::InsText(insert_index, text_to_insert.clone()),
-                ),
+                PrimitiveMod::Text(doc_id, BufMod::InsText(insert_index, text_to_insert)),
                 PrimitiveMod::Sel(
-                    doc_map.curr_doc_id(),
+                    doc_id,
                     *sel_id,
-                    SelectionMod::SetHead(new_head),
+                    SelectionMod::SetHead(insert_index + head_offset),
                 ),
             ]);
         });
-    Some(modification)
+    modification
+}
+
+#[tx_generator]
+fn insert_key(trigger: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    // Collect the text to insert from the trigger.
+    let text_to_insert = trigger.extract_text();
+    if text_to_insert.is_empty() {
+        return None;
+    }
+    // Move the head to the right of the inserted text
+    let text_num_chars = text_to_insert.chars().count();
+    Some(insert_at_heads(
+        doc_map.curr_doc_id(),
+        doc_map.get_curr_doc()?,
+        |_| (text_to_insert.clone(), text_num_chars),
+    ))
+}
+
+/// Inserts a new line that is indented as much as the current line. The indentation is cut at
+/// the head, so that breaking a line within its indentation does not indent it further.
+#[tx_generator]
+fn insert_newline_with_indent(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let doc = doc_map.get_curr_doc()?;
+    let buf = doc.get_buf();
+    Some(insert_at_heads(doc_map.curr_doc_id(), doc, |head| {
+        let column = head - line_start(head, buf).unwrap_or(head);
+        let indent = line_leading_whitespace(head, buf)
+            .chars()
+            .take(column)
+            .collect::<String>();
+        let text_to_insert = format!("\n{}", indent);
+        let text_num_chars = text_to_insert.chars().count();
+        (text_to_insert, text_num_chars)
+    }))
+}
+
+/// Inserts the closing char along with the typed opening char of a pair, placing the heads
+/// between them. Typing a closing char that is already at all the heads moves over it instead.
+/// Quotes that follow a word char, e.g., apostrophes, are not paired.
+fn insert_pair(
+    mode: &mut InsertMode,
+    kc: &KeyCombo,
+    state: &EditorStateSummary,
+) -> Option<EditorAction> {
+    if kc.len() != 1 {
+        return None;
+    }
+    let typed = kc.extract_text().chars().exactly_one().ok()?;
+    let doc_id = state.curr_buffer_idx;
+    let buf = state.curr_doc.get_buf();
+    let is_close = mode.auto_pairs.values().any(|close| *close == typed);
+    if is_close
+        && state
+            .curr_doc
+            .selections
+            .values()
+            .all(|sel| buf.get_char(sel.0) == Some(typed))
+    {
+        let tx =
+            Transaction::new().with_mods(state.curr_doc.selections.iter().map(|(sel_id, sel)| {
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetHead(sel.0 + 1))
+            }));
+        return Some([EditorCmd::RawTransaction(tx)].into_iter().collect());
+    }
+    let close = *mode.auto_pairs.get(&typed)?;
+    let follows_word = |head: usize| {
+        head > 0
+            && buf
+                .get_char(head - 1)
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    };
+    let tx = insert_at_heads(doc_id, &state.curr_doc, |head| {
+        if typed == close && follows_word(head) {
+            (typed.to_string(), 1)
+        } else {
+            (format!("{}{}", typed, close), 1)
+        }
+    });
+    Some([EditorCmd::RawTransaction(tx)].into_iter().collect())
 }
 
 #[tx_generator]
@@ -94,14 +167,218 @@ fn delete_right(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
     delete_at_side(doc_map, right_grapheme)
 }
 
+/// The maximum number of completions that are cycled through.
+const MAX_COMPLETIONS: usize = 5;
+
+/// Represents an ongoing word completion at the head of the primary selection.
+pub struct CompletionState {
+    /// The char that the completed prefix starts at.
+    prefix_start: usize,
+    prefix: String,
+    /// The words of the document that start with the prefix, most frequent first.
+    candidates: Vec<String>,
+    /// The index of the candidate that replaces the prefix.
+    curr_idx: usize,
+}
+
+impl CompletionState {
+    /// Starts completing the whitespace-delimited prefix that ends at the given char. Returns none
+    /// if there is no prefix or no word to complete it with.
+    fn new(head: usize, doc: &Document) -> Option<Self> {
+        let buf = doc.get_buf();
+        let prefix_len = buf
+            .chars_at(head)
+            .reversed()
+            .take_while(|c| !c.is_whitespace())
+            .count();
+        if prefix_len == 0 {
+            return None;
+        }
+        let prefix_start = head - prefix_len;
+        let prefix = buf.slice(prefix_start..head).to_string();
+        let text = buf.to_string();
+        let candidates = text
+            .split_whitespace()
+            .filter(|word| word.starts_with(&prefix) && *word != prefix)
+            .counts()
+            .into_iter()
+            .sorted_by(|(word, count), (other_word, other_count)| {
+                other_count.cmp(count).then(word.cmp(other_word))
+            })
+            .take(MAX_COMPLETIONS)
+            .map(|(word, _)| word.to_string())
+            .collect_vec();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(CompletionState {
+            prefix_start,
+            prefix,
+            candidates,
+            curr_idx: 0,
+        })
+    }
+
+    /// Returns the transaction that replaces the text inserted by the completion, which is
+    /// `curr_text`, with the given text.
+    fn replacement_tx(&self, doc_id: usize, curr_text: &str, new_text: &str) -> Transaction {
+        let start = self.prefix_start;
+        Transaction::new().with_mods([
+            PrimitiveMod::Text(
+                doc_id,
+                BufMod::ReplaceRange(
+                    start,
+                    start + curr_text.chars().count(),
+                    new_text.to_string(),
+                ),
+            ),
+            PrimitiveMod::Sel(
+                doc_id,
+                PRIMARY_SEL_ID,
+                SelectionMod::SetHead(start + new_text.chars().count()),
+            ),
+        ])
+    }
+}
+
+/// Completes the word before the primary head on `Ctrl+N` with the words of the document, cycling
+/// through the candidates on the following presses. `Esc` keeps the completed word, while any
+/// other key restores the prefix before it is handled without pairing, closing the completion in
+/// both cases.
+fn complete_word(
+    mode: &mut InsertMode,
+    kc: &KeyCombo,
+    state: &EditorStateSummary,
+) -> Option<EditorAction> {
+    let doc_id = state.curr_buffer_idx;
+    let is_single = |key_evt: KeyEvt| kc.len() == 1 && kc.ends_with([key_evt]);
+    if !is_single(KeyEvt::Char('n', KeyMods::CTRL)) {
+        let completion = mode.completion_state.take()?;
+        if is_single(KeyEvt::Key(Key::Esc, KeyMods::NONE)) {
+            return Some(EditorAction::default());
+        }
+        let curr_text = &completion.candidates[completion.curr_idx];
+        let restore_tx = completion.replacement_tx(doc_id, curr_text, &completion.prefix);
+        // The commands of the key are generated after the prefix is restored.
+        let key_action = mode.trigger_handler.handle(kc).unwrap_or_default();
+        return Some(
+            std::iter::once(EditorCmd::RawTransaction(restore_tx))
+                .chain(key_action)
+                .collect(),
+        );
+    }
+    let tx = if let Some(completion) = &mut mode.completion_state {
+        let curr_text = &completion.candidates[completion.curr_idx];
+        let next_idx = (completion.curr_idx + 1) % completion.candidates.len();
+        let tx = completion.replacement_tx(doc_id, curr_text, &completion.candidates[next_idx]);
+        completion.curr_idx = next_idx;
+        tx
+    } else {
+        let head = state.curr_doc.primary_selection()?.0;
+        let completion = CompletionState::new(head, &state.curr_doc)?;
+        let tx = completion.replacement_tx(doc_id, &completion.prefix, &completion.candidates[0]);
+        mode.completion_state = Some(completion);
+        tx
+    };
+    Some([EditorCmd::RawTransaction(tx)].into_iter().collect())
+}
+
+/// Handles the combos that depend on the state of the insert mode.
+fn handle_stateful_combo(
+    mode: &mut InsertMode,
+    kc: &KeyCombo,
+    state: &EditorStateSummary,
+) -> Option<EditorAction> {
+    complete_word(mode, kc, state).or_else(|| insert_pair(mode, kc, state))
+}
+
+/// Displays the cursors as green text instead of highlighting them, along with the completions.
+fn insert_display(mode: &InsertMode, _: &EditorStateSummary) -> EditorDisplay {
+    let mid_box_text = mode.completion_state.as_ref().map(|completion| {
+        completion
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(idx, word)| {
+                if idx == completion.curr_idx {
+                    format!("[{}]", word)
+                } else {
+                    word.clone()
+                }
+            })
+            .join("\t")
+    });
+    EditorDisplay {
+        mid_box_text,
+        cursor_style: Some(ConcreteStyle {
+            highlight: false,
+            fg: Some(RGBAColor(152, 195, 121, 255)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The pairs of chars that are inserted together by default.
+const DEFAULT_AUTO_PAIRS: [(char, char); 5] =
+    [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
 #[derive(BasicEditorMode)]
+#[display(insert_display)]
+#[handler(handle_stateful_combo)]
 pub struct InsertMode {
     trigger_handler: TriggerHandler,
+    /// Whether `Enter` keeps the indentation of the current line.
+    auto_indent: bool,
+    /// The closing chars that are inserted after the opening chars, keyed by the latter.
+    auto_pairs: HashMap<char, char>,
+    completion_state: Option<CompletionState>,
 }
 
 impl InsertMode {
     pub fn new() -> Self {
-        let trigger_handler = TriggerHandler::default()
+        InsertMode {
+            trigger_handler: Self::trigger_handler(true),
+            auto_indent: true,
+            auto_pairs: HashMap::from(DEFAULT_AUTO_PAIRS),
+            completion_state: None,
+        }
+    }
+
+    /// Sets whether `Enter` keeps the indentation of the current line.
+    pub fn with_auto_indent(self, auto_indent: bool) -> Self {
+        if auto_indent == self.auto_indent {
+            return self;
+        }
+        InsertMode {
+            trigger_handler: Self::trigger_handler(auto_indent),
+            auto_indent,
+            ..self
+        }
+    }
+
+    /// Sets the pairs of chars that are inserted together, e.g., none to disable the pairing.
+    pub fn with_auto_pairs(self, auto_pairs: impl IntoIterator<Item = (char, char)>) -> Self {
+        InsertMode {
+            auto_pairs: auto_pairs.into_iter().collect(),
+            ..self
+        }
+    }
+
+    fn trigger_handler(auto_indent: bool) -> TriggerHandler {
+        // Without the indentation, `Enter` inserts a bare new line like the other keys.
+        let insert_newline = if auto_indent {
+            INSERT_NEWLINE_WITH_INDENT
+        } else {
+            INSERT_KEY
+        };
+        // The keys that are not bound here, e.g., `Ctrl+P`, are handled by the mode below.
+        TriggerHandler::default()
+            .with_fallthrough()
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Key(Key::Enter, KeyMods::NONE))]],
+                [EditorCmd::Transaction(insert_newline)],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('z', KeyMods::CTRL))]],
                 [EditorCmd::UndoCurrDocument],
@@ -141,14 +418,87 @@ impl InsertMode {
                 [[
                     KeyMatcher::AnyChar(KeyMods::NONE),
                     KeyMatcher::Exact(KeyEvt::Key(Key::Tab, KeyMods::NONE)),
-                    KeyMatcher::Exact(KeyEvt::Key(Key::Enter, KeyMods::NONE)),
                 ]],
                 [EditorCmd::Transaction(INSERT_KEY)],
             )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
                 [EditorCmd::PopMode],
-            );
-        InsertMode { trigger_handler }
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::{
+        editor_server::EditorServerReq,
+        test_utils::{test_editor, TestServer},
+        ModalEditor,
+    };
+
+    use super::*;
+
+    fn insert_editor(insert_mode: InsertMode) -> ModalEditor {
+        test_editor().with_mode(Box::new(insert_mode))
+    }
+
+    #[test]
+    fn auto_indent() {
+        let server = TestServer::run(insert_editor(InsertMode::new()));
+        server.send_keys("i  a\nb");
+        assert_eq!(server.text(0), Some("  a\n  b".to_string()));
+        let server = TestServer::run(insert_editor(InsertMode::new().with_auto_indent(false)));
+        server.send_keys("i  a\nb");
+        assert_eq!(server.text(0), Some("  a\nb".to_string()));
+    }
+
+    #[test]
+    fn auto_pairs() {
+        let server = TestServer::run(insert_editor(
+            InsertMode::new().with_auto_pairs([('(', ')')]),
+        ));
+        server.send_keys("if(x[");
+        assert_eq!(server.text(0), Some("f(x[)".to_string()));
+    }
+
+    #[test]
+    fn auto_pairs_skip_over() {
+        let server = TestServer::run(insert_editor(InsertMode::new()));
+        // Typing the closing char moves over the inserted one.
+        server.send_keys("if()x");
+        assert_eq!(server.text(0), Some("f()x".to_string()));
+        // Apostrophes are not paired, while the quotes after a space are.
+        server.send_keys(" it's 'a'b");
+        assert_eq!(server.text(0), Some("f()x it's 'a'b".to_string()));
+    }
+
+    #[test]
+    fn unbound_keys_fall_through() {
+        let server = TestServer::run(insert_editor(InsertMode::new()));
+        server.send_keys("iab");
+        for evt in [
+            KeyEvt::Key(Key::Left, KeyMods::NONE),
+            KeyEvt::Char('d', KeyMods::ALT),
+        ] {
+            server.conn().send_req(EditorServerReq::UIEvent(evt));
+        }
+        // `Alt+D` is only bound in the normal mode below, and the insert mode stays active.
+        server.send_keys("c");
+        assert_eq!(server.text(0), Some("ac".to_string()));
+    }
+
+    #[test]
+    fn word_completion() {
+        let server = TestServer::run(insert_editor(InsertMode::new()));
+        server.send_keys("ifoo fa fab fab f\x0e");
+        assert_eq!(server.text(0), Some("foo fa fab fab fab".to_string()));
+        server.send_keys("\x0e\x0e");
+        assert_eq!(server.text(0), Some("foo fa fab fab foo".to_string()));
+        // Esc keeps the completion without leaving the insert mode.
+        server.send_keys("\x1b ");
+        assert_eq!(server.text(0), Some("foo fa fab fab foo ".to_string()));
+        // Any other key restores the prefix before it is inserted.
+        server.send_keys("f\x0ed");
+        assert_eq!(server.text(0), Some("foo fa fab fab foo fd".to_string()));
     }
 }
@@ -1,27 +1,63 @@
+use std::sync::LazyLock;
+
 use itertools::Itertools;
 use macros::{tx_generator, BasicEditorMode};
 use ropey::Rope;
 
 use crate::{
-    cursor::{movement::*, SelectionIterator, TextSelection},
+    cursor::{movement::*, selection_range, RopeExt, SelectionIterator, TextSelection},
     document::{
         primitive_mods::{BufMod, DocMapMod, PrimitiveMod, SelectionMod},
         DocumentMap, Transaction,
     },
     events::{Key, KeyCombo, KeyEvt, KeyMatcher, KeyMods},
+    render_server::ConcreteStyle,
 };
 
-use super::*;
+use super::{visual_line_mode::SNAP_SELECTION_TO_LINES, *};
 
 fn move_all_heads(
     movement_fn: impl Fn(usize, &Rope) -> Option<usize>,
     doc_map: &DocumentMap,
+) -> Option<Transaction> {
+    move_all_heads_n(|char_idx, _, buf| movement_fn(char_idx, buf), 1, doc_map)
+}
+
+/// Moves all the heads with the movement function the given number of times, stopping early once
+/// a head no longer moves.
+fn move_all_heads_repeated(
+    movement_fn: impl Fn(usize, &Rope) -> Option<usize>,
+    count: usize,
+    doc_map: &DocumentMap,
+) -> Option<Transaction> {
+    move_all_heads_n(
+        |char_idx, count, buf| {
+            let mut curr_idx = char_idx;
+            for _ in 0..count {
+                match movement_fn(curr_idx, buf) {
+                    Some(next_idx) if next_idx != curr_idx => curr_idx = next_idx,
+                    _ => break,
+                }
+            }
+            Some(curr_idx)
+        },
+        count,
+        doc_map,
+    )
+}
+
+/// Moves all the heads with a movement function that takes a repetition count, e.g.,
+/// `right_grapheme_n`.
+fn move_all_heads_n(
+    movement_fn: impl Fn(usize, usize, &Rope) -> Option<usize>,
+    count: usize,
+    doc_map: &DocumentMap,
 ) -> Option<Transaction> {
     let buf = &doc_map.get_curr_doc()?.get_buf();
     Some(
         Transaction::new().with_mods(doc_map.get_curr_doc()?.selections.iter().map(
             |(sel_id, sel)| {
-                let new_head = movement_fn(sel.0, buf).unwrap_or(sel.0);
+                let new_head = movement_fn(sel.0, count, buf).unwrap_or(sel.0);
                 PrimitiveMod::Sel(
                     doc_map.curr_doc_id(),
                     *sel_id,
@@ -34,22 +70,35 @@ fn move_all_heads(
 
 #[tx_generator]
 pub fn move_head_left(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(left_grapheme, doc_map)
+    move_all_heads_n(left_grapheme_n, 1, doc_map)
 }
 
 #[tx_generator]
 pub fn move_head_right(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(right_grapheme, doc_map)
+    move_all_heads_n(right_grapheme_n, 1, doc_map)
+}
+
+/// Moves the heads left as many times as the count of the combo, e.g., `3h`. Unlike
+/// `move_head_left`, which other commands build upon, this is only bound on its own.
+#[tx_generator]
+pub fn move_head_left_n(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_n(left_grapheme_n, kc.extract_count(), doc_map)
 }
 
+/// Moves the heads right as many times as the count of the combo, e.g., `3l`.
 #[tx_generator]
-pub fn move_head_up(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(upper_grapheme_or_start, doc_map)
+pub fn move_head_right_n(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_n(right_grapheme_n, kc.extract_count(), doc_map)
 }
 
 #[tx_generator]
-pub fn move_head_down(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(lower_grapheme_or_end, doc_map)
+pub fn move_head_up(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_repeated(upper_grapheme_or_start, kc.extract_count(), doc_map)
+}
+
+#[tx_generator]
+pub fn move_head_down(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_repeated(lower_grapheme_or_end, kc.extract_count(), doc_map)
 }
 
 #[tx_generator]
@@ -72,9 +121,50 @@ pub fn move_head_file_end(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transac
     move_all_heads(file_end, doc_map)
 }
 
+/// Moves the heads to the start of the line given by the number in the combo, counting from 1.
 #[tx_generator]
-pub fn move_head_right_word_start(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(right_word_start, doc_map)
+pub fn move_head_to_line(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let line_idx = kc.extract_number()?.saturating_sub(1);
+    move_all_heads(|_, buf| goto_line(line_idx, buf), doc_map)
+}
+
+/// Moves the heads to the column given by the number in the combo, counting from 1.
+#[tx_generator]
+pub fn move_head_to_column(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let column = kc.extract_number()?.saturating_sub(1);
+    move_all_heads(
+        |char_idx, buf| column_to_char_idx(char_idx, column, buf),
+        doc_map,
+    )
+}
+
+/// Moves to the start of the word that `w` or `b` selects after being pressed `count` times. Each
+/// press moves to the start of a word and the selection then extends to its end, so the
+/// preceding presses end up at the end of their word.
+fn word_start_n(
+    start_fn: impl Fn(usize, &Rope) -> Option<usize>,
+    end_fn: impl Fn(usize, &Rope) -> Option<usize>,
+    char_idx: usize,
+    count: usize,
+    buf: &Rope,
+) -> Option<usize> {
+    let mut curr_idx = char_idx;
+    for _ in 1..count {
+        match start_fn(curr_idx, buf).and_then(|idx| end_fn(idx, buf)) {
+            Some(next_idx) if next_idx != curr_idx => curr_idx = next_idx,
+            _ => break,
+        }
+    }
+    start_fn(curr_idx, buf)
+}
+
+#[tx_generator]
+pub fn move_head_right_word_start(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_n(
+        |char_idx, count, buf| word_start_n(right_word_start, right_word_end, char_idx, count, buf),
+        kc.extract_count(),
+        doc_map,
+    )
 }
 
 #[tx_generator]
@@ -83,8 +173,12 @@ pub fn move_head_right_word_end(_: &KeyCombo, doc_map: &DocumentMap) -> Option<T
 }
 
 #[tx_generator]
-pub fn move_head_left_word_start(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    move_all_heads(left_word_start, doc_map)
+pub fn move_head_left_word_start(kc: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads_n(
+        |char_idx, count, buf| word_start_n(left_word_start, left_word_end, char_idx, count, buf),
+        kc.extract_count(),
+        doc_map,
+    )
 }
 
 #[tx_generator]
@@ -92,26 +186,88 @@ pub fn move_head_left_word_end(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Tr
     move_all_heads(left_word_end, doc_map)
 }
 
+#[tx_generator]
+pub fn move_head_same_indent_forward(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads(move_head_to_same_indent_forward, doc_map)
+}
+
+#[tx_generator]
+pub fn move_head_same_indent_backward(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    move_all_heads(move_head_to_same_indent_backward, doc_map)
+}
+
+/// Returns the target of an `f<char>` combo along with the count that may precede it, e.g.,
+/// `3f<char>`.
+fn occurrence_target(kc: &KeyCombo) -> Option<(String, usize)> {
+    let KeyEvt::Char(target, _) = kc.0.last()? else {
+        return None;
+    };
+    Some((target.to_string(), kc.extract_count()))
+}
+
 #[tx_generator]
 pub fn move_head_right_occurrence(tr: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    let target = match tr.0.iter().nth(1)? {
-        KeyEvt::Char(c, _) => Some(c),
-        _ => None,
-    }?
-    .to_string();
+    let (target, count) = occurrence_target(tr)?;
+    if count > 1 {
+        return move_all_heads(
+            |idx, buf| right_occurrence_n(idx, &target, count, buf),
+            doc_map,
+        );
+    }
     move_all_heads(|idx, buf| right_occurrence(idx, &target, buf), doc_map)
 }
 
 #[tx_generator]
 pub fn move_head_left_occurrence(tr: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    let target = match tr.0.iter().nth(1)? {
-        KeyEvt::Char(c, _) => Some(c),
-        _ => None,
-    }?
-    .to_string();
+    let (target, count) = occurrence_target(tr)?;
+    if count > 1 {
+        return move_all_heads(
+            |idx, buf| left_occurrence_n(idx, &target, count, buf),
+            doc_map,
+        );
+    }
     move_all_heads(|idx, buf| left_occurrence(idx, &target, buf), doc_map)
 }
 
+/// Matches a `:grep` result formatted as `path:line:column:text`.
+static GREP_RESULT_REGEX: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"^(.+?):(\d+):(\d+):").unwrap());
+
+/// Jumps to the location of the `:grep` result on the line of the primary head. Does nothing on
+/// the other lines and outside the `:grep` results documents.
+#[tx_generator]
+fn goto_grep_result(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let curr_doc = doc_map.get_curr_doc()?;
+    if !curr_doc.grep_results {
+        return Some(Transaction::new());
+    }
+    let buf = curr_doc.get_buf();
+    let line_idx = buf.try_char_to_line(curr_doc.selections.get(&0)?.0).ok()?;
+    let line = buf.get_line(line_idx)?.to_string();
+    let Some(captures) = GREP_RESULT_REGEX.captures(&line) else {
+        return Some(Transaction::new());
+    };
+    let target_line_idx = captures[2].parse::<usize>().ok()?.saturating_sub(1);
+    let target_column = captures[3].parse::<usize>().ok()?.saturating_sub(1);
+    // The results document itself may have the same name as an unnamed target.
+    let (target_doc_id, target_doc) = doc_map
+        .iter()
+        .filter(|(doc_id, doc)| {
+            *doc_id != doc_map.curr_doc_id() && doc.source.to_string() == captures[1]
+        })
+        .min_by_key(|(doc_id, _)| *doc_id)?;
+    let target_buf = target_doc.get_buf();
+    let target_idx = std::cmp::min(
+        goto_line(target_line_idx, target_buf)? + target_column,
+        target_buf.len_chars(),
+    );
+    Some(Transaction::new().with_mods([
+        PrimitiveMod::Sel(target_doc_id, 0, SelectionMod::SetTail(None)),
+        PrimitiveMod::Sel(target_doc_id, 0, SelectionMod::SetHead(target_idx)),
+        PrimitiveMod::DocMap(DocMapMod::SwitchDoc(target_doc_id)),
+    ]))
+}
+
 #[tx_generator]
 fn select_this_or_next_line(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
     let buf = &doc_map.get_curr_doc()?.get_buf();
@@ -161,17 +317,58 @@ fn select_this_or_next_line(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Trans
     )
 }
 
-#[tx_generator]
-fn delete_sels(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
-    let merged_sels = doc_map
+/// Selects the paragraphs that contain the selection heads. Outer paragraphs also include the
+/// following paragraph of blank lines, or the preceding one if there is none.
+fn select_paragraph(outer: bool, doc_map: &DocumentMap) -> Option<Transaction> {
+    let buf = doc_map.get_curr_doc()?.get_buf();
+    let mods = doc_map
         .get_curr_doc()?
         .selections
-        .values()
-        .cloned()
-        .collect_merged(&doc_map.get_curr_doc()?.get_buf());
-    // Delete the selections while maintaining the selection positions.
+        .iter()
+        .flat_map(|(sel_id, sel)| {
+            let (mut first_line, mut last_line) = paragraph_lines(sel.0, buf)?;
+            if outer {
+                let next_paragraph = paragraph_lines(buf.line_char_range(last_line + 1).start, buf)
+                    .filter(|(next_first_line, _)| *next_first_line > last_line);
+                if let Some((_, next_last_line)) = next_paragraph {
+                    last_line = next_last_line;
+                } else if first_line > 0 {
+                    let prev_line_start = buf.line_char_range(first_line - 1).start;
+                    first_line = paragraph_lines(prev_line_start, buf)?.0;
+                }
+            }
+            let start = buf.line_char_range(first_line).start;
+            let end = line_end(buf.line_char_range(last_line).start, buf)?;
+            Some([
+                PrimitiveMod::Sel(doc_map.curr_doc_id(), *sel_id, SelectionMod::SetHead(end)),
+                PrimitiveMod::Sel(
+                    doc_map.curr_doc_id(),
+                    *sel_id,
+                    SelectionMod::SetTail(Some(start)),
+                ),
+            ])
+        })
+        .flatten()
+        .collect_vec();
+    Some(Transaction::new().with_mods(mods))
+}
+
+#[tx_generator]
+fn select_inner_paragraph(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    select_paragraph(false, doc_map)
+}
+
+#[tx_generator]
+fn select_outer_paragraph(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    select_paragraph(true, doc_map)
+}
+
+/// Deletes the given ranges, which must be sorted and non-overlapping, and collapses all the
+/// selections onto their new positions.
+fn delete_ranges(ranges: Vec<(usize, usize)>, doc_map: &DocumentMap) -> Option<Transaction> {
+    // Delete the ranges while maintaining the selection positions.
     let mut modification = Transaction::new();
-    merged_sels.iter().for_each(|(start, end)| {
+    ranges.iter().for_each(|(start, end)| {
         let start = modification
             .map_char_idx(&doc_map.curr_doc_id(), start)
             .unwrap_or(0);
@@ -209,6 +406,35 @@ fn delete_sels(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
     Some(modification)
 }
 
+#[tx_generator]
+fn delete_sels(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let merged_sels = doc_map
+        .get_curr_doc()?
+        .selections
+        .values()
+        .cloned()
+        .collect_merged(&doc_map.get_curr_doc()?.get_buf());
+    delete_ranges(merged_sels, doc_map)
+}
+
+/// Deletes each selection on its own instead of merging the overlapping ones. Selections
+/// overlapping with a selection of a lower id are dropped so that no grapheme gets deleted twice.
+#[tx_generator]
+fn delete_sels_independently(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let buf = doc_map.get_curr_doc()?.get_buf();
+    let ranges = doc_map
+        .get_curr_doc()?
+        .selections
+        .iter()
+        .sorted_by_key(|(sel_id, _)| *sel_id)
+        .map(|(_, sel)| *sel)
+        .collect_non_overlapping(buf)
+        .iter()
+        .map(|sel| selection_range(sel, buf))
+        .collect_vec();
+    delete_ranges(ranges, doc_map)
+}
+
 #[tx_generator]
 fn insert_newline(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
     let sel_heads = doc_map
@@ -348,14 +574,111 @@ fn swap_head_tail(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
     Some(Transaction::new().with_mods(mods))
 }
 
+/// Returns the block comment delimiters of the language with the given file extension.
+fn block_comment_delimiters(ext: &str) -> Option<(&'static str, &'static str)> {
+    match ext {
+        "rs" | "c" | "cpp" | "css" | "java" => Some(("/*", "*/")),
+        _ => None,
+    }
+}
+
+/// Wraps each selection that spans multiple lines in block comment delimiters, or removes the
+/// delimiters if the selection is already a block comment. Selections overlapping with a
+/// selection of a lower id are left as they are.
+#[tx_generator]
+fn toggle_block_comment(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let doc_id = doc_map.curr_doc_id();
+    let doc = doc_map.get_curr_doc()?;
+    let buf = doc.get_buf();
+    let (open, close) = block_comment_delimiters(doc.get_ext()?)?;
+    let (open_len, close_len) = (open.chars().count(), close.chars().count());
+    let mut kept: Vec<(usize, TextSelection, (usize, usize))> = vec![];
+    for (sel_id, sel) in doc.selections.iter().sorted_by_key(|(sel_id, _)| *sel_id) {
+        let range = selection_range(sel, buf);
+        if kept
+            .iter()
+            .all(|(_, _, other)| range.1 <= other.0 || other.1 <= range.0)
+        {
+            kept.push((*sel_id, *sel, range));
+        }
+    }
+    let mut modification = Transaction::new();
+    // Modify the selections from left to right while tracking the shift of the text.
+    let mut shift: isize = 0;
+    for (sel_id, sel, (start, end)) in kept.into_iter().sorted_by_key(|(_, _, range)| range.0) {
+        if sel.1.is_none() {
+            continue;
+        }
+        let text = buf.slice(start..end).to_string();
+        let (start, end) = (
+            (start as isize + shift) as usize,
+            (end as isize + shift) as usize,
+        );
+        let is_comment = text.chars().count() >= open_len + close_len
+            && text.starts_with(open)
+            && text.ends_with(close);
+        // A trailing newline does not make the selection span the next line.
+        if !is_comment && !text.trim_end_matches('\n').contains('\n') {
+            continue;
+        }
+        let new_end = if is_comment {
+            modification.append_mods([
+                PrimitiveMod::Text(doc_id, BufMod::DelRange(end - close_len, end)),
+                PrimitiveMod::Text(doc_id, BufMod::DelRange(start, start + open_len)),
+            ]);
+            shift -= (open_len + close_len) as isize;
+            end - open_len - close_len
+        } else {
+            modification.append_mods([
+                PrimitiveMod::Text(doc_id, BufMod::InsText(end, close.to_string())),
+                PrimitiveMod::Text(doc_id, BufMod::InsText(start, open.to_string())),
+            ]);
+            shift += (open_len + close_len) as isize;
+            end + open_len + close_len
+        };
+        // Keep the direction of the selection, which covers the delimiters if they are added.
+        let new_last = std::cmp::max(new_end.saturating_sub(1), start);
+        let (new_head, new_tail) = if sel.0 < sel.1.unwrap() {
+            (start, new_last)
+        } else {
+            (new_last, start)
+        };
+        modification.append_mods([
+            PrimitiveMod::Sel(doc_id, sel_id, SelectionMod::SetHead(new_head)),
+            PrimitiveMod::Sel(doc_id, sel_id, SelectionMod::SetTail(Some(new_tail))),
+        ]);
+    }
+    Some(modification)
+}
+
+/// Displays the secondary cursors in reverse video, so that they stand out from both the primary
+/// cursor and the secondary selections.
+fn normal_display(_: &NormalMode, _: &EditorStateSummary) -> EditorDisplay {
+    EditorDisplay {
+        secondary_cursor_style: Some(ConcreteStyle {
+            highlight: true,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
 #[derive(BasicEditorMode)]
+#[display(normal_display)]
 pub struct NormalMode {
     trigger_handler: TriggerHandler,
 }
 
 impl NormalMode {
     pub fn new() -> Self {
-        let trigger_handler = TriggerHandler::default()
+        NormalMode {
+            trigger_handler: Self::trigger_handler(),
+        }
+    }
+
+    /// Returns the key bindings of the normal mode, which other modes can build upon.
+    pub fn trigger_handler() -> TriggerHandler {
+        TriggerHandler::default()
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('u', KeyMods::NONE))]],
                 [EditorCmd::UndoCurrDocument],
@@ -365,26 +688,30 @@ impl NormalMode {
                 [EditorCmd::RedoCurrDocument],
             )
             .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('U', KeyMods::ALT))]],
+                [EditorCmd::RedoAltCurrDocument],
+            )
+            .with_count(
                 [[
                     KeyMatcher::Exact(KeyEvt::Key(Key::Left, KeyMods::NONE)),
                     KeyMatcher::Exact(KeyEvt::Char('h', KeyMods::NONE)),
                 ]],
                 [
                     EditorCmd::Transaction(COLLAPSE_SELS),
-                    EditorCmd::Transaction(MOVE_HEAD_LEFT),
+                    EditorCmd::Transaction(MOVE_HEAD_LEFT_N),
                 ],
             )
-            .with(
+            .with_count(
                 [[
                     KeyMatcher::Exact(KeyEvt::Key(Key::Right, KeyMods::NONE)),
                     KeyMatcher::Exact(KeyEvt::Char('l', KeyMods::NONE)),
                 ]],
                 [
                     EditorCmd::Transaction(COLLAPSE_SELS),
-                    EditorCmd::Transaction(MOVE_HEAD_RIGHT),
+                    EditorCmd::Transaction(MOVE_HEAD_RIGHT_N),
                 ],
             )
-            .with(
+            .with_count(
                 [[
                     KeyMatcher::Exact(KeyEvt::Key(Key::Up, KeyMods::NONE)),
                     KeyMatcher::Exact(KeyEvt::Char('k', KeyMods::NONE)),
@@ -394,7 +721,7 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_UP),
                 ],
             )
-            .with(
+            .with_count(
                 [[
                     KeyMatcher::Exact(KeyEvt::Key(Key::Down, KeyMods::NONE)),
                     KeyMatcher::Exact(KeyEvt::Char('j', KeyMods::NONE)),
@@ -404,7 +731,7 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_DOWN),
                 ],
             )
-            .with(
+            .with_count(
                 [
                     [KeyMatcher::Exact(KeyEvt::Char('f', KeyMods::NONE))],
                     [KeyMatcher::AnyChar(KeyMods::NONE)],
@@ -416,7 +743,7 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_RIGHT_OCCURRENCE),
                 ],
             )
-            .with(
+            .with_count(
                 [
                     [KeyMatcher::Exact(KeyEvt::Char('F', KeyMods::NONE))],
                     [KeyMatcher::AnyChar(KeyMods::NONE)],
@@ -428,7 +755,7 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_LEFT_OCCURRENCE),
                 ],
             )
-            .with(
+            .with_count(
                 [[KeyMatcher::Exact(KeyEvt::Char('w', KeyMods::NONE))]],
                 [
                     EditorCmd::Transaction(COLLAPSE_SELS),
@@ -437,7 +764,7 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_RIGHT_WORD_END),
                 ],
             )
-            .with(
+            .with_count(
                 [[
                     KeyMatcher::Exact(KeyEvt::Char('W', KeyMods::NONE)),
                     KeyMatcher::Exact(KeyEvt::Char('b', KeyMods::NONE)),
@@ -449,6 +776,26 @@ impl NormalMode {
                     EditorCmd::Transaction(MOVE_HEAD_LEFT_WORD_END),
                 ],
             )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char(']', KeyMods::NONE))],
+                    [KeyMatcher::Exact(KeyEvt::Char('i', KeyMods::NONE))],
+                ],
+                [
+                    EditorCmd::Transaction(COLLAPSE_SELS),
+                    EditorCmd::Transaction(MOVE_HEAD_SAME_INDENT_FORWARD),
+                ],
+            )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('[', KeyMods::NONE))],
+                    [KeyMatcher::Exact(KeyEvt::Char('i', KeyMods::NONE))],
+                ],
+                [
+                    EditorCmd::Transaction(COLLAPSE_SELS),
+                    EditorCmd::Transaction(MOVE_HEAD_SAME_INDENT_BACKWARD),
+                ],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('%', KeyMods::NONE))]],
                 [
@@ -462,10 +809,33 @@ impl NormalMode {
                 [[KeyMatcher::Exact(KeyEvt::Char(';', KeyMods::NONE))]],
                 [EditorCmd::Transaction(SWAP_HEAD_TAIL)],
             )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Key(Key::Enter, KeyMods::NONE))]],
+                [EditorCmd::Transaction(GOTO_GREP_RESULT)],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char(':', KeyMods::NONE))]],
                 [EditorCmd::PushMode(CommandMode::id())],
             )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('p', KeyMods::CTRL))]],
+                [EditorCmd::PushMode(FuzzyFinderMode::id())],
+            )
+            .with(
+                [[
+                    KeyMatcher::Exact(KeyEvt::Char('/', KeyMods::NONE)),
+                    KeyMatcher::Exact(KeyEvt::Char('?', KeyMods::NONE)),
+                ]],
+                [EditorCmd::PushMode(SearchMode::id())],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('n', KeyMods::NONE))]],
+                [EditorCmd::SearchNext],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('N', KeyMods::NONE))]],
+                [EditorCmd::SearchPrev],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('x', KeyMods::NONE))]],
                 [EditorCmd::Transaction(SELECT_THIS_OR_NEXT_LINE)],
@@ -477,6 +847,57 @@ impl NormalMode {
                     EditorCmd::Transaction(COLLAPSE_SELS),
                 ],
             )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('d', KeyMods::ALT))]],
+                [
+                    EditorCmd::Transaction(DELETE_SELS_INDEPENDENTLY),
+                    EditorCmd::Transaction(COLLAPSE_SELS),
+                ],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('y', KeyMods::NONE))]],
+                [EditorCmd::YankSels],
+            )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('"', KeyMods::NONE))],
+                    [KeyMatcher::AnyChar(KeyMods::NONE)],
+                    [KeyMatcher::Exact(KeyEvt::Char('y', KeyMods::NONE))],
+                ],
+                [EditorCmd::YankSels],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('p', KeyMods::NONE))]],
+                [EditorCmd::PasteAfterSels],
+            )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('"', KeyMods::NONE))],
+                    [KeyMatcher::AnyChar(KeyMods::NONE)],
+                    [KeyMatcher::Exact(KeyEvt::Char('p', KeyMods::NONE))],
+                ],
+                [EditorCmd::PasteAfterSels],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('P', KeyMods::NONE))]],
+                [EditorCmd::PasteBeforeSels],
+            )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('"', KeyMods::NONE))],
+                    [KeyMatcher::AnyChar(KeyMods::NONE)],
+                    [KeyMatcher::Exact(KeyEvt::Char('P', KeyMods::NONE))],
+                ],
+                [EditorCmd::PasteBeforeSels],
+            )
+            .with_count(
+                [[KeyMatcher::Exact(KeyEvt::Char('.', KeyMods::NONE))]],
+                [EditorCmd::RepeatLast],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('#', KeyMods::NONE))]],
+                [EditorCmd::Transaction(TOGGLE_BLOCK_COMMENT)],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('c', KeyMods::NONE))]],
                 [
@@ -531,6 +952,37 @@ impl NormalMode {
                     EditorCmd::PushMode(SelectionMode::id()),
                 ],
             )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('V', KeyMods::NONE))]],
+                [
+                    EditorCmd::Transaction(DROP_TAIL),
+                    EditorCmd::Transaction(SNAP_SELECTION_TO_LINES),
+                    EditorCmd::PushMode(VisualLineMode::id()),
+                ],
+            )
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('v', KeyMods::CTRL))]],
+                [
+                    EditorCmd::Transaction(RESET_SELS),
+                    EditorCmd::Transaction(COLLAPSE_SELS),
+                    EditorCmd::PushMode(BlockSelectionMode::id()),
+                ],
+            )
+            // `i` and `a` enter the insert mode right away, so the text objects start with Alt.
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('i', KeyMods::ALT))],
+                    [KeyMatcher::Exact(KeyEvt::Char('p', KeyMods::NONE))],
+                ],
+                [EditorCmd::Transaction(SELECT_INNER_PARAGRAPH)],
+            )
+            .with(
+                [
+                    [KeyMatcher::Exact(KeyEvt::Char('a', KeyMods::ALT))],
+                    [KeyMatcher::Exact(KeyEvt::Char('p', KeyMods::NONE))],
+                ],
+                [EditorCmd::Transaction(SELECT_OUTER_PARAGRAPH)],
+            )
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Char('g', KeyMods::NONE))]],
                 [EditorCmd::PushMode(GotoMode::id())],
@@ -538,7 +990,104 @@ impl NormalMode {
             .with(
                 [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
                 [EditorCmd::Transaction(COLLAPSE_OR_RESET_SELS)],
-            );
-        NormalMode { trigger_handler }
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        document::{Document, DocumentMap},
+        editor::{
+            editor_server::EditorServerReq,
+            test_utils::{test_editor, TestServer},
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn block_comments() {
+        let path =
+            std::env::temp_dir().join(format!("yaz-block-comment-{}.rs", std::process::id()));
+        std::fs::write(&path, "a\nb\nc d").unwrap();
+        let mut doc_map = DocumentMap::default();
+        let doc = doc_map.get_mut(&0).unwrap();
+        *doc = Document::new_from_file(&path.to_string_lossy());
+        doc.selections = HashMap::from([
+            (0, TextSelection(2, Some(0))),
+            (1, TextSelection(1, Some(3))),
+            (2, TextSelection(4, Some(6))),
+        ]);
+        let toggle = |doc_map: &mut DocumentMap| {
+            (TOGGLE_BLOCK_COMMENT.1)(&KeyCombo::default(), doc_map)
+                .unwrap()
+                .apply_tx(doc_map)
+                .unwrap();
+            doc_map.get(&0).unwrap().get_buf().to_string()
+        };
+        // Only the multi-line selection is wrapped, and the overlapping one is kept.
+        assert_eq!(toggle(&mut doc_map), "/*a\nb*/\nc d");
+        assert_eq!(doc_map.get(&0).unwrap().selections.len(), 3);
+        assert_eq!(toggle(&mut doc_map), "a\nb\nc d");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dot_repeat() {
+        let server = TestServer::run(test_editor());
+        // The insertion is repeated in the normal mode with the key that it was typed with.
+        server.send_keys("ib\x1b.");
+        assert_eq!(server.text(0), Some("bb".to_string()));
+        // Undoing keeps the last action, and the motions do not replace it.
+        server.send_keys("uh.");
+        assert_eq!(server.text(0), Some("bb".to_string()));
+    }
+
+    #[test]
+    fn dot_repeat_paste() {
+        let server = TestServer::run(test_editor());
+        // Pasting modifies the text with a command other than a transaction.
+        server.send_keys("iab\x1bhhyp.");
+        assert_eq!(server.text(0), Some("aaab".to_string()));
+    }
+
+    #[test]
+    fn counted_motions() {
+        let server = TestServer::run(test_editor());
+        // Move three lines up and two graphemes left, then repeat the insertion twice.
+        server.send_keys("iab\nc\nd\ne\x1b3k2hix\x1b2.");
+        assert_eq!(server.text(0), Some("xxxab\nc\nd\ne".to_string()));
+    }
+
+    #[test]
+    fn named_register() {
+        let server = TestServer::run(test_editor());
+        // Yank `a` into the register `r` and then `b` into the unnamed register only.
+        server.send_keys("iab\x1bhh\"ryly\"rpp");
+        assert_eq!(server.text(0), Some("abab".to_string()));
+    }
+
+    #[test]
+    fn paragraph_text_objects() {
+        let server = TestServer::run(test_editor());
+        let send_alt = |ch| {
+            server
+                .conn()
+                .send_req(EditorServerReq::UIEvent(KeyEvt::Char(ch, KeyMods::ALT)))
+        };
+        // `ip` still inserts, while the text objects are selected with Alt.
+        server.send_keys("ip\x1ba\nb\n\nc\x1b");
+        assert_eq!(server.text(0), Some("p\nb\n\nc".to_string()));
+        send_alt('i');
+        server.send_keys("pd");
+        assert_eq!(server.text(0), Some("p\nb\n\n".to_string()));
+        // The outer paragraph includes the following blank lines.
+        server.send_keys("kk");
+        send_alt('a');
+        server.send_keys("pd");
+        assert_eq!(server.text(0), Some("".to_string()));
     }
 }
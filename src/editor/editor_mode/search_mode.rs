@@ -0,0 +1,147 @@
+use itertools::Itertools;
+
+use crate::{
+    document::{
+        primitive_mods::{PrimitiveMod, SelectionMod},
+        Transaction,
+    },
+    editor::{
+        search::{move_heads_to_matches, SearchState},
+        EditorAction, EditorCmd, EditorDisplay, EditorStateSummary,
+    },
+    events::{Key, KeyCombo, KeyEvt, KeyMods},
+};
+
+use super::EditorMode;
+
+/// Searches the current document incrementally, forward if entered with `/` and backward if
+/// entered with `?`. The heads move to the match of the pattern as it is typed, and return to
+/// where they were if the search is cancelled.
+pub struct SearchMode {
+    curr_pattern: String,
+    backward: bool,
+    /// The heads of the selections when the mode was entered, which the search starts from.
+    origin_heads: Vec<(usize, usize)>,
+    /// Restores the selections as they were when the mode was entered.
+    restore_tx: Transaction,
+}
+
+impl SearchMode {
+    pub fn new() -> Self {
+        SearchMode {
+            curr_pattern: String::new(),
+            backward: false,
+            origin_heads: vec![],
+            restore_tx: Transaction::new(),
+        }
+    }
+
+    pub fn id() -> &'static str {
+        "search"
+    }
+
+    /// Returns the transaction that moves the heads from where they were to the matches of the
+    /// current pattern, or restores them if there are no matches.
+    fn search_tx(&self, state: &EditorStateSummary) -> Transaction {
+        move_heads_to_matches(
+            state.curr_buffer_idx,
+            self.origin_heads.iter().copied(),
+            &self.curr_pattern,
+            self.backward,
+            state.curr_doc.get_buf(),
+        )
+        .unwrap_or_else(|| self.restore_tx.clone())
+    }
+}
+
+impl EditorMode for SearchMode {
+    fn id(&self) -> &'static str {
+        Self::id()
+    }
+
+    fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
+        // Exit with discard
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Esc, KeyMods::NONE)]) {
+            return [
+                EditorCmd::RawTransaction(self.restore_tx.clone()),
+                EditorCmd::PopMode,
+            ]
+            .into_iter()
+            .collect();
+        }
+        // Exit with accept
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Enter, KeyMods::NONE)]) {
+            let mut action: EditorAction = [EditorCmd::PopMode, EditorCmd::ResetCombo]
+                .into_iter()
+                .collect();
+            if !self.curr_pattern.is_empty() {
+                action.append(EditorCmd::SetSearch(SearchState {
+                    pattern: self.curr_pattern.clone(),
+                    backward: self.backward,
+                }));
+            }
+            return action;
+        }
+        // Delete from the pattern on backspace.
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Backspace, KeyMods::NONE)]) {
+            self.curr_pattern.pop();
+        }
+        // Mutate the pattern
+        let additional_txt = kc.extract_text().replace(['\n', '\t'], "");
+        self.curr_pattern.push_str(&additional_txt);
+        [
+            EditorCmd::RawTransaction(self.search_tx(state)),
+            EditorCmd::ResetCombo,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn on_enter(&mut self, state: &EditorStateSummary) {
+        self.curr_pattern = String::new();
+        self.backward = state
+            .curr_combo
+            .ends_with([KeyEvt::Char('?', KeyMods::NONE)]);
+        let doc_id = state.curr_buffer_idx;
+        let sels = state
+            .curr_doc
+            .selections
+            .iter()
+            .sorted_by_key(|(sel_id, _)| **sel_id)
+            .collect_vec();
+        self.origin_heads = sels.iter().map(|(sel_id, sel)| (**sel_id, sel.0)).collect();
+        self.restore_tx = Transaction::new().with_mods(sels.iter().flat_map(|(sel_id, sel)| {
+            [
+                PrimitiveMod::Sel(doc_id, **sel_id, SelectionMod::SetHead(sel.0)),
+                PrimitiveMod::Sel(doc_id, **sel_id, SelectionMod::SetTail(sel.1)),
+            ]
+        }));
+    }
+
+    fn get_display(&self, _state: &EditorStateSummary) -> EditorDisplay {
+        let prompt = if self.backward { "?" } else { "/" };
+        EditorDisplay {
+            btm_bar_text: Some(format!("{}{}", prompt, self.curr_pattern)),
+            search_pattern: Some(self.curr_pattern.clone()).filter(|pattern| !pattern.is_empty()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::test_utils::{test_editor, TestServer};
+
+    use super::*;
+
+    #[test]
+    fn incremental_search() {
+        let server = TestServer::run(test_editor().with_mode(Box::new(SearchMode::new())));
+        // The search wraps around from the end of the document, and `n` moves to the next match.
+        server.send_keys("iab cd ab cd\x1b/cd\nni1\x1b");
+        assert_eq!(server.text(0), Some("ab cd ab 1cd".to_string()));
+        // Cancelling the search moves the head back.
+        server.send_keys("?ab\x1bi2\x1b");
+        assert_eq!(server.text(0), Some("ab cd ab 12cd".to_string()));
+    }
+}
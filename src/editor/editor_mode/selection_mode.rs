@@ -1,50 +1,40 @@
-use crate::{
-    editor::{EditorAction, EditorStateSummary},
-    events::{Key, KeyCombo, KeyEvt, KeyMods},
-};
+use macros::BasicEditorMode;
 
-use super::{normal_mode::*, EditorCmd, EditorMode, InsertMode, NormalMode};
+use crate::events::{Key, KeyEvt, KeyMatcher, KeyMods};
 
+use super::{normal_mode::*, EditorCmd, InsertMode, NormalMode, SplitMode, TriggerHandler};
+
+#[derive(BasicEditorMode)]
 pub struct SelectionMode {
-    normal_mode: NormalMode,
+    trigger_handler: TriggerHandler,
 }
 
 impl SelectionMode {
     pub fn new() -> Self {
-        SelectionMode {
-            normal_mode: NormalMode::new(),
-        }
-    }
-
-    pub fn id() -> &'static str {
-        "selection"
-    }
-}
-
-impl EditorMode for SelectionMode {
-    fn id(&self) -> &'static str {
-        Self::id()
-    }
-
-    fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
-        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Esc, KeyMods::NONE)]) {
-            return [EditorCmd::Transaction(COLLAPSE_SELS), EditorCmd::PopMode]
-                .into_iter()
-                .collect();
-        }
-        self.normal_mode
-            .handle_combo(kc, state)
-            .into_iter()
-            .flat_map(|mode_resp| match mode_resp {
-                EditorCmd::Transaction(cmd) if cmd == COLLAPSE_SELS => None,
-                EditorCmd::PushMode(mode_id) if mode_id == InsertMode::id() => None,
-                EditorCmd::PushMode(mode_id) if mode_id == self.id() => None,
-                _ => Some(mode_resp),
-            })
-            .collect()
-    }
-
-    fn get_display(&self, _state: &EditorStateSummary) -> super::EditorDisplay {
-        Default::default()
+        // Use the normal mode bindings without collapsing the selections or entering the insert
+        // mode with `i` and `a`.
+        let normal_handler = NormalMode::trigger_handler()
+            .with_exclusion([[KeyMatcher::Exact(KeyEvt::Char('i', KeyMods::NONE))]])
+            .with_exclusion([[KeyMatcher::Exact(KeyEvt::Char('a', KeyMods::NONE))]])
+            .with_exclusion([[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]])
+            .with_retained_cmds(|cmd| match cmd {
+                EditorCmd::Transaction(tx_gen) => *tx_gen != COLLAPSE_SELS,
+                EditorCmd::PushMode(mode_id) => {
+                    *mode_id != InsertMode::id() && *mode_id != Self::id()
+                }
+                _ => true,
+            });
+        let trigger_handler = TriggerHandler::default()
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
+                [EditorCmd::Transaction(COLLAPSE_SELS), EditorCmd::PopMode],
+            )
+            // Split the selections at the matches of a regex.
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Char('s', KeyMods::NONE))]],
+                [EditorCmd::PushMode(SplitMode::id())],
+            )
+            .extend(normal_handler);
+        SelectionMode { trigger_handler }
     }
 }
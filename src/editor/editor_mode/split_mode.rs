@@ -0,0 +1,254 @@
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::{
+    cursor::{movement::left_grapheme, selection_range, SelectionIterator, TextSelection},
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod, SelectionMod},
+        Document, Transaction,
+    },
+    editor::{EditorAction, EditorCmd, EditorDisplay, EditorStateSummary},
+    events::{Key, KeyCombo, KeyEvt, KeyMods},
+};
+
+use super::EditorMode;
+
+/// Returns the char ranges between the matches of the regex within the given range, skipping the
+/// empty ones. Returns none if the regex does not match within the range.
+fn split_range(
+    regex: &Regex,
+    start: usize,
+    end: usize,
+    doc: &Document,
+) -> Option<Vec<(usize, usize)>> {
+    let slice = doc.get_buf().get_slice(start..end)?;
+    let text = slice.to_string();
+    let mut matches = regex.find_iter(&text).peekable();
+    matches.peek()?;
+    let mut boundaries = vec![start];
+    for m in matches {
+        boundaries.push(start + slice.byte_to_char(m.start()));
+        boundaries.push(start + slice.byte_to_char(m.end()));
+    }
+    boundaries.push(end);
+    Some(
+        boundaries
+            .into_iter()
+            .tuples()
+            .filter(|(piece_start, piece_end)| piece_start < piece_end)
+            .collect(),
+    )
+}
+
+/// Returns the transaction that splits the selections of the document at the matches of the
+/// regex, such that the text between the matches is selected. The overlapping selections are
+/// merged first. The pieces of a range reuse the ids of the selections that it was merged from,
+/// and the new selections get ids after the largest one. The selections whose range has no
+/// matches are removed, unless the regex matches nowhere, in which case none is returned.
+pub fn split_selections(regex: &Regex, doc_id: usize, doc: &Document) -> Option<Transaction> {
+    let buf = doc.get_buf();
+    let merged = doc.selections.values().cloned().collect_merged(buf);
+    // The ids of the selections that each merged range covers, in order.
+    let mut range_ids = vec![vec![]; merged.len()];
+    for (sel_id, sel) in doc.selections.iter().sorted_by_key(|(sel_id, _)| **sel_id) {
+        let (start, end) = selection_range(sel, buf);
+        if let Some(range_idx) = merged
+            .iter()
+            .position(|(range_start, range_end)| *range_start <= start && end <= *range_end)
+        {
+            range_ids[range_idx].push(*sel_id);
+        }
+    }
+    let pieces = merged
+        .iter()
+        .map(|(start, end)| split_range(regex, *start, *end, doc).unwrap_or_default())
+        .collect_vec();
+    let first_split = pieces
+        .iter()
+        .position(|range_pieces| !range_pieces.is_empty())?;
+    // Keep the primary selection even if its own range was not split.
+    if pieces
+        .iter()
+        .zip(&range_ids)
+        .any(|(range_pieces, ids)| range_pieces.is_empty() && ids.contains(&0))
+    {
+        range_ids
+            .iter_mut()
+            .for_each(|ids| ids.retain(|sel_id| *sel_id != 0));
+        range_ids[first_split].insert(0, 0);
+    }
+    let mut next_sel_id = doc.selections.keys().max().map_or(0, |sel_id| sel_id + 1);
+    let mut tx = Transaction::new();
+    for (range_pieces, ids) in pieces.into_iter().zip(range_ids) {
+        for (piece_idx, (start, end)) in range_pieces.iter().enumerate() {
+            let head = left_grapheme(*end, buf).unwrap_or(*start).max(*start);
+            if let Some(sel_id) = ids.get(piece_idx) {
+                tx.append_mods([
+                    PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetHead(head)),
+                    PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetTail(Some(*start))),
+                ]);
+            } else {
+                tx.append_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                    doc_id,
+                    next_sel_id,
+                    TextSelection(head, Some(*start)),
+                )));
+                next_sel_id += 1;
+            }
+        }
+        tx.append_mods(
+            ids.iter()
+                .skip(range_pieces.len())
+                .map(|sel_id| PrimitiveMod::DocMap(DocMapMod::DeleteSel(doc_id, *sel_id))),
+        );
+    }
+    Some(tx)
+}
+
+/// Reads a regex and splits the selections at its matches once it is entered.
+pub struct SplitMode {
+    curr_pattern: String,
+}
+
+impl SplitMode {
+    pub fn new() -> Self {
+        SplitMode {
+            curr_pattern: String::new(),
+        }
+    }
+
+    pub fn id() -> &'static str {
+        "split"
+    }
+}
+
+impl EditorMode for SplitMode {
+    fn id(&self) -> &'static str {
+        Self::id()
+    }
+
+    fn handle_combo(&mut self, kc: &KeyCombo, state: &EditorStateSummary) -> EditorAction {
+        let reset: EditorAction = [EditorCmd::ResetCombo].into_iter().collect();
+        // Exit with discard
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Esc, KeyMods::NONE)]) {
+            return [EditorCmd::PopMode].into_iter().collect();
+        }
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Enter, KeyMods::NONE)]) {
+            // Ignore the enter before the pattern, i.e., accept `s<Enter>pattern<Enter>` as well.
+            if self.curr_pattern.is_empty() {
+                return reset;
+            }
+            let mut action: EditorAction = [EditorCmd::PopMode, EditorCmd::ResetCombo]
+                .into_iter()
+                .collect();
+            if let Some(tx) = Regex::new(&self.curr_pattern)
+                .ok()
+                .and_then(|regex| split_selections(&regex, state.curr_buffer_idx, &state.curr_doc))
+            {
+                action.append(EditorCmd::RawTransaction(tx));
+            }
+            return action;
+        }
+        // Delete from the pattern on backspace.
+        if kc.len() == 1 && kc.ends_with([KeyEvt::Key(Key::Backspace, KeyMods::NONE)]) {
+            self.curr_pattern.pop();
+        }
+        // Mutate the pattern
+        let additional_txt = kc.extract_text().replace(['\n', '\t'], "");
+        self.curr_pattern.push_str(&additional_txt);
+        reset
+    }
+
+    fn on_enter(&mut self, _state: &EditorStateSummary) {
+        self.curr_pattern = String::new();
+    }
+
+    fn get_display(&self, _state: &EditorStateSummary) -> EditorDisplay {
+        let invalid = !self.curr_pattern.is_empty() && Regex::new(&self.curr_pattern).is_err();
+        EditorDisplay {
+            btm_bar_text: Some(format!(
+                "split: {}{}",
+                self.curr_pattern,
+                if invalid { " (invalid regex)" } else { "" }
+            )),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        document::{primitive_mods::BufMod, DocumentMap},
+        editor::{
+            editor_mode::SelectionMode,
+            test_utils::{test_editor, TestServer},
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn split_at_matches() {
+        let mut doc_map = DocumentMap::default();
+        // Select `a,bb` and `c,,d` with overlapping selections, and `ef` separately.
+        let setup = Transaction::new()
+            .with_mod(PrimitiveMod::Text(
+                0,
+                BufMod::InsText(0, "a,bb c,,d ef".into()),
+            ))
+            .with_mod(PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(3)))
+            .with_mod(PrimitiveMod::Sel(0, 0, SelectionMod::SetTail(Some(0))))
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                0,
+                1,
+                TextSelection(5, Some(8)),
+            )))
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                0,
+                3,
+                TextSelection(6, Some(7)),
+            )))
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                0,
+                2,
+                TextSelection(11, Some(10)),
+            )));
+        assert!(setup.apply_tx(&mut doc_map).is_some());
+        let regex = Regex::new(",+").unwrap();
+        let doc = doc_map.get_curr_doc().unwrap();
+        assert!(split_selections(&Regex::new("x").unwrap(), 0, doc).is_none());
+        assert!(split_selections(&regex, 0, doc)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .is_some());
+        let doc = doc_map.get_curr_doc().unwrap();
+        let sels = doc
+            .selections
+            .iter()
+            .sorted_by_key(|(sel_id, _)| **sel_id)
+            .map(|(sel_id, sel)| (*sel_id, sel.0, sel.1))
+            .collect_vec();
+        assert_eq!(
+            sels,
+            vec![
+                (0, 0, Some(0)),
+                (1, 5, Some(5)),
+                (3, 8, Some(8)),
+                (4, 3, Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_in_selection_mode() {
+        let server = TestServer::run(
+            test_editor()
+                .with_mode(Box::new(SelectionMode::new()))
+                .with_mode(Box::new(SplitMode::new())),
+        );
+        // Select the whole text, split it at the commas and delete the pieces.
+        server.send_keys("ia,bb,c\x1bvhhhhhhs\n,\nd");
+        assert_eq!(server.text(0), Some(",,".to_string()));
+    }
+}
@@ -0,0 +1,138 @@
+use itertools::Itertools;
+use macros::tx_generator;
+
+use crate::{
+    cursor::movement::{line_end, line_start},
+    document::{
+        primitive_mods::{PrimitiveMod, SelectionMod},
+        DocumentMap, Transaction,
+    },
+    editor::{EditorAction, EditorStateSummary},
+    events::{Key, KeyCombo, KeyEvt, KeyMatcher, KeyMods},
+};
+
+use super::{
+    normal_mode::*, EditorCmd, EditorMode, InsertMode, NormalMode, SelectionMode, TriggerHandler,
+};
+
+/// Extends the selections so that they cover whole lines, including the trailing newlines.
+#[tx_generator]
+pub fn snap_selection_to_lines(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+    let buf = doc_map.get_curr_doc()?.get_buf();
+    let mods = doc_map
+        .get_curr_doc()?
+        .selections
+        .iter()
+        .flat_map(|(sel_id, sel)| {
+            let tail = sel.1.unwrap_or(sel.0);
+            let (new_head, new_tail) = if sel.0 >= tail {
+                (line_end(sel.0, buf)?, line_start(tail, buf)?)
+            } else {
+                (line_start(sel.0, buf)?, line_end(tail, buf)?)
+            };
+            Some([
+                PrimitiveMod::Sel(
+                    doc_map.curr_doc_id(),
+                    *sel_id,
+                    SelectionMod::SetHead(new_head),
+                ),
+                PrimitiveMod::Sel(
+                    doc_map.curr_doc_id(),
+                    *sel_id,
+                    SelectionMod::SetTail(Some(new_tail)),
+                ),
+            ])
+        })
+        .flatten()
+        .collect_vec();
+    Some(Transaction::new().with_mods(mods))
+}
+
+/// Selects whole lines. The normal mode commands act on the selected lines, and the motions keep
+/// the selections on whole lines.
+pub struct VisualLineMode {
+    trigger_handler: TriggerHandler,
+}
+
+impl VisualLineMode {
+    pub fn new() -> Self {
+        // Use the normal mode bindings without collapsing the selections or entering the other
+        // selection modes.
+        let normal_handler = NormalMode::trigger_handler()
+            .with_exclusion([[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]])
+            .with_retained_cmds(|cmd| match cmd {
+                EditorCmd::Transaction(tx_gen) => *tx_gen != COLLAPSE_SELS,
+                EditorCmd::PushMode(mode_id) => {
+                    *mode_id != InsertMode::id()
+                        && *mode_id != SelectionMode::id()
+                        && *mode_id != Self::id()
+                }
+                _ => true,
+            });
+        let trigger_handler = TriggerHandler::default()
+            .with(
+                [[KeyMatcher::Exact(KeyEvt::Key(Key::Esc, KeyMods::NONE))]],
+                [EditorCmd::Transaction(COLLAPSE_SELS), EditorCmd::PopMode],
+            )
+            .extend(normal_handler);
+        VisualLineMode { trigger_handler }
+    }
+
+    pub fn id() -> &'static str {
+        "visualline"
+    }
+}
+
+impl EditorMode for VisualLineMode {
+    fn id(&self) -> &'static str {
+        Self::id()
+    }
+
+    fn handle_combo(&mut self, kc: &KeyCombo, _state: &EditorStateSummary) -> EditorAction {
+        let Some(mut action) = self.trigger_handler.handle(kc) else {
+            return EditorAction::default();
+        };
+        if action.iter().any(|cmd| matches!(cmd, EditorCmd::PopMode)) {
+            return action;
+        }
+        let deletes = action.iter().any(|cmd| {
+            matches!(cmd, EditorCmd::Transaction(tx_gen)
+                if *tx_gen == DELETE_SELS || *tx_gen == DELETE_SELS_INDEPENDENTLY)
+        });
+        // The selected lines are gone after a deletion, so leave the mode as well.
+        if deletes {
+            action.prepend(EditorCmd::PopMode);
+            action.append(EditorCmd::Transaction(COLLAPSE_SELS));
+            return action;
+        }
+        // Keep the selections on whole lines after the motions.
+        if action
+            .iter()
+            .any(|cmd| matches!(cmd, EditorCmd::Transaction(_)))
+        {
+            action.append(EditorCmd::Transaction(SNAP_SELECTION_TO_LINES));
+        }
+        action
+    }
+
+    fn get_display(&self, _state: &EditorStateSummary) -> super::EditorDisplay {
+        Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::test_utils::{test_editor, TestServer};
+
+    use super::*;
+
+    #[test]
+    fn delete_lines() {
+        let server = TestServer::run(test_editor().with_mode(Box::new(VisualLineMode::new())));
+        // The motions extend the selection by whole lines, and the mode is left after deleting.
+        server.send_keys("ia\nbc\nd\x1bkVkjjkd");
+        assert_eq!(server.text(0), Some("a\nd".to_string()));
+        server.send_keys("ix\x1b");
+        assert_eq!(server.text(0), Some("a\nxd".to_string()));
+    }
+}
@@ -1,6 +1,8 @@
 use std::sync::mpsc;
 
-use crate::document::DocumentView;
+use itertools::Itertools;
+
+use crate::document::{DocumentMapEvent, DocumentView, Transaction};
 use crate::editor::{EditorStateSummary, ModalEditor, ModalEditorError, ModalEditorResult};
 
 use crate::events::KeyEvt;
@@ -11,8 +13,17 @@ pub enum EditorServerReq {
     UIEvent(KeyEvt),
     StylizeInitEvent,
     StylizeEvent(usize, usize, ConcreteStyle),
+    /// Multiple styles, each applied to the chars in `start..end`.
+    StylizeBatchEvent(Vec<(usize, usize, ConcreteStyle)>),
     StylizeEndEvent,
     UpdateViewEvent(usize, usize),
+    ListThemes,
+    ThemesListed(Vec<String>),
+    GetDocumentText(usize),
+    /// Requests the logged document map events starting from the given sequence number.
+    GetEventLogSince(usize),
+    GetTransactionLog,
+    Shutdown,
 }
 
 #[derive(Clone, Debug)]
@@ -22,8 +33,17 @@ pub enum EditorServerMsg {
     EditorResult(ModalEditorResult, EditorStateSummary),
     StylizeInit(EditorStateSummary),
     Stylize(usize, usize, ConcreteStyle, EditorStateSummary),
+    StylizeBatch(Vec<(usize, usize, ConcreteStyle)>, EditorStateSummary),
     StylizeEnd(EditorStateSummary),
     ViewUpdated(DocumentView, EditorStateSummary),
+    ListThemesRequested,
+    ListThemesResponse(Vec<String>),
+    /// The text of the document with the given id, if it exists.
+    DocumentText(usize, Option<String>),
+    /// The logged document map events starting from the given sequence number.
+    EventLog(usize, Vec<DocumentMapEvent>),
+    /// The logged transactions, see [`crate::editor::HistoricalEditorState::from_log`].
+    TransactionLog(Vec<Transaction>),
 }
 
 pub struct EditorConnection(
@@ -40,15 +60,71 @@ impl EditorConnection {
         self.1.try_recv()
     }
 
+    /// Requests the text of the given document and blocks until the server responds. The other
+    /// messages received in the meantime are dropped. Returns none if the document does not exist
+    /// or the server has shut down.
+    pub fn request_document_text(&self, doc_id: usize) -> Option<String> {
+        self.send_req(EditorServerReq::GetDocumentText(doc_id));
+        loop {
+            match self.receive_msg().ok()? {
+                EditorServerMsg::DocumentText(msg_doc_id, text) if msg_doc_id == doc_id => {
+                    return text
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Requests the logged transactions, e.g., to replay them elsewhere, and blocks until the
+    /// server responds. The other messages received in the meantime are dropped. Returns an empty
+    /// log if the server has shut down.
+    pub fn get_transaction_log(&self) -> Vec<Transaction> {
+        self.send_req(EditorServerReq::GetTransactionLog);
+        loop {
+            match self.receive_msg() {
+                Ok(EditorServerMsg::TransactionLog(log)) => return log,
+                Ok(_) => {}
+                Err(_) => return vec![],
+            }
+        }
+    }
+
+    /// Sends a request to the server. Requests sent after the server has shut down are dropped.
     pub fn send_req(&self, msg: EditorServerReq) {
-        self.0.send(msg).unwrap();
+        self.0.send(msg).ok();
+    }
+}
+
+/// A connection to the editor server that can be awaited on from an async context.
+#[cfg(feature = "tokio-api")]
+pub struct AsyncEditorConnection(
+    mpsc::Sender<EditorServerReq>,
+    tokio::sync::mpsc::UnboundedReceiver<EditorServerMsg>,
+);
+
+#[cfg(feature = "tokio-api")]
+impl AsyncEditorConnection {
+    /// Waits for the next message from the server. Returns none if the server has shut down.
+    pub async fn receive_msg(&mut self) -> Option<EditorServerMsg> {
+        self.1.recv().await
+    }
+
+    /// Sends a request to the server. Requests sent after the server has shut down are dropped.
+    pub async fn send_req(&self, msg: EditorServerReq) {
+        // Sending to the server never blocks, so there is nothing to await on.
+        self.0.send(msg).ok();
     }
 }
 
+/// Decides whether a message should be sent to a connection.
+pub type MsgFilter = fn(&EditorServerMsg) -> bool;
+
 pub struct EditorServer {
     incoming_channel_rcv: mpsc::Receiver<EditorServerReq>,
     incoming_channel_snd: mpsc::Sender<EditorServerReq>,
-    outgoing_channels: Vec<mpsc::Sender<EditorServerMsg>>,
+    outgoing_channels: Vec<(MsgFilter, mpsc::Sender<EditorServerMsg>)>,
+    #[cfg(feature = "tokio-api")]
+    async_outgoing_channels: Vec<tokio::sync::mpsc::UnboundedSender<EditorServerMsg>>,
     modal_state: ModalEditor,
 }
 
@@ -59,22 +135,51 @@ impl EditorServer {
             incoming_channel_rcv: rcv,
             incoming_channel_snd: snd,
             outgoing_channels: Default::default(),
+            #[cfg(feature = "tokio-api")]
+            async_outgoing_channels: Default::default(),
             modal_state: init_state,
         }
     }
 
     pub fn new_connection(&mut self) -> EditorConnection {
+        self.new_filtered_connection(|_| true)
+    }
+
+    /// Returns a connection that only receives the messages that pass the given filter. Quit
+    /// requests are always received.
+    pub fn new_filtered_connection(&mut self, filter: MsgFilter) -> EditorConnection {
         let (snd, rcv) = mpsc::channel();
-        self.outgoing_channels.push(snd);
+        self.outgoing_channels.push((filter, snd));
         EditorConnection(self.incoming_channel_snd.clone(), rcv)
     }
 
-    fn broadcast(&self, msg: EditorServerMsg) {
-        for c in &self.outgoing_channels {
-            c.send(msg.clone()).unwrap();
+    /// Returns a connection whose messages can be awaited on. The channel is unbounded, so the
+    /// server never blocks on a slow async consumer.
+    #[cfg(feature = "tokio-api")]
+    pub fn new_async_connection(&mut self) -> AsyncEditorConnection {
+        let (snd, rcv) = tokio::sync::mpsc::unbounded_channel();
+        self.async_outgoing_channels.push(snd);
+        AsyncEditorConnection(self.incoming_channel_snd.clone(), rcv)
+    }
+
+    /// Returns a sender that can issue requests to the server without subscribing to its messages.
+    pub fn new_req_sender(&self) -> mpsc::Sender<EditorServerReq> {
+        self.incoming_channel_snd.clone()
+    }
+
+    /// Sends the message to the connections that accept it, dropping the disconnected ones.
+    fn broadcast(&mut self, msg: EditorServerMsg) {
+        let is_quit = matches!(msg, EditorServerMsg::QuitRequested);
+        self.outgoing_channels
+            .retain(|(filter, c)| !(is_quit || filter(&msg)) || c.send(msg.clone()).is_ok());
+        #[cfg(feature = "tokio-api")]
+        for c in &self.async_outgoing_channels {
+            // The async consumer may have already been dropped along with its runtime.
+            c.send(msg.clone()).ok();
         }
     }
 
+    /// Broadcasts the results of an editor update. Returns false iff the editor requested to quit.
     fn handle_editor_results(
         &mut self,
         results: impl IntoIterator<Item = ModalEditorResult>,
@@ -83,8 +188,6 @@ impl EditorServer {
         for result in results {
             match result {
                 ModalEditorResult::QuitRequested => {
-                    self.broadcast(EditorServerMsg::QuitRequested);
-                    println!("EditorServer: quitting");
                     return false;
                 }
                 ModalEditorResult::ErrorThrown(err) => {
@@ -100,55 +203,324 @@ impl EditorServer {
         return true;
     }
 
+    /// Handles a single request. Returns false iff the server should shut down.
+    fn handle_req(&mut self, req: EditorServerReq) -> bool {
+        match req {
+            EditorServerReq::UIEvent(evt) => {
+                self.modal_state.receive_key(evt);
+                let mut update_result = self.modal_state.update();
+                loop {
+                    match update_result {
+                        Ok(results) => {
+                            let should_continue = self.handle_editor_results(results);
+                            if !should_continue {
+                                return false;
+                            }
+                        }
+                        Err(err) => {
+                            self.broadcast(EditorServerMsg::ErrorThrown(err));
+                        }
+                    }
+                    // Run the commands that follow a loading indicator once it is broadcast.
+                    if !self.modal_state.has_pending_action() {
+                        break;
+                    }
+                    update_result = self.modal_state.resume_pending_action();
+                }
+                self.modal_state.update_view();
+            }
+            EditorServerReq::UpdateViewEvent(new_width, new_height)
+                if new_height != self.modal_state.get_view().max_height
+                    || new_width != self.modal_state.get_view().max_width =>
+            {
+                self.modal_state.get_view_mut().max_height = new_height;
+                self.modal_state.get_view_mut().max_width = new_width;
+                let summary = self.modal_state.summarize();
+                self.broadcast(EditorServerMsg::ViewUpdated(
+                    *self.modal_state.get_view(),
+                    summary,
+                ));
+            }
+            EditorServerReq::StylizeInitEvent => {
+                let summary = self.modal_state.summarize();
+                self.broadcast(EditorServerMsg::StylizeInit(summary));
+            }
+            EditorServerReq::StylizeEvent(start, end, style) => {
+                let summary = self.modal_state.summarize();
+                self.broadcast(EditorServerMsg::Stylize(start, end, style, summary));
+            }
+            EditorServerReq::StylizeBatchEvent(styles) => {
+                let summary = self.modal_state.summarize();
+                self.broadcast(EditorServerMsg::StylizeBatch(styles, summary));
+            }
+            EditorServerReq::StylizeEndEvent => {
+                let summary = self.modal_state.summarize();
+                self.broadcast(EditorServerMsg::StylizeEnd(summary));
+            }
+            EditorServerReq::ListThemes => {
+                self.broadcast(EditorServerMsg::ListThemesRequested);
+            }
+            EditorServerReq::ThemesListed(theme_names) => {
+                self.modal_state.set_themes(theme_names.clone());
+                self.broadcast(EditorServerMsg::ListThemesResponse(theme_names));
+            }
+            EditorServerReq::GetDocumentText(doc_id) => {
+                let text = self
+                    .modal_state
+                    .get_doc(doc_id)
+                    .map(|doc| doc.get_buf().to_string());
+                self.broadcast(EditorServerMsg::DocumentText(doc_id, text));
+            }
+            EditorServerReq::GetEventLogSince(seq) => {
+                let events = self.modal_state.events_since(seq).to_vec();
+                self.broadcast(EditorServerMsg::EventLog(seq, events));
+            }
+            EditorServerReq::GetTransactionLog => {
+                let log = self.modal_state.transaction_log();
+                self.broadcast(EditorServerMsg::TransactionLog(log));
+            }
+            EditorServerReq::Shutdown => {
+                return false;
+            }
+            _ => {}
+        };
+        return true;
+    }
+
+    /// Merges the consecutive stylize requests into batches, so that they are broadcast along
+    /// with a single state summary.
+    fn coalesce_reqs(reqs: impl IntoIterator<Item = EditorServerReq>) -> Vec<EditorServerReq> {
+        let mut coalesced = vec![];
+        for req in reqs {
+            match (coalesced.last_mut(), req) {
+                (
+                    Some(EditorServerReq::StylizeBatchEvent(styles)),
+                    EditorServerReq::StylizeEvent(start, end, style),
+                ) => styles.push((start, end, style)),
+                (
+                    Some(EditorServerReq::StylizeBatchEvent(styles)),
+                    EditorServerReq::StylizeBatchEvent(other_styles),
+                ) => styles.extend(other_styles),
+                (_, EditorServerReq::StylizeEvent(start, end, style)) => coalesced.push(
+                    EditorServerReq::StylizeBatchEvent(vec![(start, end, style)]),
+                ),
+                (_, req) => coalesced.push(req),
+            }
+        }
+        coalesced
+    }
+
+    /// Handles the requests that are still in the queue and notifies the connections that the
+    /// server is quitting.
+    pub fn shutdown(&mut self) {
+        while let Ok(req) = self.incoming_channel_rcv.try_recv() {
+            self.handle_req(req);
+        }
+        self.broadcast(EditorServerMsg::QuitRequested);
+        println!("EditorServer: quitting");
+    }
+
     pub fn run(mut self) -> std::thread::JoinHandle<()> {
         std::thread::spawn(move || {
             println!("EditorServer: started");
             loop {
-                if let Ok(req) = self.incoming_channel_rcv.recv() {
-                    match req {
-                        EditorServerReq::UIEvent(evt) => {
-                            self.modal_state.receive_key(evt);
-                            match self.modal_state.update() {
-                                Ok(results) => {
-                                    let should_continue = self.handle_editor_results(results);
-                                    if !should_continue {
-                                        break;
-                                    }
-                                }
-                                Err(err) => {
-                                    self.broadcast(EditorServerMsg::ErrorThrown(err));
-                                }
-                            }
-                            self.modal_state.update_view();
-                        }
-                        EditorServerReq::UpdateViewEvent(new_width, new_height)
-                            if new_height != self.modal_state.get_view().max_height
-                                || new_width != self.modal_state.get_view().max_width =>
-                        {
-                            self.modal_state.get_view_mut().max_height = new_height;
-                            self.modal_state.get_view_mut().max_width = new_width;
-                            let summary = self.modal_state.summarize();
-                            self.broadcast(EditorServerMsg::ViewUpdated(
-                                *self.modal_state.get_view(),
-                                summary,
-                            ));
-                        }
-                        EditorServerReq::StylizeInitEvent => {
-                            let summary = self.modal_state.summarize();
-                            self.broadcast(EditorServerMsg::StylizeInit(summary));
-                        }
-                        EditorServerReq::StylizeEvent(start, end, style) => {
-                            let summary = self.modal_state.summarize();
-                            self.broadcast(EditorServerMsg::Stylize(start, end, style, summary));
-                        }
-                        EditorServerReq::StylizeEndEvent => {
-                            let summary = self.modal_state.summarize();
-                            self.broadcast(EditorServerMsg::StylizeEnd(summary));
-                        }
-                        _ => {}
-                    };
+                // Block only while the queue is empty, then handle all the queued requests at
+                // once.
+                let Ok(first_req) = self.incoming_channel_rcv.recv() else {
+                    continue;
+                };
+                let reqs = std::iter::once(first_req)
+                    .chain(self.incoming_channel_rcv.try_iter())
+                    .collect_vec();
+                // The requests after a shutdown request are still handled, like the rest of the
+                // queue on shutdown.
+                let mut should_shutdown = false;
+                for req in Self::coalesce_reqs(reqs) {
+                    should_shutdown |= !self.handle_req(req);
+                }
+                if should_shutdown {
+                    self.shutdown();
+                    break;
                 }
             }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use macros::{action_generator, tx_generator, BasicEditorMode};
+
+    use crate::{
+        document::{
+            primitive_mods::{BufMod, PrimitiveMod},
+            DocumentMap, Transaction,
+        },
+        editor::{
+            editor_mode::{InsertMode, NormalMode, TriggerHandler},
+            test_utils::{test_editor, TestServer},
+            ActionGenerator, EditorAction, EditorCmd, HistoricalEditorState, TransactionGenerator,
+        },
+        events::KeyCombo,
+    };
+
+    use super::*;
+
+    #[test]
+    fn document_text_request() {
+        let server = TestServer::run(test_editor());
+        server.send_keys("iab");
+        assert_eq!(server.text(0), Some("ab".to_string()));
+        assert_eq!(server.text(1), None);
+    }
+
+    #[test]
+    fn exported_transaction_log() {
+        let init_state = HistoricalEditorState::from(DocumentMap::default().with_event_log());
+        let server = TestServer::run(
+            ModalEditor::new(init_state, NormalMode::id())
+                .with_mode(Box::new(NormalMode::new()))
+                .with_mode(Box::new(InsertMode::new())),
+        );
+        server.send_keys("iab");
+        let replayed = HistoricalEditorState::from_log(
+            DocumentMap::default(),
+            server.conn().get_transaction_log(),
+        );
+        assert_eq!(
+            replayed
+                .doc_map
+                .get(&0)
+                .map(|doc| doc.get_buf().to_string()),
+            server.text(0)
+        );
+    }
+
+    #[test]
+    fn disconnected_connection() {
+        let mut editor_server = EditorServer::new(test_editor());
+        drop(editor_server.new_connection());
+        // The server keeps serving the other connections.
+        let server = TestServer::run_server(editor_server);
+        server.send_keys("ia");
+        assert_eq!(server.text(0), Some("a".to_string()));
+    }
+
+    #[action_generator]
+    fn insert(_args: &[&str], _state: &EditorStateSummary) -> Option<EditorAction> {
+        Some(
+            [EditorCmd::PushMode(InsertMode::id())]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    #[tx_generator]
+    fn insert_x(_: &KeyCombo, doc_map: &DocumentMap) -> Option<Transaction> {
+        Some(Transaction::new().with_mod(PrimitiveMod::Text(
+            doc_map.curr_doc_id(),
+            BufMod::InsText(0, "x".into()),
+        )))
+    }
+
+    #[derive(BasicEditorMode)]
+    struct DeclarativeMode {
+        trigger_handler: TriggerHandler,
+        #[handler(key = "x")]
+        #[handler(key = "<C-x>y")]
+        insert_x: TransactionGenerator,
+        #[handler(key = "gi")]
+        insert: ActionGenerator,
+    }
+
+    #[test]
+    fn declarative_mode() {
+        let server = TestServer::run(
+            ModalEditor::new(DocumentMap::default().into(), DeclarativeMode::id())
+                .with_mode(Box::new(DeclarativeMode::new()))
+                .with_mode(Box::new(InsertMode::new())),
+        );
+        server.send_keys("x\x18ygia");
+        assert_eq!(server.text(0), Some("axx".to_string()));
+    }
+
+    #[test]
+    fn stylize_batching() {
+        let mut server = EditorServer::new(test_editor());
+        let conn = server.new_connection();
+        // Queue the requests before the server starts, so that they are handled as one batch.
+        let style = ConcreteStyle::default();
+        conn.send_req(EditorServerReq::StylizeInitEvent);
+        for start in 0..3 {
+            conn.send_req(EditorServerReq::StylizeEvent(start, start + 1, style));
+        }
+        conn.send_req(EditorServerReq::StylizeEndEvent);
+        conn.send_req(EditorServerReq::Shutdown);
+        server.run().join().unwrap();
+        assert!(matches!(
+            conn.receive_msg(),
+            Ok(EditorServerMsg::StylizeInit(_))
+        ));
+        assert!(matches!(
+            conn.receive_msg(),
+            Ok(EditorServerMsg::StylizeBatch(styles, _)) if styles.len() == 3
+        ));
+        assert!(matches!(
+            conn.receive_msg(),
+            Ok(EditorServerMsg::StylizeEnd(_))
+        ));
+        assert!(matches!(
+            conn.receive_msg(),
+            Ok(EditorServerMsg::QuitRequested)
+        ));
+    }
+
+    #[test]
+    fn filtered_connection() {
+        let mut server = EditorServer::new(test_editor());
+        let conn = server
+            .new_filtered_connection(|msg| matches!(msg, EditorServerMsg::ListThemesResponse(_)));
+        let server_handle = server.run();
+        conn.send_req(EditorServerReq::UpdateViewEvent(80, 24));
+        conn.send_req(EditorServerReq::ThemesListed(vec!["theme".to_string()]));
+        conn.send_req(EditorServerReq::Shutdown);
+        server_handle.join().unwrap();
+        assert!(matches!(
+            conn.try_receive_msg(),
+            Ok(EditorServerMsg::ListThemesResponse(_))
+        ));
+        assert!(matches!(
+            conn.try_receive_msg(),
+            Ok(EditorServerMsg::QuitRequested)
+        ));
+        assert!(conn.try_receive_msg().is_err());
+    }
+
+    #[cfg(feature = "tokio-api")]
+    #[tokio::test]
+    async fn async_connection_round_trip() {
+        let mut server = EditorServer::new(test_editor());
+        let mut conn = server.new_async_connection();
+        let server_handle = server.run();
+        for ch in ['i', 'a'] {
+            conn.send_req(EditorServerReq::UIEvent(KeyEvt::Char(
+                ch,
+                crate::events::KeyMods::NONE,
+            )))
+            .await;
+        }
+        let mut text = String::new();
+        while text != "a" {
+            if let EditorServerMsg::EditorResult(_, state) = conn.receive_msg().await.unwrap() {
+                text = state.curr_doc.get_buf().to_string();
+            }
+        }
+        conn.send_req(EditorServerReq::Shutdown).await;
+        while let Some(msg) = conn.receive_msg().await {
+            if let EditorServerMsg::QuitRequested = msg {
+                break;
+            }
+        }
+        server_handle.join().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::{
+    cursor::{selection_range, SelectionIterator},
+    document::{
+        primitive_mods::{BufMod, PrimitiveMod, SelectionMod},
+        DocumentMap, Transaction,
+    },
+    events::{KeyCombo, KeyEvt, KeyMods},
+};
+
+/// Holds the yanked texts. Yanking into a named register also fills the unnamed one, so that the
+/// plain paste always uses the most recently yanked text.
+#[derive(Clone, Debug, Default)]
+pub struct RegisterBank {
+    unnamed: String,
+    named: HashMap<char, String>,
+}
+
+impl RegisterBank {
+    /// Returns the contents of the given register, or of the unnamed register if none is given.
+    /// Registers that were never written to are empty.
+    pub fn get(&self, name: Option<char>) -> &str {
+        match name {
+            Some(name) => self.named.get(&name).map(|text| text.as_str()),
+            None => Some(self.unnamed.as_str()),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Replaces the contents of the given register, or of the unnamed register if none is given.
+    pub fn set(&mut self, name: Option<char>, text: String) {
+        if let Some(name) = name {
+            self.named.insert(name, text.clone());
+        }
+        self.unnamed = text;
+    }
+
+    /// Returns the register that the given combo is qualified with, e.g., `a` for `"ay`.
+    pub fn register_of(combo: &KeyCombo) -> Option<char> {
+        match combo.0.as_slice() {
+            [KeyEvt::Char('"', KeyMods::NONE), KeyEvt::Char(name, KeyMods::NONE), _, ..] => {
+                Some(*name)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the text of the selections of the current document, where the overlapping selections
+/// are merged, joined by newlines.
+pub fn yank_sels(doc_map: &DocumentMap) -> Option<String> {
+    let doc = doc_map.get_curr_doc()?;
+    let buf = doc.get_buf();
+    Some(
+        doc.selections
+            .values()
+            .cloned()
+            .collect_merged(buf)
+            .into_iter()
+            .filter_map(|(start, end)| buf.get_slice(start..end).map(|slice| slice.to_string()))
+            .join("\n"),
+    )
+}
+
+/// Inserts the text at each selection of the current document, at the position returned for
+/// the selection range, and selects the inserted text.
+fn paste_at(
+    text: &str,
+    doc_map: &DocumentMap,
+    position: impl Fn((usize, usize)) -> usize,
+) -> Option<Transaction> {
+    if text.is_empty() {
+        return None;
+    }
+    let doc_id = doc_map.curr_doc_id();
+    let doc = doc_map.get_curr_doc()?;
+    let buf = doc.get_buf();
+    let text_len = text.chars().count();
+    let mut modification = Transaction::new();
+    doc.selections
+        .iter()
+        .map(|(sel_id, sel)| (sel_id, position(selection_range(sel, buf))))
+        .sorted_by_key(|(_, insert_idx)| *insert_idx)
+        .for_each(|(sel_id, insert_idx)| {
+            let insert_idx = modification.map_char_idx(&doc_id, &insert_idx).unwrap_or(0);
+            modification.append_mods([
+                PrimitiveMod::Text(doc_id, BufMod::InsText(insert_idx, text.to_string())),
+                PrimitiveMod::Sel(
+                    doc_id,
+                    *sel_id,
+                    SelectionMod::SetHead(insert_idx + text_len - 1),
+                ),
+                PrimitiveMod::Sel(doc_id, *sel_id, SelectionMod::SetTail(Some(insert_idx))),
+            ]);
+        });
+    Some(modification)
+}
+
+/// Inserts the text after each selection of the current document.
+pub fn paste_after(text: &str, doc_map: &DocumentMap) -> Option<Transaction> {
+    paste_at(text, doc_map, |(_, end)| end)
+}
+
+/// Inserts the text before each selection of the current document.
+pub fn paste_before(text: &str, doc_map: &DocumentMap) -> Option<Transaction> {
+    paste_at(text, doc_map, |(start, _)| start)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cursor::TextSelection, document::primitive_mods::DocMapMod};
+
+    use super::*;
+
+    #[test]
+    fn yank_and_paste() {
+        let mut doc_map = DocumentMap::default();
+        let setup = Transaction::new()
+            .with_mod(PrimitiveMod::Text(0, BufMod::InsText(0, "abc def".into())))
+            .with_mod(PrimitiveMod::Sel(0, 0, SelectionMod::SetTail(Some(1))))
+            .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateSel(
+                0,
+                1,
+                TextSelection(6, Some(4)),
+            )));
+        assert!(setup.apply_tx(&mut doc_map).is_some());
+        let mut registers = RegisterBank::default();
+        registers.set(Some('a'), yank_sels(&doc_map).unwrap());
+        assert_eq!(registers.get(Some('a')), "ab\ndef");
+        assert_eq!(registers.get(None), "ab\ndef");
+        assert_eq!(registers.get(Some('b')), "");
+        assert!(paste_after("X", &doc_map)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .is_some());
+        let doc = doc_map.get_curr_doc().unwrap();
+        assert_eq!(doc.get_buf().to_string(), "abXc defX");
+        assert_eq!(doc.selections[&1].0, 8);
+        assert!(paste_before("Y", &doc_map)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .is_some());
+        assert_eq!(
+            doc_map.get_curr_doc().unwrap().get_buf().to_string(),
+            "abYXc defYX"
+        );
+    }
+
+    #[test]
+    fn qualified_combo() {
+        let combo = |s: &str| s.chars().map(|c| KeyEvt::Char(c, KeyMods::NONE)).collect();
+        assert_eq!(RegisterBank::register_of(&combo("\"ay")), Some('a'));
+        assert_eq!(RegisterBank::register_of(&combo("y")), None);
+        assert_eq!(RegisterBank::register_of(&combo("\"a")), None);
+    }
+}
@@ -0,0 +1,156 @@
+use ropey::Rope;
+
+use crate::{
+    cursor::GraphemeIterable,
+    document::{
+        primitive_mods::{PrimitiveMod, SelectionMod},
+        DocumentMap, Transaction,
+    },
+};
+
+/// The last confirmed search, whose matches `n` and `N` move between.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchState {
+    pub pattern: String,
+    /// True iff the search went backward, i.e., `?`, in which case `n` moves backward as well.
+    pub backward: bool,
+}
+
+/// Returns the starts of the occurrences of the pattern in order. Only the occurrences that start
+/// at a grapheme boundary are matched.
+pub fn match_starts(pattern: &str, buf: &Rope) -> Vec<usize> {
+    let pattern_len = pattern.chars().count();
+    if pattern_len == 0 {
+        return vec![];
+    }
+    let mut starts = vec![];
+    let mut char_idx = 0;
+    for g in buf.graphemes(0) {
+        if buf.len_chars() - char_idx < pattern_len {
+            break;
+        }
+        if buf
+            .chars_at(char_idx)
+            .zip(pattern.chars())
+            .all(|(a, b)| a == b)
+        {
+            starts.push(char_idx);
+        }
+        char_idx += g.chars().count();
+    }
+    starts
+}
+
+/// Returns the start of the first match after the given char, or the last one before it if
+/// `backward` is set, wrapping around the buffer.
+pub fn next_match(pattern: &str, from: usize, backward: bool, buf: &Rope) -> Option<usize> {
+    let starts = match_starts(pattern, buf);
+    if backward {
+        starts
+            .iter()
+            .rev()
+            .find(|start| **start < from)
+            .or(starts.last())
+            .copied()
+    } else {
+        starts
+            .iter()
+            .find(|start| **start > from)
+            .or(starts.first())
+            .copied()
+    }
+}
+
+/// Returns the transaction that moves each of the given heads of the document, keyed by their
+/// selection, to the next match of the pattern and collapses their selection. Returns none if
+/// there are no matches.
+pub fn move_heads_to_matches(
+    doc_id: usize,
+    heads: impl IntoIterator<Item = (usize, usize)>,
+    pattern: &str,
+    backward: bool,
+    buf: &Rope,
+) -> Option<Transaction> {
+    let mods = heads
+        .into_iter()
+        .map(|(sel_id, head)| {
+            next_match(pattern, head, backward, buf).map(|start| {
+                [
+                    PrimitiveMod::Sel(doc_id, sel_id, SelectionMod::SetHead(start)),
+                    PrimitiveMod::Sel(doc_id, sel_id, SelectionMod::SetTail(None)),
+                ]
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Transaction::new().with_mods(mods.into_iter().flatten()))
+}
+
+/// Moves the heads of the current document to the next match of the search, or the previous one
+/// if `reverse` is set.
+pub fn repeat_search(
+    search: &SearchState,
+    reverse: bool,
+    doc_map: &DocumentMap,
+) -> Option<Transaction> {
+    let doc = doc_map.get_curr_doc()?;
+    move_heads_to_matches(
+        doc_map.curr_doc_id(),
+        doc.selections.iter().map(|(sel_id, sel)| (*sel_id, sel.0)),
+        &search.pattern,
+        search.backward != reverse,
+        doc.get_buf(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::document::primitive_mods::BufMod;
+
+    use super::*;
+
+    #[test]
+    fn wrapping_matches() {
+        let buf = Rope::from_str("ab e\u{301}b ab");
+        assert_eq!(match_starts("ab", &buf), vec![0, 7]);
+        // The combining accent belongs to the grapheme of the `e`.
+        assert!(match_starts("\u{301}b", &buf).is_empty());
+        assert_eq!(next_match("ab", 0, false, &buf), Some(7));
+        assert_eq!(next_match("ab", 7, false, &buf), Some(0));
+        assert_eq!(next_match("ab", 7, true, &buf), Some(0));
+        assert_eq!(next_match("ab", 0, true, &buf), Some(7));
+        assert_eq!(next_match("x", 0, false, &buf), None);
+    }
+
+    #[test]
+    fn repeated_search() {
+        let mut doc_map = DocumentMap::default();
+        let setup = Transaction::new()
+            .with_mod(PrimitiveMod::Text(0, BufMod::InsText(0, "ab ab ab".into())))
+            .with_mod(PrimitiveMod::Sel(0, 0, SelectionMod::SetHead(0)));
+        assert!(setup.apply_tx(&mut doc_map).is_some());
+        let search = SearchState {
+            pattern: "ab".into(),
+            backward: false,
+        };
+        let head = |doc_map: &DocumentMap| doc_map.get_curr_doc().unwrap().selections[&0].0;
+        repeat_search(&search, false, &doc_map)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .unwrap();
+        assert_eq!(head(&doc_map), 3);
+        repeat_search(&search, true, &doc_map)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .unwrap();
+        assert_eq!(head(&doc_map), 0);
+        assert!(repeat_search(
+            &SearchState {
+                pattern: "x".into(),
+                backward: false,
+            },
+            false,
+            &doc_map
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cursor::TextSelection,
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod},
+        Document, DocumentMap, Transaction,
+    },
+};
+
+/// Represents a document of a session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionDoc {
+    pub path: String,
+    /// The position of the head of the primary selection.
+    pub cursor: usize,
+}
+
+/// Represents the open files of the editor, which can be saved and restored later.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// The index of the current document in `docs`.
+    pub curr_doc: Option<usize>,
+    pub docs: Vec<SessionDoc>,
+}
+
+impl Session {
+    /// Creates a session from the documents that are read from files, ordered by their ids.
+    pub fn from_doc_map(doc_map: &DocumentMap) -> Self {
+        let docs = doc_map
+            .iter()
+            .sorted_by_key(|(doc_id, _)| *doc_id)
+            .filter_map(|(doc_id, doc)| {
+                let cursor = doc.selections.get(&0).map(|sel| sel.0).unwrap_or(0);
+                doc.source.path().map(|path| {
+                    (
+                        doc_id,
+                        SessionDoc {
+                            path: path.to_string(),
+                            cursor,
+                        },
+                    )
+                })
+            })
+            .collect_vec();
+        Session {
+            curr_doc: docs
+                .iter()
+                .position(|(doc_id, _)| *doc_id == doc_map.curr_doc_id()),
+            docs: docs.into_iter().map(|(_, doc)| doc).collect(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let contents = toml::to_string(self).map_err(|err| err.to_string())?;
+        std::fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        toml::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    /// Returns the transaction that opens the documents of the session in the given document map
+    /// and switches to the current one. Fails if a file of the session cannot be read anymore,
+    /// instead of opening it as an unnamed document.
+    pub fn restore_tx(&self, doc_map: &DocumentMap) -> Result<Transaction, String> {
+        let first_doc_id = doc_map.get_unused_id();
+        let docs = self
+            .docs
+            .iter()
+            .map(|session_doc| {
+                std::fs::metadata(&session_doc.path)
+                    .map_err(|err| format!("cannot open {}: {}", session_doc.path, err))?;
+                let mut doc = Document::new_from_file(&session_doc.path);
+                let cursor = std::cmp::min(session_doc.cursor, doc.get_buf().len_chars());
+                doc.selections.insert(0, TextSelection(cursor, None));
+                Ok(PrimitiveMod::DocMap(DocMapMod::CreateDoc(doc)))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut tx = Transaction::new().with_mods(docs);
+        if let Some(curr_doc) = self.curr_doc.filter(|idx| *idx < self.docs.len()) {
+            tx.append_mod(PrimitiveMod::DocMap(DocMapMod::SwitchDoc(
+                first_doc_id + curr_doc,
+            )));
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_round_trip() {
+        let dir = std::env::temp_dir().join(format!("yaz-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        std::fs::write(file_path("a.txt"), "abc").unwrap();
+        std::fs::write(file_path("b.txt"), "defgh").unwrap();
+        let session = Session {
+            curr_doc: Some(1),
+            docs: vec![
+                SessionDoc {
+                    path: file_path("a.txt"),
+                    cursor: 1,
+                },
+                SessionDoc {
+                    path: file_path("b.txt"),
+                    cursor: 4,
+                },
+            ],
+        };
+        session.save(&file_path("session.toml")).unwrap();
+        assert_eq!(
+            Session::load(&file_path("session.toml")),
+            Ok(session.clone())
+        );
+        // Restore the session next to the unnamed document.
+        let mut doc_map = DocumentMap::default();
+        assert!(session
+            .restore_tx(&doc_map)
+            .unwrap()
+            .apply_tx(&mut doc_map)
+            .is_some());
+        assert_eq!(doc_map.curr_doc_id(), 2);
+        assert_eq!(
+            doc_map.get_curr_doc().unwrap().get_buf().to_string(),
+            "defgh"
+        );
+        assert_eq!(Session::from_doc_map(&doc_map), session);
+        // The files that are removed since are not restored as unnamed documents.
+        std::fs::remove_file(file_path("b.txt")).unwrap();
+        assert!(session.restore_tx(&doc_map).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::thread::JoinHandle;
+
+use crate::{
+    document::DocumentMap,
+    editor::{
+        editor_mode::{InsertMode, NormalMode},
+        editor_server::{EditorConnection, EditorServer, EditorServerReq},
+        ModalEditor,
+    },
+    events::{Key, KeyEvt, KeyMods},
+};
+
+/// Returns an editor on an empty document that starts in the normal mode, with the normal and
+/// insert modes registered. The other modes can be registered with `ModalEditor::with_mode`.
+pub fn test_editor() -> ModalEditor {
+    ModalEditor::new(DocumentMap::default().into(), NormalMode::id())
+        .with_mode(Box::new(NormalMode::new()))
+        .with_mode(Box::new(InsertMode::new()))
+}
+
+/// Runs an editor on a server for the duration of a test, and shuts it down when dropped.
+pub struct TestServer {
+    conn: EditorConnection,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn run(editor: ModalEditor) -> Self {
+        Self::run_server(EditorServer::new(editor))
+    }
+
+    pub fn run_server(mut server: EditorServer) -> Self {
+        let conn = server.new_connection();
+        TestServer {
+            conn,
+            handle: Some(server.run()),
+        }
+    }
+
+    pub fn conn(&self) -> &EditorConnection {
+        &self.conn
+    }
+
+    /// Sends the given keys, where `\x1b` is Esc, `\n` and `\r` are Enter, `\t` is Tab, `\x7f` is
+    /// Backspace and the other control chars are the letters with Ctrl, e.g., `\x16` for Ctrl+V.
+    pub fn send_keys(&self, keys: &str) {
+        for ch in keys.chars() {
+            let evt = match ch {
+                '\x1b' => KeyEvt::Key(Key::Esc, KeyMods::NONE),
+                '\n' | '\r' => KeyEvt::Key(Key::Enter, KeyMods::NONE),
+                '\t' => KeyEvt::Key(Key::Tab, KeyMods::NONE),
+                '\x7f' => KeyEvt::Key(Key::Backspace, KeyMods::NONE),
+                '\x01'..='\x1a' => KeyEvt::Char((ch as u8 - 1 + b'a') as char, KeyMods::CTRL),
+                _ => KeyEvt::Char(ch, KeyMods::NONE),
+            };
+            self.conn.send_req(EditorServerReq::UIEvent(evt));
+        }
+    }
+
+    pub fn text(&self, doc_id: usize) -> Option<String> {
+        self.conn.request_document_text(doc_id)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.conn.send_req(EditorServerReq::Shutdown);
+        if let Some(handle) = self.handle.take() {
+            // Do not panic again if the test already failed.
+            let result = handle.join();
+            if !std::thread::panicking() {
+                result.unwrap();
+            }
+        }
+    }
+}
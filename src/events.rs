@@ -1,8 +1,11 @@
+use std::{iter::Peekable, str::FromStr};
+
 use bitflags::bitflags;
 use itertools::Itertools;
+use serde::Serialize;
 
 bitflags! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
     pub struct KeyMods: usize {
         const NONE = 0;
         const CTRL = 1;
@@ -11,7 +14,7 @@ bitflags! {
     }
 }
 /// A non-character key on the keyboard
-#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Serialize)]
 pub enum Key {
     Enter,
     Tab,
@@ -44,13 +47,45 @@ pub enum Key {
     F12,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+impl KeyMods {
+    /// Returns the modifiers in the form of a `<C-...>` prefix, e.g., `C-S-`.
+    fn to_display_prefix(self) -> String {
+        [
+            (KeyMods::CTRL, "C-"),
+            (KeyMods::ALT, "A-"),
+            (KeyMods::SHIFT, "S-"),
+        ]
+        .into_iter()
+        .filter(|(mods, _)| self.contains(*mods))
+        .map(|(_, prefix)| prefix)
+        .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
 pub enum KeyEvt {
     Char(char, KeyMods),
     Key(Key, KeyMods),
 }
 
-#[derive(Clone, Default, Debug, Eq, PartialEq)]
+impl KeyEvt {
+    /// Returns a human-readable description of the key event, e.g., `a`, `<C-a>` or `<Enter>`.
+    pub fn to_display_string(self) -> String {
+        match self {
+            KeyEvt::Char(' ', mods) => format!("<{}Space>", mods.to_display_prefix()),
+            KeyEvt::Char('<', mods) => format!("<{}lt>", mods.to_display_prefix()),
+            KeyEvt::Char(c, mods) if mods.is_empty() => c.to_string(),
+            KeyEvt::Char(c, mods) => format!("<{}{}>", mods.to_display_prefix(), c),
+            KeyEvt::Key(key, mods) => format!("<{}{:?}>", mods.to_display_prefix(), key),
+        }
+    }
+}
+
+/// The largest count that a command can be repeated with, so that a mistyped count does not
+/// hang the editor on a large buffer.
+pub const MAX_COUNT: usize = 10_000;
+
+#[derive(Clone, Default, Debug, Eq, PartialEq, Serialize)]
 pub struct KeyCombo(pub Vec<KeyEvt>);
 
 impl FromIterator<KeyEvt> for KeyCombo {
@@ -78,38 +113,10 @@ impl KeyCombo {
         self.0.len()
     }
 
-    pub fn starts_with(&self, other: impl IntoIterator<Item = KeyEvt>) -> bool {
-        self.0.starts_with(&other.into_iter().collect_vec())
-    }
-
     pub fn ends_with(&self, other: impl IntoIterator<Item = KeyEvt>) -> bool {
         self.0.ends_with(&other.into_iter().collect_vec())
     }
 
-    pub fn pop_first(&mut self) -> Option<KeyEvt> {
-        if self.is_empty() {
-            None
-        } else {
-            Some(self.0.remove(0))
-        }
-    }
-
-    pub fn first(&self) -> Option<&KeyEvt> {
-        self.0.first()
-    }
-
-    pub fn first_matches<F: FnOnce(&KeyEvt) -> bool>(&self, pred: F) -> bool {
-        self.0.first().map(|k| pred(k)).unwrap_or(false)
-    }
-
-    pub fn pop_first_if<F: FnOnce(&KeyEvt) -> bool>(&mut self, pred: F) -> Option<KeyEvt> {
-        if self.first_matches(pred) {
-            self.pop_first()
-        } else {
-            None
-        }
-    }
-
     pub fn add(&mut self, evt: KeyEvt) {
         self.0.push(evt);
     }
@@ -120,6 +127,40 @@ impl KeyCombo {
         ret
     }
 
+    /// Returns a human-readable description of the combo, e.g., `g<C-a><Enter>`.
+    pub fn to_display_string(&self) -> String {
+        self.0.iter().map(|evt| evt.to_display_string()).collect()
+    }
+
+    /// Returns the number formed by the digits in the combo, e.g., `12` for `g12l`.
+    pub fn extract_number(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .filter_map(|key_evt| match key_evt {
+                KeyEvt::Char(c, KeyMods::NONE) if c.is_ascii_digit() => Some(*c),
+                _ => None,
+            })
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    /// Returns the count that the combo starts with, e.g., `3` for `3j`. A missing or zero count
+    /// is one, and the count is clamped to `MAX_COUNT`.
+    pub fn extract_count(&self) -> usize {
+        self.0
+            .iter()
+            .map_while(|key_evt| match key_evt {
+                KeyEvt::Char(c, KeyMods::NONE) => c.to_digit(10),
+                _ => None,
+            })
+            // Saturate while accumulating, so that overflowing counts are capped as well.
+            .fold(0, |count: usize, digit| {
+                count.saturating_mul(10).saturating_add(digit as usize)
+            })
+            .clamp(1, MAX_COUNT)
+    }
+
     pub fn extract_text(&self) -> String {
         self.0
             .iter()
@@ -133,7 +174,7 @@ impl KeyCombo {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum KeyMatcher {
     Exact(KeyEvt),
     Number(KeyMods),
@@ -144,73 +185,80 @@ pub enum KeyMatcher {
 }
 
 impl KeyMatcher {
-    pub fn try_consume(&self, kc: &mut KeyCombo) -> Vec<KeyEvt> {
+    /// Returns a human-readable description of the matcher. The matchers that match a class of
+    /// key events are displayed in angle brackets, e.g., `<char>` or `<C-num>`. The characters
+    /// that delimit the alternatives of a clause are escaped, e.g., `<[>`.
+    pub fn to_display_string(self) -> String {
         match self {
-            KeyMatcher::Exact(evt) => {
-                if let Some(k) = kc.pop_first_if(|k| k == evt) {
-                    return vec![k];
-                }
-            }
-            KeyMatcher::AnyChar(kmods) => {
-                if let Some(k) =
-                    kc.pop_first_if(|k| matches!(k, KeyEvt::Char(_, mods) if mods == kmods))
-                {
-                    return vec![k];
-                }
-            }
-            KeyMatcher::AnyKey(kmods) => {
-                if let Some(k) =
-                    kc.pop_first_if(|k| matches!(k, KeyEvt::Key(_, mods) if mods == kmods))
-                {
-                    return vec![k];
-                }
+            KeyMatcher::Exact(KeyEvt::Char(c @ ('[' | ']' | '|'), KeyMods::NONE)) => {
+                format!("<{}>", c)
             }
+            KeyMatcher::Exact(evt) => evt.to_display_string(),
+            KeyMatcher::Number(mods) => format!("<{}num>", mods.to_display_prefix()),
+            KeyMatcher::AnyChar(mods) => format!("<{}char>", mods.to_display_prefix()),
+            KeyMatcher::AnyKey(mods) => format!("<{}key>", mods.to_display_prefix()),
+            KeyMatcher::Digit(mods) => format!("<{}digit>", mods.to_display_prefix()),
+            KeyMatcher::Any => "<any>".to_string(),
+        }
+    }
+
+    /// Consumes the key events that the matcher matches from the front of the given events.
+    /// Returns the number of consumed events, which is zero iff the matcher does not match.
+    pub fn try_consume<'a>(&self, evts: &mut Peekable<impl Iterator<Item = &'a KeyEvt>>) -> usize {
+        let is_digit = |k: &KeyEvt, kmods: &KeyMods| matches!(k, KeyEvt::Char(c, mods) if mods == kmods && c.is_ascii_digit());
+        match self {
+            KeyMatcher::Exact(evt) => usize::from(evts.next_if(|k| *k == evt).is_some()),
+            KeyMatcher::AnyChar(kmods) => usize::from(
+                evts.next_if(|k| matches!(k, KeyEvt::Char(_, mods) if mods == kmods))
+                    .is_some(),
+            ),
+            KeyMatcher::AnyKey(kmods) => usize::from(
+                evts.next_if(|k| matches!(k, KeyEvt::Key(_, mods) if mods == kmods))
+                    .is_some(),
+            ),
             KeyMatcher::Number(kmods) => {
-                let mut num = vec![];
-                while kc.first_matches(|k| {
-                    if let KeyEvt::Char(c, mods) = k {
-                        mods == kmods && c.is_ascii_digit()
-                    } else {
-                        false
-                    }
-                }) {
-                    num.push(kc.pop_first().unwrap())
-                }
-                return num;
-            }
-            KeyMatcher::Digit(kmods) => {
-                if let Some(k) = kc.pop_first_if(|k| {
-                    if let KeyEvt::Char(c, mods) = k {
-                        mods == kmods && c.is_ascii_digit()
-                    } else {
-                        false
-                    }
-                }) {
-                    return vec![k];
-                }
-            }
-            KeyMatcher::Any => {
-                if let Some(k) = kc.pop_first_if(|_| true) {
-                    return vec![k];
+                let mut num_digits = 0;
+                while evts.next_if(|k| is_digit(k, kmods)).is_some() {
+                    num_digits += 1;
                 }
+                num_digits
             }
+            KeyMatcher::Digit(kmods) => usize::from(evts.next_if(|k| is_digit(k, kmods)).is_some()),
+            KeyMatcher::Any => usize::from(evts.next().is_some()),
         }
-        return vec![];
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct KeyPatternClause(Vec<KeyMatcher>);
 
 impl KeyPatternClause {
-    pub fn try_consume(&self, kc: &mut KeyCombo) -> Vec<KeyEvt> {
+    /// Returns a human-readable description of the clause. Clauses with multiple alternatives
+    /// are displayed as `[a|b]`.
+    pub fn to_display_string(&self) -> String {
+        if self.0.len() == 1 {
+            self.0[0].to_display_string()
+        } else {
+            format!(
+                "[{}]",
+                self.0
+                    .iter()
+                    .map(|matcher| matcher.to_display_string())
+                    .join("|")
+            )
+        }
+    }
+
+    /// Consumes the key events with the first matcher that matches them. Returns the number of
+    /// consumed events, which is zero iff none of the matchers match.
+    pub fn try_consume<'a>(&self, evts: &mut Peekable<impl Iterator<Item = &'a KeyEvt>>) -> usize {
         for unit in &self.0 {
-            let consumed = unit.try_consume(kc);
-            if consumed.len() > 0 {
-                return consumed;
+            let num_consumed = unit.try_consume(evts);
+            if num_consumed > 0 {
+                return num_consumed;
             }
         }
-        return vec![];
+        0
     }
 }
 
@@ -230,18 +278,45 @@ impl IntoIterator for KeyPatternClause {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct KeyPattern(Vec<KeyPatternClause>);
 
 impl KeyPattern {
-    pub fn matches(&self, mut kc: KeyCombo) -> bool {
-        for clause in &self.0 {
-            let consumed = clause.try_consume(&mut kc);
-            if consumed.is_empty() {
-                return false;
-            }
-        }
-        return kc.is_empty();
+    /// Consumes the combo clause by clause until a clause fails. Returns the number of clauses
+    /// that consumed a part of the combo and whether the whole combo is consumed.
+    fn consume(&self, kc: &KeyCombo) -> (usize, bool) {
+        let mut evts = kc.0.iter().peekable();
+        let num_consumed = self
+            .0
+            .iter()
+            .take_while(|clause| clause.try_consume(&mut evts) > 0)
+            .count();
+        (num_consumed, evts.peek().is_none())
+    }
+
+    pub fn matches(&self, kc: KeyCombo) -> bool {
+        self.matches_ref(&kc)
+    }
+
+    /// Returns true iff the pattern matches the whole combo. Unlike `matches`, the combo is
+    /// neither moved nor copied.
+    pub fn matches_ref(&self, kc: &KeyCombo) -> bool {
+        let (num_consumed, is_consumed) = self.consume(kc);
+        num_consumed == self.0.len() && is_consumed
+    }
+
+    /// Returns a human-readable description of the pattern, e.g., `f<char>`.
+    pub fn to_display_string(&self) -> String {
+        self.0
+            .iter()
+            .map(|clause| clause.to_display_string())
+            .collect()
+    }
+
+    /// Returns true iff the given (possibly incomplete) combo matches the beginning of the
+    /// pattern, i.e., the pattern may still match once more keys are pressed.
+    pub fn is_prefix_of(&self, kc: &KeyCombo) -> bool {
+        self.consume(kc).1
     }
 }
 
@@ -260,3 +335,360 @@ impl IntoIterator for KeyPattern {
         self.0.into_iter()
     }
 }
+
+/// Denotes that a key event, combo or pattern could not be parsed from a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyParseError {
+    Empty,
+    /// An angle or square bracket is not closed.
+    Unclosed(String),
+    UnknownModifier(String),
+    UnknownKey(String),
+}
+
+impl std::fmt::Display for KeyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyParseError::Empty => f.write_str("empty key string"),
+            KeyParseError::Unclosed(s) => f.write_fmt(format_args!("unclosed bracket in `{}`", s)),
+            KeyParseError::UnknownModifier(s) => {
+                f.write_fmt(format_args!("unknown modifier `{}`", s))
+            }
+            KeyParseError::UnknownKey(s) => f.write_fmt(format_args!("unknown key `{}`", s)),
+        }
+    }
+}
+
+impl std::error::Error for KeyParseError {}
+
+impl Key {
+    /// Returns the key with the given case-insensitive name, e.g., `Enter` or `pageup`.
+    pub fn from_name(name: &str) -> Option<Key> {
+        let key = match name.to_lowercase().as_str() {
+            "enter" => Key::Enter,
+            "tab" => Key::Tab,
+            "backspace" => Key::Backspace,
+            "esc" => Key::Esc,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "ins" => Key::Ins,
+            "del" => Key::Del,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "pausebreak" => Key::PauseBreak,
+            "numpadcenter" => Key::NumpadCenter,
+            "f0" => Key::F0,
+            "f1" => Key::F1,
+            "f2" => Key::F2,
+            "f3" => Key::F3,
+            "f4" => Key::F4,
+            "f5" => Key::F5,
+            "f6" => Key::F6,
+            "f7" => Key::F7,
+            "f8" => Key::F8,
+            "f9" => Key::F9,
+            "f10" => Key::F10,
+            "f11" => Key::F11,
+            "f12" => Key::F12,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// Parses the contents of angle brackets, i.e., modifier prefixes followed by a key name, a
+/// single character or a matcher class, e.g., `C-S-a`, `Enter` or `num`.
+fn parse_angle_bracketed(s: &str) -> Result<KeyMatcher, KeyParseError> {
+    let mut mods = KeyMods::NONE;
+    let mut name = s;
+    while let Some((prefix, rest)) = name.split_once('-') {
+        if rest.is_empty() || prefix.chars().count() != 1 {
+            break;
+        }
+        mods |= match prefix {
+            "C" | "c" => KeyMods::CTRL,
+            "A" | "a" => KeyMods::ALT,
+            "S" | "s" => KeyMods::SHIFT,
+            _ => return Err(KeyParseError::UnknownModifier(prefix.to_string())),
+        };
+        name = rest;
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyMatcher::Exact(KeyEvt::Char(c, mods)));
+    }
+    let matcher = match name.to_lowercase().as_str() {
+        "space" => KeyMatcher::Exact(KeyEvt::Char(' ', mods)),
+        "lt" => KeyMatcher::Exact(KeyEvt::Char('<', mods)),
+        "num" => KeyMatcher::Number(mods),
+        "char" => KeyMatcher::AnyChar(mods),
+        "key" => KeyMatcher::AnyKey(mods),
+        "digit" => KeyMatcher::Digit(mods),
+        "any" if mods.is_empty() => KeyMatcher::Any,
+        _ => KeyMatcher::Exact(KeyEvt::Key(
+            Key::from_name(name).ok_or_else(|| KeyParseError::UnknownKey(s.to_string()))?,
+            mods,
+        )),
+    };
+    Ok(matcher)
+}
+
+/// Parses the matcher at the beginning of the given string and returns it along with the rest of
+/// the string.
+fn parse_matcher(s: &str) -> Result<(KeyMatcher, &str), KeyParseError> {
+    let mut chars = s.chars();
+    match chars.next() {
+        None => Err(KeyParseError::Empty),
+        Some('<') => {
+            // Skip the first character so that `<>>` denotes the `>` key.
+            let end = s
+                .char_indices()
+                .skip(2)
+                .find(|(_, c)| *c == '>')
+                .map(|(idx, _)| idx)
+                .ok_or_else(|| KeyParseError::Unclosed(s.to_string()))?;
+            Ok((parse_angle_bracketed(&s[1..end])?, &s[end + 1..]))
+        }
+        Some(c) => Ok((
+            KeyMatcher::Exact(KeyEvt::Char(c, KeyMods::NONE)),
+            chars.as_str(),
+        )),
+    }
+}
+
+/// Parses the key event at the beginning of the given string and returns it along with the rest
+/// of the string.
+fn parse_key_evt(s: &str) -> Result<(KeyEvt, &str), KeyParseError> {
+    match parse_matcher(s)? {
+        (KeyMatcher::Exact(evt), rest) => Ok((evt, rest)),
+        _ => Err(KeyParseError::UnknownKey(s.to_string())),
+    }
+}
+
+impl FromStr for KeyEvt {
+    type Err = KeyParseError;
+
+    /// Parses a key event either as a bare character, e.g., `a`, or in angle brackets with
+    /// optional modifiers, e.g., `<C-a>`, `<S-Up>` or `<Enter>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A lone `<` is not an unclosed bracket.
+        if s == "<" {
+            return Ok(KeyEvt::Char('<', KeyMods::NONE));
+        }
+        match parse_key_evt(s)? {
+            (evt, "") => Ok(evt),
+            _ => Err(KeyParseError::UnknownKey(s.to_string())),
+        }
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = KeyParseError;
+
+    /// Parses a sequence of key events, e.g., `g<C-a><Enter>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(KeyParseError::Empty);
+        }
+        let mut combo = KeyCombo::default();
+        let mut rest = s;
+        while !rest.is_empty() {
+            let (evt, new_rest) = parse_key_evt(rest)?;
+            combo.add(evt);
+            rest = new_rest;
+        }
+        Ok(combo)
+    }
+}
+
+impl FromStr for KeyPattern {
+    type Err = KeyParseError;
+
+    /// Parses a pattern in the format of `KeyPattern::to_display_string`, e.g., `g<num>` or
+    /// `[a|<C-char>]x`. Alternatives are given in square brackets, separated by `|`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(KeyParseError::Empty);
+        }
+        let mut clauses = vec![];
+        let mut rest = s;
+        while !rest.is_empty() {
+            let Some(mut alts_rest) = rest.strip_prefix('[') else {
+                let (matcher, new_rest) = parse_matcher(rest)?;
+                clauses.push(KeyPatternClause(vec![matcher]));
+                rest = new_rest;
+                continue;
+            };
+            let mut alts = vec![];
+            loop {
+                let (matcher, new_rest) = parse_matcher(alts_rest)
+                    .map_err(|_| KeyParseError::Unclosed(rest.to_string()))?;
+                alts.push(matcher);
+                let mut chars = new_rest.chars();
+                match chars.next() {
+                    Some('|') => alts_rest = chars.as_str(),
+                    Some(']') => {
+                        alts_rest = chars.as_str();
+                        break;
+                    }
+                    _ => return Err(KeyParseError::Unclosed(rest.to_string())),
+                }
+            }
+            clauses.push(KeyPatternClause(alts));
+            rest = alts_rest;
+        }
+        Ok(KeyPattern(clauses))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_prefix() {
+        let pattern: KeyPattern = [
+            [KeyMatcher::Exact(KeyEvt::Char('f', KeyMods::NONE))]
+                .into_iter()
+                .collect::<KeyPatternClause>(),
+            [KeyMatcher::AnyChar(KeyMods::NONE)].into_iter().collect(),
+        ]
+        .into_iter()
+        .collect();
+        let combo =
+            |s: &str| -> KeyCombo { s.chars().map(|c| KeyEvt::Char(c, KeyMods::NONE)).collect() };
+        assert!(pattern.is_prefix_of(&combo("")));
+        assert!(pattern.is_prefix_of(&combo("f")));
+        assert!(pattern.is_prefix_of(&combo("fx")));
+        assert!(!pattern.is_prefix_of(&combo("z")));
+        assert!(!pattern.is_prefix_of(&combo("fxy")));
+        assert!(!pattern.matches(combo("f")));
+        assert!(pattern.matches(combo("fx")));
+    }
+
+    #[test]
+    fn combo_count() {
+        let combo =
+            |s: &str| -> KeyCombo { s.chars().map(|c| KeyEvt::Char(c, KeyMods::NONE)).collect() };
+        assert_eq!(combo("12j").extract_count(), 12);
+        assert_eq!(combo("j").extract_count(), 1);
+        assert_eq!(combo("0j").extract_count(), 1);
+        assert_eq!(combo("f3").extract_count(), 1);
+        assert_eq!(combo("99999999999999999999999j").extract_count(), MAX_COUNT);
+        assert_eq!(combo("123456w").extract_count(), MAX_COUNT);
+    }
+
+    #[test]
+    fn display_strings() {
+        let combo = KeyCombo(vec![
+            KeyEvt::Char('a', KeyMods::NONE),
+            KeyEvt::Char('a', KeyMods::CTRL),
+            KeyEvt::Char('a', KeyMods::CTRL | KeyMods::SHIFT),
+            KeyEvt::Char(' ', KeyMods::NONE),
+            KeyEvt::Key(Key::Enter, KeyMods::NONE),
+            KeyEvt::Key(Key::F1, KeyMods::NONE),
+            KeyEvt::Key(Key::Up, KeyMods::ALT),
+        ]);
+        assert_eq!(
+            combo.to_display_string(),
+            "a<C-a><C-S-a><Space><Enter><F1><A-Up>"
+        );
+        let pattern: KeyPattern = [
+            [KeyMatcher::Exact(KeyEvt::Char('g', KeyMods::NONE))]
+                .into_iter()
+                .collect::<KeyPatternClause>(),
+            [
+                KeyMatcher::Number(KeyMods::NONE),
+                KeyMatcher::AnyChar(KeyMods::CTRL),
+            ]
+            .into_iter()
+            .collect(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(pattern.to_display_string(), "g[<num>|<C-char>]");
+    }
+
+    #[test]
+    fn parse_key_evts() {
+        let cases = [
+            ("a", KeyEvt::Char('a', KeyMods::NONE)),
+            ("<", KeyEvt::Char('<', KeyMods::NONE)),
+            (">", KeyEvt::Char('>', KeyMods::NONE)),
+            ("<lt>", KeyEvt::Char('<', KeyMods::NONE)),
+            ("<>>", KeyEvt::Char('>', KeyMods::NONE)),
+            ("<Space>", KeyEvt::Char(' ', KeyMods::NONE)),
+            ("<C-a>", KeyEvt::Char('a', KeyMods::CTRL)),
+            ("<A-a>", KeyEvt::Char('a', KeyMods::ALT)),
+            ("<C-S-a>", KeyEvt::Char('a', KeyMods::CTRL | KeyMods::SHIFT)),
+            ("<C-->", KeyEvt::Char('-', KeyMods::CTRL)),
+            ("<->", KeyEvt::Char('-', KeyMods::NONE)),
+            ("<S-Up>", KeyEvt::Key(Key::Up, KeyMods::SHIFT)),
+            ("<Enter>", KeyEvt::Key(Key::Enter, KeyMods::NONE)),
+            ("<enter>", KeyEvt::Key(Key::Enter, KeyMods::NONE)),
+            ("<Tab>", KeyEvt::Key(Key::Tab, KeyMods::NONE)),
+            ("<Esc>", KeyEvt::Key(Key::Esc, KeyMods::NONE)),
+            (
+                "<C-A-PageDown>",
+                KeyEvt::Key(Key::PageDown, KeyMods::CTRL | KeyMods::ALT),
+            ),
+        ];
+        for (s, evt) in cases {
+            assert_eq!(s.parse::<KeyEvt>(), Ok(evt), "{}", s);
+        }
+        let f_keys = [
+            Key::F1,
+            Key::F2,
+            Key::F3,
+            Key::F4,
+            Key::F5,
+            Key::F6,
+            Key::F7,
+            Key::F8,
+            Key::F9,
+            Key::F10,
+            Key::F11,
+            Key::F12,
+        ];
+        for (idx, key) in f_keys.into_iter().enumerate() {
+            assert_eq!(
+                format!("<F{}>", idx + 1).parse::<KeyEvt>(),
+                Ok(KeyEvt::Key(key, KeyMods::NONE))
+            );
+        }
+        let errors = [
+            ("", KeyParseError::Empty),
+            ("<C-a", KeyParseError::Unclosed("<C-a".to_string())),
+            ("<X-a>", KeyParseError::UnknownModifier("X".to_string())),
+            ("<Foo>", KeyParseError::UnknownKey("Foo".to_string())),
+            ("ab", KeyParseError::UnknownKey("ab".to_string())),
+            ("<num>", KeyParseError::UnknownKey("<num>".to_string())),
+        ];
+        for (s, err) in errors {
+            assert_eq!(s.parse::<KeyEvt>(), Err(err), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let combo = "a<C-a><C-S-a><Space><lt>[<Enter><F1><A-Up>";
+        assert_eq!(
+            combo.parse::<KeyCombo>().unwrap().to_display_string(),
+            combo
+        );
+        let pattern = "g<num>[<C-char>|<[>|x]<any><S-digit><A-key>";
+        let parsed = pattern.parse::<KeyPattern>().unwrap();
+        assert_eq!(parsed.0.len(), 6);
+        assert_eq!(parsed.to_display_string(), pattern);
+        assert!(parsed.matches("g12[x<S-1><A-Enter>".parse().unwrap()));
+        assert_eq!(
+            "a[b|c".parse::<KeyPattern>(),
+            Err(KeyParseError::Unclosed("[b|c".to_string()))
+        );
+        assert_eq!("".parse::<KeyPattern>(), Err(KeyParseError::Empty));
+    }
+}
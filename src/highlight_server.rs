@@ -1,10 +1,60 @@
-use syntect::{easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet};
+use std::{borrow::Cow, collections::HashMap, path::PathBuf};
+
+use ropey::RopeSlice;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{HighlightState, Style, ThemeSet},
+    parsing::{ParseState, SyntaxSet},
+};
 
 use crate::{
-    editor::editor_server::*,
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod},
+        DocumentView, Transaction,
+    },
+    editor::{
+        editor_server::*, EditorOption, EditorStateSummary, ModalEditorResult, DEFAULT_THEME,
+    },
     render_server::{ConcreteStyle, RGBAColor},
 };
 
+/// Highlights a line of a rope and returns the styles along with the number of chars they cover.
+/// The line is only copied if it is split across multiple chunks of the rope.
+pub fn highlight_rope_line(
+    line: RopeSlice,
+    highlighter: &mut HighlightLines,
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<(Style, usize)>, syntect::Error> {
+    let mut chunks = line.chunks();
+    let line: Cow<str> = match (chunks.next(), chunks.next()) {
+        (None, _) => Cow::Borrowed(""),
+        (Some(chunk), None) => Cow::Borrowed(chunk),
+        _ => Cow::Owned(line.to_string()),
+    };
+    Ok(highlighter
+        .highlight_line(&line, syntax_set)?
+        .into_iter()
+        .map(|(style, s)| (style, s.chars().count()))
+        .collect())
+}
+
+/// The styles of a highlighted line along with the state of the highlighter after it.
+type HighlightedLine = (Vec<(Style, usize)>, (HighlightState, ParseState));
+
+/// The highlighted lines of a document, which are kept until the document is modified so that
+/// only the lines starting from the first modified one are highlighted again.
+struct HighlightCache {
+    /// The name of the syntax that the lines are highlighted with.
+    syntax_name: String,
+    /// The name of the theme that the lines are highlighted with.
+    theme_name: String,
+    lines: Vec<HighlightedLine>,
+}
+
+/// The highlight caches of the documents, keyed by their ids. The caches are kept while switching
+/// between the documents.
+type HighlightCaches = HashMap<usize, HighlightCache>;
+
 pub struct HighlightServer {
     editor_conn: EditorConnection,
     syntax_set: SyntaxSet,
@@ -23,6 +73,7 @@ impl From<syntect::highlighting::Style> for ConcreteStyle {
             fg: Some(value.foreground.into()),
             bg: Some(value.background.into()),
             highlight: false,
+            highlight_color: None,
         }
     }
 }
@@ -30,7 +81,13 @@ impl From<syntect::highlighting::Style> for ConcreteStyle {
 impl HighlightServer {
     pub fn new(editor_conn: EditorConnection) -> Self {
         let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
+        let mut theme_set = ThemeSet::load_defaults();
+        if let Some(custom_theme_set) = Self::custom_themes_dir()
+            .filter(|dir| dir.is_dir())
+            .and_then(|dir| ThemeSet::load_from_folder(dir).ok())
+        {
+            theme_set.themes.extend(custom_theme_set.themes);
+        }
         HighlightServer {
             editor_conn,
             syntax_set,
@@ -38,53 +95,184 @@ impl HighlightServer {
         }
     }
 
+    /// Returns true iff the highlight server needs to receive the given message.
+    pub fn msg_filter(msg: &EditorServerMsg) -> bool {
+        matches!(
+            msg,
+            EditorServerMsg::ViewUpdated(..)
+                | EditorServerMsg::EditorResult(ModalEditorResult::TxApplied(_), _)
+                | EditorServerMsg::EditorResult(
+                    ModalEditorResult::OptionSet(EditorOption::Theme(_)),
+                    _
+                )
+                | EditorServerMsg::ListThemesRequested
+        )
+    }
+
+    /// Returns the directory that contains the user's `.tmTheme` files.
+    fn custom_themes_dir() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join(".config")
+                .join("yaz")
+                .join("themes")
+        })
+    }
+
+    /// Returns the names of all the loaded themes.
+    pub fn list_themes(&self) -> Vec<String> {
+        self.theme_set.themes.keys().cloned().collect()
+    }
+
+    /// Drops the cached lines of the documents starting from the first line that the given
+    /// transaction affects. The lines of the other documents are dropped altogether, as their
+    /// buffers are not at hand. Returns false iff the current document is neither modified nor
+    /// switched, in which case its highlights remain valid.
+    fn invalidate_caches(
+        caches: &mut HighlightCaches,
+        tx: &Transaction,
+        state: &EditorStateSummary,
+    ) -> bool {
+        let mut docs_changed = false;
+        for pm in &tx.primitive_mods {
+            match pm {
+                PrimitiveMod::DocMap(DocMapMod::PopDoc(doc_id)) => {
+                    // The id may be reused by a new document.
+                    caches.remove(doc_id);
+                    docs_changed = true;
+                }
+                PrimitiveMod::DocMap(
+                    DocMapMod::SwitchDoc(_) | DocMapMod::CreateDoc(_) | DocMapMod::DuplicateDoc(_),
+                ) => docs_changed = true,
+                _ => {}
+            }
+        }
+        let mut curr_doc_modified = false;
+        for doc_id in tx.split_by_document().into_keys() {
+            let Some(affected_range) = tx.get_affected_char_range(doc_id) else {
+                continue;
+            };
+            if doc_id != state.curr_buffer_idx {
+                caches.remove(&doc_id);
+                continue;
+            }
+            curr_doc_modified = true;
+            // The text before the affected range is left untouched.
+            if let Some(cache) = caches.get_mut(&doc_id) {
+                let buf = state.curr_doc.get_buf();
+                let first_line =
+                    buf.char_to_line(std::cmp::min(affected_range.start, buf.len_chars()));
+                cache.lines.truncate(first_line);
+            }
+        }
+        docs_changed || curr_doc_modified
+    }
+
+    /// Highlights the current document up to the last line in the view, reusing the cached lines,
+    /// and sends the styles to the editor.
+    fn highlight(
+        &self,
+        caches: &mut HighlightCaches,
+        view: &DocumentView,
+        state: &EditorStateSummary,
+    ) {
+        self.editor_conn.send_req(EditorServerReq::StylizeInitEvent);
+        // get the extension
+        let syntax = state
+            .curr_doc
+            .get_ext()
+            .and_then(|ext| self.syntax_set.find_syntax_by_extension(&ext));
+        if let Some(syntax) = syntax {
+            // An unknown theme, e.g., one that is removed from the themes directory, falls back
+            // to the default one.
+            let (theme_name, theme) = match self.theme_set.themes.get(&state.settings.theme) {
+                Some(theme) => (state.settings.theme.as_str(), theme),
+                None => (DEFAULT_THEME, &self.theme_set.themes[DEFAULT_THEME]),
+            };
+            // Saving the document under another extension may change its syntax.
+            let cache = caches
+                .entry(state.curr_buffer_idx)
+                .and_modify(|cache| {
+                    if cache.syntax_name != syntax.name || cache.theme_name != theme_name {
+                        cache.lines.clear();
+                        cache.syntax_name = syntax.name.clone();
+                        cache.theme_name = theme_name.to_string();
+                    }
+                })
+                .or_insert_with(|| HighlightCache {
+                    syntax_name: syntax.name.clone(),
+                    theme_name: theme_name.to_string(),
+                    lines: vec![],
+                });
+            let buf = state.curr_doc.get_buf();
+            // Highlight every line up to the view, as each line depends on the previous ones.
+            let end_line = view.visible_line_range(buf).end() + 1;
+            let first_line = cache.lines.len();
+            let mut highlighter = match cache.lines.last() {
+                Some((_, (highlight_state, parse_state))) => {
+                    HighlightLines::from_state(theme, highlight_state.clone(), parse_state.clone())
+                }
+                None => HighlightLines::new(syntax, theme),
+            };
+            for line in buf
+                .lines_at(first_line)
+                .take(end_line.saturating_sub(first_line))
+            {
+                let styles = highlight_rope_line(line, &mut highlighter, &self.syntax_set).unwrap();
+                let (highlight_state, parse_state) = highlighter.state();
+                highlighter =
+                    HighlightLines::from_state(theme, highlight_state.clone(), parse_state.clone());
+                cache.lines.push((styles, (highlight_state, parse_state)));
+            }
+            let mut curr_char_idx = 0;
+            let styles = cache
+                .lines
+                .iter()
+                .take(end_line)
+                .flat_map(|(styles, _)| styles)
+                .map(|(style, num_chars)| {
+                    let start = curr_char_idx;
+                    curr_char_idx += num_chars;
+                    (start, curr_char_idx, (*style).into())
+                })
+                .collect();
+            self.editor_conn
+                .send_req(EditorServerReq::StylizeBatchEvent(styles));
+        }
+        self.editor_conn.send_req(EditorServerReq::StylizeEndEvent);
+    }
+
     pub fn run(self) {
         std::thread::spawn(move || {
             println!("HighlightServer: started");
+            // The parse states cannot be sent between threads, so the cache is created here.
+            let mut caches = HighlightCaches::new();
             loop {
                 // Then, try to receive a message from the editor server.
                 if let Ok(editor_msg) = self.editor_conn.try_receive_msg() {
                     match editor_msg {
                         EditorServerMsg::ViewUpdated(view, new_state) => {
-                            self.editor_conn.send_req(EditorServerReq::StylizeInitEvent);
-                            // get the extension
-                            let syntax = new_state
-                                .curr_doc
-                                .get_ext()
-                                .and_then(|ext| self.syntax_set.find_syntax_by_extension(&ext));
-                            if syntax.is_none() {
-                                continue;
-                            }
-                            // start highlighting.
-                            let mut highlighter = HighlightLines::new(
-                                &syntax.unwrap(),
-                                &self.theme_set.themes["base16-ocean.dark"],
-                            );
-                            for (line_idx, line) in new_state
-                                .curr_doc
-                                .get_buf()
-                                .lines()
-                                .take(view.y_offset + view.max_height)
-                                .enumerate()
-                            {
-                                let mut curr_char_idx = new_state
-                                    .curr_doc
-                                    .get_buf()
-                                    .try_line_to_char(line_idx)
-                                    .unwrap_or(0);
-                                for (style, s) in highlighter
-                                    .highlight_line(&line.to_string(), &self.syntax_set)
-                                    .unwrap()
-                                {
-                                    self.editor_conn.send_req(EditorServerReq::StylizeEvent(
-                                        curr_char_idx,
-                                        curr_char_idx + s.chars().count(),
-                                        style.into(),
-                                    ));
-                                    curr_char_idx += s.chars().count();
-                                }
+                            self.highlight(&mut caches, &view, &new_state);
+                        }
+                        EditorServerMsg::EditorResult(
+                            ModalEditorResult::TxApplied(tx),
+                            new_state,
+                        ) => {
+                            let needs_highlight =
+                                Self::invalidate_caches(&mut caches, &tx, &new_state);
+                            if needs_highlight {
+                                self.highlight(&mut caches, &new_state.view, &new_state);
                             }
-                            self.editor_conn.send_req(EditorServerReq::StylizeEndEvent);
+                        }
+                        EditorServerMsg::EditorResult(
+                            ModalEditorResult::OptionSet(EditorOption::Theme(_)),
+                            new_state,
+                        ) => {
+                            self.highlight(&mut caches, &new_state.view, &new_state);
+                        }
+                        EditorServerMsg::ListThemesRequested => {
+                            self.editor_conn
+                                .send_req(EditorServerReq::ThemesListed(self.list_themes()));
                         }
                         EditorServerMsg::QuitRequested => {
                             println!("HighlightServer: quitting");
@@ -0,0 +1,8 @@
+pub mod cursive_frontend;
+pub mod cursor;
+pub mod document;
+pub mod editor;
+pub mod events;
+pub mod highlight_server;
+pub mod lsp_server;
+pub mod render_server;
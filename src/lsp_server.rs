@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use lsp_types::{
+    notification::{DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized, Notification},
+    request::{Initialize, Request, Shutdown},
+    DidChangeTextDocumentParams, DidOpenTextDocumentParams, InitializeParams, InitializeResult,
+    InitializedParams, Position, Range, TextDocumentContentChangeEvent, TextDocumentItem,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url, VersionedTextDocumentIdentifier,
+};
+use ropey::Rope;
+use serde_json::{json, Value};
+
+use crate::{
+    document::{DiffOp, Document, EditOp, Transaction},
+    editor::{editor_server::*, ModalEditorResult},
+};
+
+/// Describes how to start a language server.
+#[derive(Clone, Debug)]
+pub struct LspConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    /// The language identifier sent along with the opened documents, e.g., `rust`.
+    pub language_id: String,
+}
+
+impl LspConfig {
+    /// Parses a configuration of the form `<language id> <command> [args...]`.
+    pub fn from_cmd_line(cmd_line: &str) -> Option<Self> {
+        let mut parts = cmd_line.split_whitespace().map(|part| part.to_string());
+        Some(LspConfig {
+            language_id: parts.next()?,
+            command: parts.next()?,
+            args: parts.collect(),
+        })
+    }
+}
+
+/// Keeps a language server in sync with the documents of the editor. The documents are
+/// synchronized by sending only the changed ranges if the server supports it, and their full
+/// text otherwise.
+pub struct LspServer {
+    editor_conn: EditorConnection,
+    config: LspConfig,
+    /// The version and the last sent state of the opened documents, keyed by their uri.
+    opened_docs: HashMap<Url, (i32, Document)>,
+    next_req_id: i64,
+    incremental_sync: bool,
+}
+
+impl LspServer {
+    pub fn new(editor_conn: EditorConnection, config: LspConfig) -> Self {
+        LspServer {
+            editor_conn,
+            config,
+            opened_docs: Default::default(),
+            next_req_id: 0,
+            incremental_sync: false,
+        }
+    }
+
+    /// Returns true iff the LSP server needs to receive the given message.
+    pub fn msg_filter(msg: &EditorServerMsg) -> bool {
+        matches!(msg, EditorServerMsg::EditorResult(..))
+    }
+
+    /// Writes a JSON-RPC message with its header to the language server.
+    fn write_msg(stdin: &mut ChildStdin, msg: Value) -> std::io::Result<()> {
+        let content = msg.to_string();
+        write!(
+            stdin,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        stdin.flush()
+    }
+
+    /// Reads a JSON-RPC message from the language server.
+    fn read_msg(stdout: &mut BufReader<ChildStdout>) -> std::io::Result<Value> {
+        let mut content_len = 0;
+        loop {
+            let mut header = String::new();
+            if stdout.read_line(&mut header)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(len) = header.strip_prefix("Content-Length: ") {
+                content_len = len
+                    .parse()
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+            }
+        }
+        let mut content = vec![0; content_len];
+        stdout.read_exact(&mut content)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    fn send_request<R: Request>(
+        &mut self,
+        stdin: &mut ChildStdin,
+        params: R::Params,
+    ) -> std::io::Result<i64> {
+        self.next_req_id += 1;
+        Self::write_msg(
+            stdin,
+            json!({
+                "jsonrpc": "2.0",
+                "id": self.next_req_id,
+                "method": R::METHOD,
+                "params": params,
+            }),
+        )?;
+        Ok(self.next_req_id)
+    }
+
+    fn send_notification<N: Notification>(
+        stdin: &mut ChildStdin,
+        params: N::Params,
+    ) -> std::io::Result<()> {
+        Self::write_msg(
+            stdin,
+            json!({
+                "jsonrpc": "2.0",
+                "method": N::METHOD,
+                "params": params,
+            }),
+        )
+    }
+
+    /// Performs the initialization handshake with the language server.
+    fn initialize(
+        &mut self,
+        stdin: &mut ChildStdin,
+        stdout: &mut BufReader<ChildStdout>,
+    ) -> std::io::Result<()> {
+        let root_uri = std::env::current_dir()
+            .ok()
+            .and_then(|dir| Url::from_directory_path(dir).ok());
+        #[allow(deprecated)]
+        let params = InitializeParams {
+            process_id: Some(std::process::id()),
+            root_uri,
+            ..Default::default()
+        };
+        let req_id = self.send_request::<Initialize>(stdin, params)?;
+        // Skip the notifications that the server may send before responding.
+        let response = loop {
+            let msg = Self::read_msg(stdout)?;
+            if msg.get("id") == Some(&json!(req_id)) {
+                break msg;
+            }
+        };
+        let sync_kind = response
+            .get("result")
+            .and_then(|result| serde_json::from_value::<InitializeResult>(result.clone()).ok())
+            .and_then(|result| result.capabilities.text_document_sync)
+            .and_then(|sync| match sync {
+                TextDocumentSyncCapability::Kind(kind) => Some(kind),
+                TextDocumentSyncCapability::Options(options) => options.change,
+            });
+        self.incremental_sync = sync_kind == Some(TextDocumentSyncKind::INCREMENTAL);
+        Self::send_notification::<Initialized>(stdin, InitializedParams {})
+    }
+
+    /// Returns the LSP position of the given char, where the characters are counted in UTF-16
+    /// code units.
+    fn lsp_position(char_idx: usize, buf: &Rope) -> Position {
+        let line_idx = buf.char_to_line(char_idx);
+        let line_start = buf.line_to_char(line_idx);
+        Position::new(
+            line_idx as u32,
+            (buf.char_to_utf16_cu(char_idx) - buf.char_to_utf16_cu(line_start)) as u32,
+        )
+    }
+
+    /// Returns the edits of the given transaction as the changes to the old buffer, or none if
+    /// they do not turn it into the new buffer, e.g., if the old buffer is not the one that the
+    /// transaction was applied to.
+    fn tx_content_changes(
+        tx: &Transaction,
+        old_buf: &Rope,
+        new_buf: &Rope,
+    ) -> Option<Vec<TextDocumentContentChangeEvent>> {
+        let mut buf = old_buf.clone();
+        let content_changes = tx
+            .to_edit_script(old_buf)
+            .into_iter()
+            .map(|op: EditOp| {
+                let range = op.char_range(&buf)?;
+                let change = TextDocumentContentChangeEvent {
+                    range: Some(Range::new(
+                        Self::lsp_position(range.start, &buf),
+                        Self::lsp_position(range.end, &buf),
+                    )),
+                    range_length: None,
+                    text: op.new_text,
+                };
+                buf.remove(range.clone());
+                buf.insert(range.start, &change.text);
+                Some(change)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        (&buf == new_buf).then_some(content_changes)
+    }
+
+    /// Returns the changes that turn the old document into the new one. The changes are applied
+    /// in order, so they are ordered from the end of the document so as not to shift each other.
+    /// The edits of the transaction that produced the new document are sent as they are if
+    /// possible, and the documents are diffed otherwise.
+    fn content_changes(
+        &self,
+        old_doc: &Document,
+        new_doc: &Document,
+        tx: Option<&Transaction>,
+    ) -> Vec<TextDocumentContentChangeEvent> {
+        if !self.incremental_sync {
+            return vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: new_doc.get_buf().to_string(),
+            }];
+        }
+        let old_buf = old_doc.get_buf();
+        if let Some(content_changes) =
+            tx.and_then(|tx| Self::tx_content_changes(tx, old_buf, new_doc.get_buf()))
+        {
+            return content_changes;
+        }
+        old_doc
+            .diff(new_doc)
+            .into_iter()
+            .rev()
+            .map(|op| {
+                let (start, end, text) = match op {
+                    DiffOp::Insert(idx, text) => (idx, idx, text),
+                    DiffOp::Delete(start, end) => (start, end, String::new()),
+                    DiffOp::Replace(start, end, text) => (start, end, text),
+                };
+                TextDocumentContentChangeEvent {
+                    range: Some(Range::new(
+                        Self::lsp_position(start, old_buf),
+                        Self::lsp_position(end, old_buf),
+                    )),
+                    range_length: None,
+                    text,
+                }
+            })
+            .collect()
+    }
+
+    /// Notifies the language server about the given document if it was opened or changed since
+    /// the last synchronization, possibly by the given transaction.
+    fn sync_doc(
+        &mut self,
+        stdin: &mut ChildStdin,
+        doc: &Document,
+        tx: Option<&Transaction>,
+    ) -> std::io::Result<()> {
+        let Some(uri) = doc
+            .source
+            .path()
+            .and_then(|path| std::fs::canonicalize(path).ok())
+            .and_then(|path| Url::from_file_path(path).ok())
+        else {
+            return Ok(());
+        };
+        match self.opened_docs.get(&uri) {
+            None => {
+                Self::send_notification::<DidOpenTextDocument>(
+                    stdin,
+                    DidOpenTextDocumentParams {
+                        text_document: TextDocumentItem::new(
+                            uri.clone(),
+                            self.config.language_id.clone(),
+                            0,
+                            doc.get_buf().to_string(),
+                        ),
+                    },
+                )?;
+                self.opened_docs.insert(uri, (0, doc.clone()));
+            }
+            Some((version, last_doc)) if last_doc.get_buf() != doc.get_buf() => {
+                let version = version + 1;
+                let content_changes = self.content_changes(last_doc, doc, tx);
+                Self::send_notification::<DidChangeTextDocument>(
+                    stdin,
+                    DidChangeTextDocumentParams {
+                        text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+                        content_changes,
+                    },
+                )?;
+                self.opened_docs.insert(uri, (version, doc.clone()));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn start_process(&self) -> std::io::Result<Child> {
+        Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+    }
+
+    fn serve(
+        &mut self,
+        stdin: &mut ChildStdin,
+        mut stdout: BufReader<ChildStdout>,
+    ) -> std::io::Result<()> {
+        self.initialize(stdin, &mut stdout)?;
+        // Drain the messages from the server so that it never blocks on a full pipe.
+        std::thread::spawn(move || while Self::read_msg(&mut stdout).is_ok() {});
+        while let Ok(editor_msg) = self.editor_conn.receive_msg() {
+            match editor_msg {
+                EditorServerMsg::EditorResult(result, state) => {
+                    let doc_tx = match result {
+                        ModalEditorResult::TxApplied(tx) => {
+                            tx.split_by_document().remove(&state.curr_buffer_idx)
+                        }
+                        _ => None,
+                    };
+                    self.sync_doc(stdin, &state.curr_doc, doc_tx.as_ref())?;
+                }
+                EditorServerMsg::QuitRequested => break,
+                _ => {}
+            }
+        }
+        self.send_request::<Shutdown>(stdin, ())?;
+        Self::send_notification::<Exit>(stdin, ())
+    }
+
+    pub fn run(mut self) {
+        std::thread::spawn(move || {
+            println!("LspServer: started");
+            match self.start_process() {
+                Ok(mut process) => {
+                    let mut stdin = process.stdin.take().unwrap();
+                    let stdout = BufReader::new(process.stdout.take().unwrap());
+                    if let Err(err) = self.serve(&mut stdin, stdout) {
+                        println!("LspServer: {}", err);
+                    }
+                    process.kill().ok();
+                    process.wait().ok();
+                }
+                Err(err) => println!("LspServer: could not start the language server: {}", err),
+            }
+            // Keep the connection alive until the editor quits, even if the language server
+            // failed.
+            while let Ok(editor_msg) = self.editor_conn.receive_msg() {
+                if let EditorServerMsg::QuitRequested = editor_msg {
+                    break;
+                }
+            }
+            println!("LspServer: quitting");
+        });
+    }
+}
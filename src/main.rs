@@ -1,26 +1,24 @@
-use cursive_frontend::CursiveFrontend;
-use document::{
-    primitive_mods::{DocMapMod, PrimitiveMod},
-    Document, DocumentMap, Transaction,
+use yaz::{
+    cursive_frontend::CursiveFrontend,
+    document::{
+        primitive_mods::{DocMapMod, PrimitiveMod},
+        Document, DocumentMap, Transaction,
+    },
+    editor::{
+        editor_mode::*,
+        editor_server::{EditorServer, EditorServerReq},
+        HistoricalEditorState, ModalEditor,
+    },
+    highlight_server::HighlightServer,
+    lsp_server::{LspConfig, LspServer},
+    render_server::RendererServer,
 };
-use editor::{editor_mode::*, editor_server::EditorServer, HistoricalEditorState, ModalEditor};
-
-use highlight_server::HighlightServer;
-use render_server::RendererServer;
-
-mod cursive_frontend;
-mod cursor;
-mod document;
-mod editor;
-mod events;
-mod highlight_server;
-mod render_server;
 
 fn main() {
     let file_name = std::env::args().nth(1).unwrap_or_default();
-    // Initialize the editor state with the file.
-    let mut editor_state: HistoricalEditorState = DocumentMap::default().into();
-    editor_state.modify_with_tx(
+    // Initialize the editor state with the file, logging the events from the start.
+    let mut editor_state = HistoricalEditorState::from(DocumentMap::default().with_event_log());
+    editor_state.apply_without_history(
         &Transaction::new()
             .with_mod(PrimitiveMod::DocMap(DocMapMod::PopDoc(0)))
             .with_mod(PrimitiveMod::DocMap(DocMapMod::CreateDoc(
@@ -28,21 +26,42 @@ fn main() {
             ))),
     );
     // Construct the editor.
-    let editor = ModalEditor::new(editor_state, NormalMode::id())
-        .with_mode(Box::new(InsertMode::new()))
-        .with_mode(Box::new(NormalMode::new()))
-        .with_mode(Box::new(GotoMode::new()))
-        .with_mode(Box::new(CommandMode::new()))
-        .with_mode(Box::new(SelectionMode::new()));
+    let editor = all_modes().into_iter().fold(
+        ModalEditor::new(editor_state, NormalMode::id()),
+        |editor, mode| editor.with_mode(mode),
+    );
     // Construct the servers.
     let mut editor_server = EditorServer::new(editor);
     let mut rnd_server = RendererServer::<CursiveFrontend>::new(editor_server.new_connection());
-    let mut hl_server = HighlightServer::new(editor_server.new_connection());
+    let mut hl_server =
+        HighlightServer::new(editor_server.new_filtered_connection(HighlightServer::msg_filter));
+    // A language server can be set with its language id followed by its command line, e.g.,
+    // `YAZ_LSP="rust rust-analyzer"`.
+    let lsp_server = std::env::var("YAZ_LSP")
+        .ok()
+        .and_then(|cmd| LspConfig::from_cmd_line(&cmd))
+        .map(|config| {
+            LspServer::new(
+                editor_server.new_filtered_connection(LspServer::msg_filter),
+                config,
+            )
+        });
+    let editor_req_snd = editor_server.new_req_sender();
     let mut cursive_ctx = rnd_server.get_frontend_mut().init_cursive_context();
     // Run in the background.
     hl_server.run();
+    // Let the editor know which themes can be selected.
+    editor_req_snd.send(EditorServerReq::ListThemes).ok();
+    if let Some(lsp_server) = lsp_server {
+        lsp_server.run();
+    }
     rnd_server.run();
-    editor_server.run();
+    let editor_server_handle = editor_server.run();
     // Run in the main thread.
     cursive_ctx.run();
+    // The frontend may be closed without a quit request (e.g. on Ctrl-C), so explicitly ask the
+    // editor server to shut down. Sending fails iff the server has already stopped.
+    editor_req_snd.send(EditorServerReq::Shutdown).ok();
+    // Wait for the editor server to finish handling the pending requests.
+    editor_server_handle.join().unwrap();
 }
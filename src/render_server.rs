@@ -1,13 +1,18 @@
-use std::sync::mpsc;
+use std::{sync::mpsc, time::Duration};
+
+use itertools::Itertools;
+use ropey::Rope;
 
 use crate::{
-    cursor::SelectionIterator,
-    editor::{editor_server::*, EditorStateSummary, ModalEditorError},
+    cursor::{movement::right_grapheme, selection_range, SelectionIterator},
+    editor::{editor_server::*, EditorStateSummary, ModalEditorError, ModalEditorResult},
     events::KeyEvt,
 };
 
-pub use self::stylizer::*;
+pub use self::{config::Config, stylizer::*};
 
+mod config;
+mod css_colors;
 mod stylizer;
 
 #[derive(Clone, Debug)]
@@ -16,11 +21,28 @@ pub enum RendererEvent {
     Resized(usize, usize),
 }
 
+/// The style of the occurrences of the search pattern.
+const SEARCH_MATCH_STYLE: ConcreteStyle = ConcreteStyle {
+    fg: None,
+    bg: None,
+    highlight: true,
+    highlight_color: Some(RGBAColor(229, 192, 123, 255)),
+};
+
+/// A style applied to the chars in `start..end`.
+type StyledRegion = (usize, usize, ConcreteStyle);
+
 pub struct RendererServer<T> {
     editor_conn: EditorConnection,
     frontend: T,
     evt_chan: mpsc::Receiver<RendererEvent>,
-    stylizer: Stylizer,
+    syntax_stylizer: Stylizer,
+    selection_stylizer: Stylizer,
+    search_stylizer: Stylizer,
+    config: Config,
+    /// The syntax styles received since the last `StylizeInit`. They replace the syntax
+    /// stylizer at once on `StylizeEnd`, so that the redraws in between use the complete styles.
+    pending: Option<(EditorStateSummary, Vec<StyledRegion>)>,
 }
 
 impl<T> RendererServer<T>
@@ -33,7 +55,11 @@ where
             editor_conn,
             frontend: T::new(snd),
             evt_chan: rcv,
-            stylizer: Default::default(),
+            syntax_stylizer: Default::default(),
+            selection_stylizer: Default::default(),
+            search_stylizer: Default::default(),
+            config: Default::default(),
+            pending: None,
         }
     }
 
@@ -41,21 +67,97 @@ where
         &mut self.frontend
     }
 
+    /// Styles the heads within the given ranges with the cursor style and the rest of the ranges
+    /// with the selection style.
+    fn layer_selection_styles(
+        &mut self,
+        ranges: impl IntoIterator<Item = (usize, usize)>,
+        heads: &[usize],
+        (selection_style, cursor_style): (ConcreteStyle, ConcreteStyle),
+        buf: &Rope,
+    ) {
+        for (start, end) in ranges {
+            let mut region_start = start;
+            for head in heads.iter().filter(|head| (start..end).contains(*head)) {
+                let head_end = right_grapheme(*head, buf).unwrap_or(*head);
+                self.selection_stylizer
+                    .layer_region_style(region_start, *head, selection_style);
+                self.selection_stylizer
+                    .layer_region_style(*head, head_end, cursor_style);
+                region_start = head_end;
+            }
+            self.selection_stylizer
+                .layer_region_style(region_start, end, selection_style);
+        }
+    }
+
     fn redraw(&mut self, state: EditorStateSummary) {
         let buf = state.curr_doc.get_buf();
-        let mut tmp_stylizer = self.stylizer.clone();
-        state
+        self.selection_stylizer.reset();
+        let selection_style = ConcreteStyle::default_for_mode(state.curr_mode, &self.config);
+        let secondary_style = ConcreteStyle::secondary_for_mode(state.curr_mode, &self.config);
+        let cursor_style = state.display.cursor_style.unwrap_or(selection_style);
+        let secondary_cursor_style = state
+            .display
+            .secondary_cursor_style
+            .unwrap_or(secondary_style);
+        // The primary selection is drawn on top of the secondary ones.
+        let primary = state.curr_doc.primary_selection();
+        let primary_range = primary.map(|sel| selection_range(sel, buf));
+        let secondary_sels = state
             .curr_doc
             .selections
-            .values()
-            .cloned()
+            .iter()
+            .filter(|(sel_id, _)| **sel_id != 0)
+            .map(|(_, sel)| *sel)
+            .collect_vec();
+        let secondary_heads = secondary_sels
+            .iter()
+            .map(|sel| sel.0)
+            .sorted()
+            .dedup()
+            .collect_vec();
+        let secondary_ranges = secondary_sels
+            .into_iter()
             .collect_merged(buf)
             .into_iter()
-            .for_each(|(start, end)| {
-                tmp_stylizer.layer_region_style(start, end, [StyleAttr::Highlight]);
-            });
-        let max_chars = state.view.approx_displayed_len_chars(buf);
-        let regions = tmp_stylizer.compute_regions(max_chars);
+            .flat_map(|(start, end)| match primary_range {
+                Some((primary_start, primary_end)) => vec![
+                    (start, std::cmp::min(end, primary_start)),
+                    (std::cmp::max(start, primary_end), end),
+                ],
+                None => vec![(start, end)],
+            })
+            .filter(|(start, end)| start < end)
+            .collect_vec();
+        self.layer_selection_styles(
+            secondary_ranges,
+            &secondary_heads,
+            (secondary_style, secondary_cursor_style),
+            buf,
+        );
+        if let (Some(primary), Some(primary_range)) = (primary, primary_range) {
+            self.layer_selection_styles(
+                [primary_range],
+                &[primary.0],
+                (selection_style, cursor_style),
+                buf,
+            );
+        }
+        // Clear the highlights once the mode stops searching.
+        self.search_stylizer.reset();
+        if let Some(pattern) = &state.display.search_pattern {
+            self.search_stylizer.highlight_all_occurrences(
+                pattern,
+                &state.curr_doc,
+                SEARCH_MATCH_STYLE,
+            );
+        }
+        let regions = self
+            .syntax_stylizer
+            .merge(&self.search_stylizer)
+            .merge(&self.selection_stylizer)
+            .compute_visible_regions(&state.view, buf);
         self.frontend.state_updated(&state, regions);
     }
 
@@ -79,6 +181,8 @@ where
                 if let Ok(editor_msg) = self.editor_conn.try_receive_msg() {
                     match editor_msg {
                         EditorServerMsg::ErrorThrown(err) => {
+                            // A failed slow operation does not stop the loading itself.
+                            self.frontend.loading_finished();
                             self.frontend.error(err);
                         }
                         EditorServerMsg::QuitRequested => {
@@ -90,22 +194,53 @@ where
                             self.redraw(state);
                         }
                         EditorServerMsg::EditorResult(res, state) => {
+                            match res {
+                                ModalEditorResult::DocumentSaved(_) => self.frontend.notify(
+                                    &format!("saved {}", state.curr_doc.source),
+                                    Duration::from_secs(2),
+                                ),
+                                ModalEditorResult::Notified(msg) => {
+                                    self.frontend.notify(&msg, Duration::from_secs(5))
+                                }
+                                ModalEditorResult::LoadingStarted => self.frontend.loading(),
+                                ModalEditorResult::LoadingStopped => {
+                                    self.frontend.loading_finished()
+                                }
+                                ModalEditorResult::PopupRequested(text) => {
+                                    self.frontend.popup(&text)
+                                }
+                                _ => {}
+                            }
                             self.redraw(state);
                         }
                         EditorServerMsg::StylizeInit(state) => {
-                            self.stylizer.reset();
-                            self.stylizer.layer_region_style(
-                                0,
-                                state.curr_doc.get_buf().len_chars(),
-                                ConcreteStyle::default(),
-                            );
+                            self.pending = Some((state, vec![]));
                         }
                         EditorServerMsg::Stylize(start, end, style, _state) => {
-                            self.stylizer.layer_region_style(start, end, style);
+                            if let Some((_, styles)) = &mut self.pending {
+                                styles.push((start, end, style));
+                            }
+                        }
+                        EditorServerMsg::StylizeBatch(batch, _state) => {
+                            if let Some((_, styles)) = &mut self.pending {
+                                styles.extend(batch);
+                            }
                         }
                         EditorServerMsg::StylizeEnd(state) => {
+                            if let Some((init_state, styles)) = self.pending.take() {
+                                self.syntax_stylizer.reset();
+                                self.syntax_stylizer.layer_region_style(
+                                    0,
+                                    init_state.curr_doc.get_buf().len_chars(),
+                                    ConcreteStyle::default(),
+                                );
+                                for (start, end, style) in styles {
+                                    self.syntax_stylizer.layer_region_style(start, end, style);
+                                }
+                            }
                             self.redraw(state);
                         }
+                        _ => {}
                     }
                 }
             }
@@ -120,6 +255,15 @@ pub trait RendererFrontend: Send {
         new_state: &EditorStateSummary,
         styles: Vec<(usize, usize, ConcreteStyle)>,
     );
+    fn update_line_numbers(&mut self, first_line: usize, last_line: usize, cursor_line: usize);
     fn error(&mut self, error: ModalEditorError);
+    /// Briefly displays the given message.
+    fn notify(&mut self, _msg: &str, _duration: Duration) {}
+    /// Displays an indicator while the editor is busy with a slow operation.
+    fn loading(&mut self) {}
+    /// Hides the loading indicator.
+    fn loading_finished(&mut self) {}
+    /// Displays the given text in a popup until a key is pressed.
+    fn popup(&mut self, _text: &str) {}
     fn quit(&mut self);
 }
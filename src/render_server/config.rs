@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+
+use super::stylizer::RGBAColor;
+
+/// Represents the configurable settings of the renderer.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The colors of the selections in each mode, keyed by the mode id.
+    pub mode_highlight_colors: HashMap<String, RGBAColor>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let blue = RGBAColor(97, 175, 239, 255);
+        let green = RGBAColor(152, 195, 121, 255);
+        let orange = RGBAColor(229, 152, 72, 255);
+        Config {
+            mode_highlight_colors: [
+                ("normal", blue),
+                ("goto", blue),
+                ("command", blue),
+                ("fuzzyfinder", blue),
+                ("search", blue),
+                ("insert", green),
+                ("selection", orange),
+                ("visualline", orange),
+                ("blockselection", orange),
+                ("split", orange),
+            ]
+            .into_iter()
+            .map(|(mode_id, color)| (mode_id.to_string(), color))
+            .collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Returns the color of the selections in the given mode, if it has one.
+    pub fn mode_highlight_color(&self, mode_id: &str) -> Option<RGBAColor> {
+        self.mode_highlight_colors.get(mode_id).copied()
+    }
+}
@@ -0,0 +1,154 @@
+use phf::phf_map;
+
+use super::RGBAColor;
+
+/// Maps the CSS color names to their colors.
+pub static CSS_COLORS: phf::Map<&'static str, RGBAColor> = phf_map! {
+    "aliceblue" => RGBAColor(240, 248, 255, 255),
+    "antiquewhite" => RGBAColor(250, 235, 215, 255),
+    "aqua" => RGBAColor(0, 255, 255, 255),
+    "aquamarine" => RGBAColor(127, 255, 212, 255),
+    "azure" => RGBAColor(240, 255, 255, 255),
+    "beige" => RGBAColor(245, 245, 220, 255),
+    "bisque" => RGBAColor(255, 228, 196, 255),
+    "black" => RGBAColor(0, 0, 0, 255),
+    "blanchedalmond" => RGBAColor(255, 235, 205, 255),
+    "blue" => RGBAColor(0, 0, 255, 255),
+    "blueviolet" => RGBAColor(138, 43, 226, 255),
+    "brown" => RGBAColor(165, 42, 42, 255),
+    "burlywood" => RGBAColor(222, 184, 135, 255),
+    "cadetblue" => RGBAColor(95, 158, 160, 255),
+    "chartreuse" => RGBAColor(127, 255, 0, 255),
+    "chocolate" => RGBAColor(210, 105, 30, 255),
+    "coral" => RGBAColor(255, 127, 80, 255),
+    "cornflowerblue" => RGBAColor(100, 149, 237, 255),
+    "cornsilk" => RGBAColor(255, 248, 220, 255),
+    "crimson" => RGBAColor(220, 20, 60, 255),
+    "cyan" => RGBAColor(0, 255, 255, 255),
+    "darkblue" => RGBAColor(0, 0, 139, 255),
+    "darkcyan" => RGBAColor(0, 139, 139, 255),
+    "darkgoldenrod" => RGBAColor(184, 134, 11, 255),
+    "darkgray" => RGBAColor(169, 169, 169, 255),
+    "darkgreen" => RGBAColor(0, 100, 0, 255),
+    "darkgrey" => RGBAColor(169, 169, 169, 255),
+    "darkkhaki" => RGBAColor(189, 183, 107, 255),
+    "darkmagenta" => RGBAColor(139, 0, 139, 255),
+    "darkolivegreen" => RGBAColor(85, 107, 47, 255),
+    "darkorange" => RGBAColor(255, 140, 0, 255),
+    "darkorchid" => RGBAColor(153, 50, 204, 255),
+    "darkred" => RGBAColor(139, 0, 0, 255),
+    "darksalmon" => RGBAColor(233, 150, 122, 255),
+    "darkseagreen" => RGBAColor(143, 188, 143, 255),
+    "darkslateblue" => RGBAColor(72, 61, 139, 255),
+    "darkslategray" => RGBAColor(47, 79, 79, 255),
+    "darkslategrey" => RGBAColor(47, 79, 79, 255),
+    "darkturquoise" => RGBAColor(0, 206, 209, 255),
+    "darkviolet" => RGBAColor(148, 0, 211, 255),
+    "deeppink" => RGBAColor(255, 20, 147, 255),
+    "deepskyblue" => RGBAColor(0, 191, 255, 255),
+    "dimgray" => RGBAColor(105, 105, 105, 255),
+    "dimgrey" => RGBAColor(105, 105, 105, 255),
+    "dodgerblue" => RGBAColor(30, 144, 255, 255),
+    "firebrick" => RGBAColor(178, 34, 34, 255),
+    "floralwhite" => RGBAColor(255, 250, 240, 255),
+    "forestgreen" => RGBAColor(34, 139, 34, 255),
+    "fuchsia" => RGBAColor(255, 0, 255, 255),
+    "gainsboro" => RGBAColor(220, 220, 220, 255),
+    "ghostwhite" => RGBAColor(248, 248, 255, 255),
+    "gold" => RGBAColor(255, 215, 0, 255),
+    "goldenrod" => RGBAColor(218, 165, 32, 255),
+    "gray" => RGBAColor(128, 128, 128, 255),
+    "green" => RGBAColor(0, 128, 0, 255),
+    "greenyellow" => RGBAColor(173, 255, 47, 255),
+    "grey" => RGBAColor(128, 128, 128, 255),
+    "honeydew" => RGBAColor(240, 255, 240, 255),
+    "hotpink" => RGBAColor(255, 105, 180, 255),
+    "indianred" => RGBAColor(205, 92, 92, 255),
+    "indigo" => RGBAColor(75, 0, 130, 255),
+    "ivory" => RGBAColor(255, 255, 240, 255),
+    "khaki" => RGBAColor(240, 230, 140, 255),
+    "lavender" => RGBAColor(230, 230, 250, 255),
+    "lavenderblush" => RGBAColor(255, 240, 245, 255),
+    "lawngreen" => RGBAColor(124, 252, 0, 255),
+    "lemonchiffon" => RGBAColor(255, 250, 205, 255),
+    "lightblue" => RGBAColor(173, 216, 230, 255),
+    "lightcoral" => RGBAColor(240, 128, 128, 255),
+    "lightcyan" => RGBAColor(224, 255, 255, 255),
+    "lightgoldenrodyellow" => RGBAColor(250, 250, 210, 255),
+    "lightgray" => RGBAColor(211, 211, 211, 255),
+    "lightgreen" => RGBAColor(144, 238, 144, 255),
+    "lightgrey" => RGBAColor(211, 211, 211, 255),
+    "lightpink" => RGBAColor(255, 182, 193, 255),
+    "lightsalmon" => RGBAColor(255, 160, 122, 255),
+    "lightseagreen" => RGBAColor(32, 178, 170, 255),
+    "lightskyblue" => RGBAColor(135, 206, 250, 255),
+    "lightslategray" => RGBAColor(119, 136, 153, 255),
+    "lightslategrey" => RGBAColor(119, 136, 153, 255),
+    "lightsteelblue" => RGBAColor(176, 196, 222, 255),
+    "lightyellow" => RGBAColor(255, 255, 224, 255),
+    "lime" => RGBAColor(0, 255, 0, 255),
+    "limegreen" => RGBAColor(50, 205, 50, 255),
+    "linen" => RGBAColor(250, 240, 230, 255),
+    "magenta" => RGBAColor(255, 0, 255, 255),
+    "maroon" => RGBAColor(128, 0, 0, 255),
+    "mediumaquamarine" => RGBAColor(102, 205, 170, 255),
+    "mediumblue" => RGBAColor(0, 0, 205, 255),
+    "mediumorchid" => RGBAColor(186, 85, 211, 255),
+    "mediumpurple" => RGBAColor(147, 112, 219, 255),
+    "mediumseagreen" => RGBAColor(60, 179, 113, 255),
+    "mediumslateblue" => RGBAColor(123, 104, 238, 255),
+    "mediumspringgreen" => RGBAColor(0, 250, 154, 255),
+    "mediumturquoise" => RGBAColor(72, 209, 204, 255),
+    "mediumvioletred" => RGBAColor(199, 21, 133, 255),
+    "midnightblue" => RGBAColor(25, 25, 112, 255),
+    "mintcream" => RGBAColor(245, 255, 250, 255),
+    "mistyrose" => RGBAColor(255, 228, 225, 255),
+    "moccasin" => RGBAColor(255, 228, 181, 255),
+    "navajowhite" => RGBAColor(255, 222, 173, 255),
+    "navy" => RGBAColor(0, 0, 128, 255),
+    "oldlace" => RGBAColor(253, 245, 230, 255),
+    "olive" => RGBAColor(128, 128, 0, 255),
+    "olivedrab" => RGBAColor(107, 142, 35, 255),
+    "orange" => RGBAColor(255, 165, 0, 255),
+    "orangered" => RGBAColor(255, 69, 0, 255),
+    "orchid" => RGBAColor(218, 112, 214, 255),
+    "palegoldenrod" => RGBAColor(238, 232, 170, 255),
+    "palegreen" => RGBAColor(152, 251, 152, 255),
+    "paleturquoise" => RGBAColor(175, 238, 238, 255),
+    "palevioletred" => RGBAColor(219, 112, 147, 255),
+    "papayawhip" => RGBAColor(255, 239, 213, 255),
+    "peachpuff" => RGBAColor(255, 218, 185, 255),
+    "peru" => RGBAColor(205, 133, 63, 255),
+    "pink" => RGBAColor(255, 192, 203, 255),
+    "plum" => RGBAColor(221, 160, 221, 255),
+    "powderblue" => RGBAColor(176, 224, 230, 255),
+    "purple" => RGBAColor(128, 0, 128, 255),
+    "red" => RGBAColor(255, 0, 0, 255),
+    "rosybrown" => RGBAColor(188, 143, 143, 255),
+    "royalblue" => RGBAColor(65, 105, 225, 255),
+    "saddlebrown" => RGBAColor(139, 69, 19, 255),
+    "salmon" => RGBAColor(250, 128, 114, 255),
+    "sandybrown" => RGBAColor(244, 164, 96, 255),
+    "seagreen" => RGBAColor(46, 139, 87, 255),
+    "seashell" => RGBAColor(255, 245, 238, 255),
+    "sienna" => RGBAColor(160, 82, 45, 255),
+    "silver" => RGBAColor(192, 192, 192, 255),
+    "skyblue" => RGBAColor(135, 206, 235, 255),
+    "slateblue" => RGBAColor(106, 90, 205, 255),
+    "slategray" => RGBAColor(112, 128, 144, 255),
+    "slategrey" => RGBAColor(112, 128, 144, 255),
+    "snow" => RGBAColor(255, 250, 250, 255),
+    "springgreen" => RGBAColor(0, 255, 127, 255),
+    "steelblue" => RGBAColor(70, 130, 180, 255),
+    "tan" => RGBAColor(210, 180, 140, 255),
+    "teal" => RGBAColor(0, 128, 128, 255),
+    "thistle" => RGBAColor(216, 191, 216, 255),
+    "tomato" => RGBAColor(255, 99, 71, 255),
+    "turquoise" => RGBAColor(64, 224, 208, 255),
+    "violet" => RGBAColor(238, 130, 238, 255),
+    "wheat" => RGBAColor(245, 222, 179, 255),
+    "white" => RGBAColor(255, 255, 255, 255),
+    "whitesmoke" => RGBAColor(245, 245, 245, 255),
+    "yellow" => RGBAColor(255, 255, 0, 255),
+    "yellowgreen" => RGBAColor(154, 205, 50, 255),
+};
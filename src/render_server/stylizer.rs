@@ -1,33 +1,146 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
 
 use itertools::Itertools;
+use ropey::Rope;
 
-use crate::document::DocumentView;
+use crate::document::{Document, DocumentView};
+
+use super::{config::Config, css_colors::CSS_COLORS};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub struct RGBAColor(pub u8, pub u8, pub u8, pub u8);
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ColorParseError {
+    MissingHash,
+    InvalidLength(usize),
+    InvalidDigit,
+    UnknownName(String),
+}
+
+impl std::fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{:?}", self))
+    }
+}
+
+impl std::error::Error for ColorParseError {}
+
+impl RGBAColor {
+    /// Parses a color in `#RRGGBB` or `#RRGGBBAA` format. The color is opaque if the alpha
+    /// component is omitted.
+    pub fn from_hex(s: &str) -> Result<RGBAColor, ColorParseError> {
+        let digits = s.strip_prefix('#').ok_or(ColorParseError::MissingHash)?;
+        if !digits.is_ascii() || (digits.len() != 6 && digits.len() != 8) {
+            return Err(ColorParseError::InvalidLength(digits.len()));
+        }
+        let component = |idx: usize| {
+            digits
+                .get(idx..idx + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(ColorParseError::InvalidDigit)
+        };
+        let alpha = if digits.len() == 8 {
+            component(6)?
+        } else {
+            255
+        };
+        Ok(RGBAColor(
+            component(0)?,
+            component(2)?,
+            component(4)?,
+            alpha,
+        ))
+    }
+
+    /// Returns the color with the given CSS color name, e.g. `orange`.
+    pub fn from_name(name: &str) -> Option<RGBAColor> {
+        CSS_COLORS.get(name.to_lowercase().as_str()).copied()
+    }
+}
+
+impl FromStr for RGBAColor {
+    type Err = ColorParseError;
+
+    /// Parses a color either in hex format or as a CSS color name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('#') {
+            Self::from_hex(s)
+        } else {
+            Self::from_name(s).ok_or_else(|| ColorParseError::UnknownName(s.to_string()))
+        }
+    }
+}
+
+impl serde::Serialize for RGBAColor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(&format_args!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            self.0, self.1, self.2, self.3
+        ))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RGBAColor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum StyleAttr {
     Fg(RGBAColor),
     Bg(RGBAColor),
     Highlight,
+    /// The background color of the highlighted regions, which takes precedence over `Bg`.
+    HighlightColor(RGBAColor),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum StyleAttrMod {
     AddAttr(StyleAttr),
     RemAttr(StyleAttr),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, serde::Serialize)]
 pub struct ConcreteStyle {
     pub fg: Option<RGBAColor>,
     pub bg: Option<RGBAColor>,
     pub highlight: bool,
+    pub highlight_color: Option<RGBAColor>,
 }
 
 impl ConcreteStyle {
+    /// Returns the style of the selections in the given mode, using the mode colors of the config.
+    /// Falls back to the plain highlight for the modes without a color.
+    pub fn default_for_mode(mode_id: &str, config: &Config) -> ConcreteStyle {
+        ConcreteStyle {
+            highlight: true,
+            highlight_color: config.mode_highlight_color(mode_id),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the style of the selections other than the primary one in the given mode, which
+    /// use a darker shade of the mode color.
+    pub fn secondary_for_mode(mode_id: &str, config: &Config) -> ConcreteStyle {
+        let primary = Self::default_for_mode(mode_id, config);
+        ConcreteStyle {
+            highlight_color: primary
+                .highlight_color
+                .map(|RGBAColor(r, g, b, a)| RGBAColor(r / 3 * 2, g / 3 * 2, b / 3 * 2, a)),
+            ..primary
+        }
+    }
+
     /// Constructs a new style from the given list of attributes.
     fn new<T: IntoIterator<Item = StyleAttr>>(attr_set: T) -> Self {
         let mut style: Self = Default::default();
@@ -35,6 +148,7 @@ impl ConcreteStyle {
             StyleAttr::Fg(color) => style.fg = Some(color),
             StyleAttr::Bg(color) => style.bg = Some(color),
             StyleAttr::Highlight => style.highlight = true,
+            StyleAttr::HighlightColor(color) => style.highlight_color = Some(color),
         });
         style
     }
@@ -56,6 +170,9 @@ impl IntoIterator for ConcreteStyle {
         if self.highlight {
             attrs.push(StyleAttr::Highlight);
         }
+        if let Some(color) = self.highlight_color {
+            attrs.push(StyleAttr::HighlightColor(color));
+        }
         attrs.into_iter()
     }
 }
@@ -74,12 +191,20 @@ fn extend_attrs(attrs: &mut Vec<StyleAttr>, mods: &Vec<StyleAttrMod>) {
     });
 }
 
-#[derive(Clone, Debug, Default)]
+/// Keeps the style attribute modifications at the char indices of a document. The stylizer can be
+/// dumped to JSON, e.g., to inspect its state while debugging.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Stylizer {
     stylization_points: BTreeMap<usize, Vec<StyleAttrMod>>,
 }
 
 impl Stylizer {
+    /// Restores a stylizer that was serialized to JSON.
+    #[cfg(test)]
+    pub fn from_json(s: &str) -> Result<Stylizer, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
     fn add_attribute(&mut self, point: usize, style_attr: StyleAttr) {
         self.stylization_points
             .entry(point)
@@ -106,29 +231,152 @@ impl Stylizer {
         })
     }
 
+    /// Layers the style on each non-overlapping occurrence of the pattern in the document.
+    pub fn highlight_all_occurrences(
+        &mut self,
+        pattern: &str,
+        doc: &Document,
+        style: ConcreteStyle,
+    ) {
+        let mut from = 0;
+        while let Some((start, end)) = doc.search_forward(pattern, from) {
+            self.layer_region_style(start, end, style);
+            from = end;
+        }
+    }
+
     pub fn reset(&mut self) {
         self.stylization_points.clear();
     }
 
-    pub fn compute_regions(&self, max_chars: usize) -> Vec<(usize, usize, ConcreteStyle)> {
+    /// Clears the stylization points within the given range, where the end is exclusive.
+    /// Note that the regions crossing the range boundaries lose their start/end points.
+    #[cfg(test)]
+    pub fn reset_range(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let mut tail = self.stylization_points.split_off(&start);
+        let mut rest = tail.split_off(&end);
+        self.stylization_points.append(&mut rest);
+    }
+
+    /// Returns a new stylizer that contains the stylization points of both stylizers. The styles
+    /// of the other stylizer are layered on top at the common points.
+    pub fn merge(&self, other: &Stylizer) -> Stylizer {
+        let mut merged = self.clone();
+        other.stylization_points.iter().for_each(|(point, mods)| {
+            merged
+                .stylization_points
+                .entry(*point)
+                .or_default()
+                .extend(mods.iter().cloned());
+        });
+        merged
+    }
+
+    /// Computes the regions within the given character range, where the end is exclusive.
+    /// The regions intersecting with the range boundaries are clipped.
+    pub fn compute_regions_for_range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Vec<(usize, usize, ConcreteStyle)> {
+        let (first_point, last_point) = match (
+            self.stylization_points.keys().next(),
+            self.stylization_points.keys().next_back(),
+        ) {
+            (Some(first_point), Some(last_point)) => (*first_point, *last_point),
+            _ => return vec![],
+        };
+        let start = std::cmp::max(start, first_point);
+        let end = std::cmp::min(end, last_point);
+        if start >= end {
+            return vec![];
+        }
+        // Accumulate the attributes up to the start of the range.
+        let mut init_attrs = Vec::new();
         self.stylization_points
-            .iter()
+            .range(..=start)
+            .for_each(|(_, mods)| extend_attrs(&mut init_attrs, mods));
+        let no_mods = Vec::new();
+        std::iter::once((&start, &no_mods))
+            .chain(self.stylization_points.range((start + 1)..end))
+            .chain(std::iter::once((&end, &no_mods)))
             .tuple_windows()
-            .scan(Vec::new(), |curr_attrs, (start, end)| {
+            .scan(init_attrs, |curr_attrs, (start, end)| {
                 // extend by the start style
                 extend_attrs(curr_attrs, start.1);
                 // output the range
                 Some((*start.0, *end.0, curr_attrs.clone()))
             })
             .map(|(start, end, attrs)| (start, end, ConcreteStyle::new(attrs)))
-            // .take(max_chars)
             .collect_vec()
     }
+
+    /// Computes the regions on the lines displayed in the view, starting with the style that is
+    /// accumulated up to the first visible character.
+    pub fn compute_visible_regions(
+        &self,
+        view: &DocumentView,
+        buf: &Rope,
+    ) -> Vec<(usize, usize, ConcreteStyle)> {
+        let (visible_start, visible_end) = view.visible_char_range(buf);
+        self.compute_regions_for_range(visible_start, visible_end)
+    }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Computes the regions on a single visible line of 100 chars.
+    fn visible_regions(stylizer: &Stylizer) -> Vec<(usize, usize, ConcreteStyle)> {
+        let buf = Rope::from_str(&" ".repeat(100));
+        let view = DocumentView {
+            max_height: 1,
+            ..Default::default()
+        };
+        stylizer.compute_visible_regions(&view, &buf)
+    }
+
+    #[test]
+    fn stylizer_json_snapshot() {
+        let mut stylizer = Stylizer::default();
+        stylizer.layer_region_style(
+            1,
+            3,
+            [
+                StyleAttr::Fg(RGBAColor(255, 0, 0, 255)),
+                StyleAttr::Highlight,
+            ],
+        );
+        let json = serde_json::to_string(&stylizer).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r##"{"stylization_points":{"##,
+                r##""1":[{"AddAttr":{"Fg":"#ff0000ff"}},{"AddAttr":"Highlight"}],"##,
+                r##""3":[{"RemAttr":{"Fg":"#ff0000ff"}},{"RemAttr":"Highlight"}]}}"##
+            )
+        );
+        assert_eq!(Stylizer::from_json(&json).unwrap(), stylizer);
+        assert!(Stylizer::from_json(r#"{"stylization_points":{"1":[]"#).is_err());
+    }
+
+    #[test]
+    fn mode_colors_cover_all_modes() {
+        for mode in crate::editor::editor_mode::all_modes() {
+            assert!(
+                ConcreteStyle::default_for_mode(mode.id(), &Config::default())
+                    .highlight_color
+                    .is_some(),
+                "no highlight color for mode {}",
+                mode.id()
+            );
+        }
+    }
+
     #[test]
     fn stylizer_simple() {
         let mut stylizer = Stylizer::default();
@@ -137,7 +385,126 @@ mod tests {
         let style_2 = ConcreteStyle::new([StyleAttr::Highlight]);
         stylizer.layer_region_style(0, 10, style_1);
         stylizer.layer_region_style(0, 20, style_2);
-        let regions = stylizer.compute_regions(100);
+        let regions = visible_regions(&stylizer);
         assert_eq!(regions, vec![(0, 10, style_1), (10, 20, style_2)]);
     }
+
+    #[test]
+    fn color_parsing() {
+        assert_eq!(
+            RGBAColor::from_hex("#FF8800"),
+            Ok(RGBAColor(255, 136, 0, 255))
+        );
+        assert_eq!(
+            RGBAColor::from_hex("#ff880080"),
+            Ok(RGBAColor(255, 136, 0, 128))
+        );
+        assert_eq!(
+            RGBAColor::from_hex("FF8800"),
+            Err(ColorParseError::MissingHash)
+        );
+        assert_eq!(
+            RGBAColor::from_hex("#FF88"),
+            Err(ColorParseError::InvalidLength(4))
+        );
+        assert_eq!(
+            RGBAColor::from_hex("#FF88GG"),
+            Err(ColorParseError::InvalidDigit)
+        );
+        assert_eq!(
+            RGBAColor::from_name("Orange"),
+            Some(RGBAColor(255, 165, 0, 255))
+        );
+        assert_eq!(RGBAColor::from_name("not-a-color"), None);
+        assert_eq!("orange".parse(), Ok(RGBAColor(255, 165, 0, 255)));
+        assert_eq!(
+            serde_json::to_string(&RGBAColor(255, 136, 0, 128)).unwrap(),
+            "\"#ff880080\""
+        );
+    }
+
+    #[test]
+    fn stylizer_occurrences() {
+        let mut doc = Document::new_empty();
+        doc.get_buf_mut().insert(0, "abaaba");
+        let style = ConcreteStyle::new([StyleAttr::Highlight]);
+        let mut stylizer = Stylizer::default();
+        stylizer.highlight_all_occurrences("aba", &doc, style);
+        assert_eq!(
+            visible_regions(&stylizer),
+            vec![(0, 3, style), (3, 6, style)]
+        );
+        stylizer.reset();
+        stylizer.highlight_all_occurrences("c", &doc, style);
+        assert_eq!(visible_regions(&stylizer), vec![]);
+    }
+
+    #[test]
+    fn stylizer_merge() {
+        let color = RGBAColor(0, 0, 0, 0);
+        let style_1 = ConcreteStyle::new([StyleAttr::Fg(color)]);
+        let style_2 = ConcreteStyle::new([StyleAttr::Highlight]);
+        let mut stylizer_1 = Stylizer::default();
+        stylizer_1.layer_region_style(0, 10, style_1);
+        let mut stylizer_2 = Stylizer::default();
+        stylizer_2.layer_region_style(5, 10, style_2);
+        let regions = visible_regions(&stylizer_1.merge(&stylizer_2));
+        assert_eq!(
+            regions,
+            vec![
+                (0, 5, style_1),
+                (
+                    5,
+                    10,
+                    ConcreteStyle::new([StyleAttr::Fg(color), StyleAttr::Highlight])
+                )
+            ]
+        );
+        let mut merged = stylizer_1.merge(&stylizer_2);
+        merged.reset_range(5, 10);
+        assert_eq!(visible_regions(&merged), visible_regions(&stylizer_1));
+    }
+
+    #[test]
+    fn stylizer_range() {
+        let mut stylizer = Stylizer::default();
+        let color = RGBAColor(0, 0, 0, 0);
+        let style_1 = ConcreteStyle::new([StyleAttr::Highlight, StyleAttr::Fg(color)]);
+        let style_2 = ConcreteStyle::new([StyleAttr::Highlight]);
+        stylizer.layer_region_style(0, 10, style_1);
+        stylizer.layer_region_style(0, 20, style_2);
+        stylizer.layer_region_style(30, 40, style_1);
+        let regions = stylizer.compute_regions_for_range(5, 35);
+        assert_eq!(
+            regions,
+            vec![
+                (5, 10, style_1),
+                (10, 20, style_2),
+                (20, 30, ConcreteStyle::default()),
+                (30, 35, style_1)
+            ]
+        );
+        assert_eq!(stylizer.compute_regions_for_range(40, 50), vec![]);
+        assert_eq!(
+            stylizer.compute_regions_for_range(0, 100),
+            visible_regions(&stylizer)
+        );
+    }
+
+    #[test]
+    fn stylizer_visible_regions() {
+        let mut stylizer = Stylizer::default();
+        let style = ConcreteStyle::new([StyleAttr::Highlight]);
+        stylizer.layer_region_style(2, 7, style);
+        let buf = Rope::from_str("abc\ndef\nghi\n");
+        let view = DocumentView {
+            y_offset: 1,
+            max_height: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            stylizer.compute_visible_regions(&view, &buf),
+            vec![(4, 7, style)]
+        );
+    }
 }